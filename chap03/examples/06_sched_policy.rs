@@ -0,0 +1,112 @@
+use std::env;
+use std::time::Duration;
+use chap03::calibrate::WorkUnit;
+use chap03::experiment::Experiment;
+use chap03::plot;
+use chap03::policy::{self, Policy, Sched};
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--cpus LIST] [--rr-timeslice MS] [--work UNIT] <policy>...", prog_name);
+    eprintln!();
+    eprintln!("  Run one load process per <policy> on the same CPUs and visualize their progress.");
+    eprintln!();
+    eprintln!("Policies:");
+    eprintln!("  other[,nice=N]    SCHED_OTHER (default fair policy)");
+    eprintln!("  batch[,nice=N]    SCHED_BATCH");
+    eprintln!("  idle              SCHED_IDLE");
+    eprintln!("  fifo:PRIO         SCHED_FIFO with priority 1-99");
+    eprintln!("  rr:PRIO           SCHED_RR with priority 1-99");
+    eprintln!("  deadline:R/D/P    SCHED_DEADLINE with runtime/deadline/period in ms");
+    eprintln!();
+    eprintln!("  Real-time policies need root, CAP_SYS_NICE or RLIMIT_RTPRIO (`ulimit -r`),");
+    eprintln!("  SCHED_DEADLINE needs root and --cpus all.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --cpus LIST: CPUs shared by the processes, e.g. 0,1, or all (default: 0)");
+    eprintln!("  --rr-timeslice MS: SCHED_RR timeslice during the run, needs root");
+    eprintln!("  --work UNIT: Work unit, calibrated loops or cputime (default: loops)");
+    eprintln!();
+    eprintln!("  Output: sched-policy.png");
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut cpus = vec![0];
+    let mut rr_timeslice = None;
+    let mut work = WorkUnit::Loops;
+    let mut workers = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--cpus" => {
+                cpus = match rest.next().map(String::as_str) {
+                    Some("all") => Vec::new(),
+                    Some(list) => list.split(',')
+                        .map(|cpu| cpu.parse().ok())
+                        .collect::<Option<Vec<usize>>>()
+                        .unwrap_or_else(|| usage(prog_name)),
+                    None => usage(prog_name),
+                }
+            }
+            "--rr-timeslice" => {
+                rr_timeslice = rest.next()
+                    .and_then(|ms| ms.parse::<u64>().ok())
+                    .filter(|&ms| ms > 0)
+                    .map(Duration::from_millis)
+                    .or_else(|| usage(prog_name))
+            }
+            "--work" => work = rest.next().and_then(|unit| WorkUnit::parse(unit)).unwrap_or_else(|| usage(prog_name)),
+            spec => workers.push(Sched::parse(spec).unwrap_or_else(|| usage(prog_name))),
+        }
+    }
+    if workers.is_empty() {
+        usage(prog_name);
+    }
+
+    let mut experiment = Experiment::new(workers.len()).cpus(&cpus).work(work);
+    for (worker, &sched) in workers.iter().enumerate() {
+        experiment = experiment.sched(worker, sched);
+    }
+    if let Some(timeslice) = rr_timeslice {
+        policy::install_signal_handler().expect("Failed to install the signal handler");
+        experiment = experiment.rr_timeslice(timeslice);
+    }
+
+    println!("Running {} processes ({} work units)...", workers.len(), work.name());
+    let outcome = experiment.run().unwrap_or_else(|e| {
+        eprintln!("Experiment failed: {}", e);
+        std::process::exit(1);
+    });
+    if let Some(calibration) = &outcome.calibration {
+        for line in calibration.summary() {
+            println!("{}", line);
+        }
+    }
+    if workers.iter().any(|sched| matches!(sched.policy, Policy::Rr { .. })) {
+        match rr_timeslice.map_or_else(policy::rr_timeslice, Ok) {
+            Ok(timeslice) => println!("SCHED_RR timeslice: {} ms", timeslice.as_millis()),
+            Err(e) => eprintln!("Failed to read the SCHED_RR timeslice: {}", e),
+        }
+    }
+
+    let labels: Vec<String> = outcome.traces.iter()
+        .map(|trace| format!("Process {} ({})", trace.worker, workers[trace.worker].label()))
+        .collect();
+    for (trace, label) in outcome.traces.iter().zip(&labels) {
+        println!("{} with PID {} finished at {:.1} ms", label, trace.pid, trace.end_ms());
+        trace.save(&format!("{}.data", trace.worker)).expect("Failed to write data");
+    }
+
+    println!("\nAll process finished.");
+
+    let filename = "sched-policy.png";
+    let caption = format!("Scheduling policies ({})", workers.iter().map(Sched::label).collect::<Vec<_>>().join(" vs "));
+    if let Err(e) = plot::plot_progress(filename, &caption, &outcome.traces, &labels) {
+        eprintln!("Failed to plot: {}", e);
+    } else {
+        println!("Graph saved to: {}", filename);
+    }
+}
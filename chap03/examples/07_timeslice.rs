@@ -0,0 +1,120 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+use chap03::calibrate::WorkUnit;
+use chap03::experiment::Experiment;
+use chap03::plot;
+use chap03::timeline::{self, Timeline};
+
+const DEFAULT_UNIT_US: u64 = 10;
+const DEFAULT_WORK_MS: u64 = 100;
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--unit-us US] [--work-ms MS] [--min-gap-ms MS] [--work UNIT] <concurrency>", prog_name);
+    eprintln!();
+    eprintln!("  Run <concurrency> load processes on CPU 0, recording their progress finely enough");
+    eprintln!("  to see when each one ran. Reports the observed timeslices, context switches and");
+    eprintln!("  CPU share of every process, next to the context switches counted in /proc/<pid>/status.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --unit-us US: Work between two timestamps (default: {})", DEFAULT_UNIT_US);
+    eprintln!("  --work-ms MS: Work of each process (default: {})", DEFAULT_WORK_MS);
+    eprintln!("  --min-gap-ms MS: Shortest gap taken as preemption (default: {})", timeline::DEFAULT_MIN_GAP_MS);
+    eprintln!("  --work UNIT: Work unit, calibrated loops or cputime (default: loops)");
+    eprintln!();
+    eprintln!("  Output: timeslice.txt, timeline.png");
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut unit_us = DEFAULT_UNIT_US;
+    let mut work_ms = DEFAULT_WORK_MS;
+    let mut min_gap_ms = timeline::DEFAULT_MIN_GAP_MS;
+    let mut work = WorkUnit::Loops;
+    let mut concurrency = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--unit-us" => {
+                unit_us = rest.next()
+                    .and_then(|us| us.parse().ok())
+                    .filter(|&us| us > 0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--work-ms" => {
+                work_ms = rest.next()
+                    .and_then(|ms| ms.parse().ok())
+                    .filter(|&ms| ms > 0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--min-gap-ms" => {
+                min_gap_ms = rest.next()
+                    .and_then(|ms| ms.parse().ok())
+                    .filter(|&ms: &f64| ms > 0.0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--work" => work = rest.next().and_then(|unit| WorkUnit::parse(unit)).unwrap_or_else(|| usage(prog_name)),
+            n => concurrency = Some(n.parse::<usize>().ok().filter(|&n| n > 0).unwrap_or_else(|| usage(prog_name))),
+        }
+    }
+    let concurrency = concurrency.unwrap_or_else(|| usage(prog_name));
+    let units = (work_ms * 1000).div_ceil(unit_us) as usize;
+
+    println!("Running {} processes ({} units of {} us, {})...", concurrency, units, unit_us, work.name());
+    let outcome = Experiment::new(concurrency)
+        .cpus(&[0])
+        .work(work)
+        .units(units)
+        .unit(Duration::from_micros(unit_us))
+        .run()
+        .unwrap_or_else(|e| {
+            eprintln!("Experiment failed: {}", e);
+            std::process::exit(1);
+        });
+    if let Some(calibration) = &outcome.calibration {
+        for line in calibration.summary() {
+            println!("{}", line);
+        }
+    }
+
+    let timelines: Vec<Timeline> = outcome.traces.iter().map(|trace| Timeline::from_trace(trace, min_gap_ms)).collect();
+    let window_ms = outcome.traces.iter().map(|trace| trace.end_ms()).fold(0.0, f64::max);
+
+    let mut file = File::create("timeslice.txt").expect("Failed to create timeslice.txt");
+    writeln!(file, "# worker\tpid\tslices\tswitches\trunning_ms\tcpu_share\tmin_slice_ms\tmedian_slice_ms\tmax_slice_ms\tvoluntary\tnonvoluntary")
+        .expect("Failed to write to file");
+    println!("\n{:>7} {:>7} {:>8} {:>9} {:>20} {:>10} {:>10}",
+        "process", "share", "switches", "unit [us]", "slice min/med/max", "/proc vol", "/proc invol");
+    for (trace, timeline) in outcome.traces.iter().zip(&timelines) {
+        let share = timeline.cpu_share(window_ms);
+        let slices = timeline.timeslices_ms();
+        let slices_label = slices.map_or("-".to_string(), |(min, median, max)| format!("{:.2}/{:.2}/{:.2} ms", min, median, max));
+        let (voluntary, nonvoluntary) = trace.ctxt_switches
+            .map_or(("-".to_string(), "-".to_string()), |switches| (switches.voluntary.to_string(), switches.nonvoluntary.to_string()));
+        println!("{:>7} {:>6.1}% {:>8} {:>9.1} {:>20} {:>10} {:>10}",
+            trace.worker, share * 100.0, timeline.switches(), timeline.unit_ms * 1000.0, slices_label, voluntary, nonvoluntary);
+
+        let (min, median, max) = slices.unwrap_or((0.0, 0.0, 0.0));
+        writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            trace.worker, trace.pid, timeline.slices.len(), timeline.switches(), timeline.running_ms(), share,
+            min, median, max, voluntary, nonvoluntary)
+            .expect("Failed to write to file");
+    }
+    let total_share: f64 = timelines.iter().map(|timeline| timeline.cpu_share(window_ms)).sum();
+    println!("\nTotal CPU share: {:.1}% of {:.1} ms", total_share * 100.0, window_ms);
+    println!("Every preemption shows up as a switch, so the switches should match the involuntary");
+    println!("context switches; more switches are gaps of interrupts or of the hypervisor, and the");
+    println!("voluntary ones include waiting for the start.");
+
+    let labels: Vec<String> = outcome.traces.iter().map(|trace| format!("Process {} (PID {})", trace.worker, trace.pid)).collect();
+    let caption = format!("Timeline (concurrency={}, {} us units)", concurrency, unit_us);
+    if let Err(e) = plot::plot_timeline("timeline.png", &caption, &timelines, &labels) {
+        eprintln!("Failed to plot: {}", e);
+    } else {
+        println!("Graph saved to: timeline.png");
+    }
+}
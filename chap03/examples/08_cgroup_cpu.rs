@@ -0,0 +1,136 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+use chap03::cgroup;
+use chap03::experiment::Experiment;
+use chap03::plot;
+use chap03::timeline::{self, Timeline};
+use chap03::trace;
+
+const DEFAULT_UNIT_US: u64 = 100;
+const DEFAULT_WORK_MS: u64 = 200;
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--unit-us US] [--work-ms MS] <group>...", prog_name);
+    eprintln!();
+    eprintln!("  Run the load processes of every <group> on CPU 0, each group in a temporary");
+    eprintln!("  cgroup v2 group with its own cpu.weight and cpu.max, and show how they share the CPU");
+    eprintln!("  and how often cpu.max throttled them. Needs root and the cgroup v2 cpu controller.");
+    eprintln!();
+    eprintln!("Groups:");
+    eprintln!("  <name>[:<processes>][,weight=N][,max=QUOTA_MS][,period=MS]");
+    eprintln!("    e.g. web,max=20 batch:2,weight=50 (default: 1 process, weight {},", cgroup::DEFAULT_WEIGHT);
+    eprintln!("    no quota, period {} ms)", cgroup::DEFAULT_PERIOD.as_millis());
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --unit-us US: Work between two timestamps (default: {})", DEFAULT_UNIT_US);
+    eprintln!("  --work-ms MS: Work of each process (default: {})", DEFAULT_WORK_MS);
+    eprintln!();
+    eprintln!("  Output: cgroup.txt, cgroup-progress.png, cgroup-timeline.png");
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut unit_us = DEFAULT_UNIT_US;
+    let mut work_ms = DEFAULT_WORK_MS;
+    let mut groups = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--unit-us" => {
+                unit_us = rest.next()
+                    .and_then(|us| us.parse().ok())
+                    .filter(|&us| us > 0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--work-ms" => {
+                work_ms = rest.next()
+                    .and_then(|ms| ms.parse().ok())
+                    .filter(|&ms| ms > 0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            spec => groups.push(cgroup::parse_group(spec).unwrap_or_else(|| usage(prog_name))),
+        }
+    }
+    if groups.is_empty() {
+        usage(prog_name);
+    }
+    let units = (work_ms * 1000).div_ceil(unit_us) as usize;
+
+    // Workers are numbered group after group
+    let mut members = Vec::new();
+    let mut next = 0;
+    for (_, processes) in &groups {
+        members.push((next..next + processes).collect::<Vec<usize>>());
+        next += processes;
+    }
+    let mut experiment = Experiment::new(next)
+        .cpus(&[0])
+        .units(units)
+        .unit(Duration::from_micros(unit_us));
+    for ((spec, _), workers) in groups.iter().zip(&members) {
+        experiment = experiment.group(spec.clone(), workers);
+    }
+
+    println!("Calibrating the busy loop and running {} processes in {} groups...", next, groups.len());
+    let outcome = experiment.run().unwrap_or_else(|e| {
+        eprintln!("Experiment failed: {}", e);
+        std::process::exit(1);
+    });
+    if let Some(calibration) = &outcome.calibration {
+        for line in calibration.summary() {
+            println!("{}", line);
+        }
+    }
+
+    let timelines: Vec<Timeline> = outcome.traces.iter()
+        .map(|trace| Timeline::from_trace(trace, timeline::DEFAULT_MIN_GAP_MS))
+        .collect();
+    let window_ms = outcome.traces.iter().map(|trace| trace.end_ms()).fold(0.0, f64::max);
+
+    let mut file = File::create("cgroup.txt").expect("Failed to create cgroup.txt");
+    writeln!(file, "# group\tprocesses\tweight\tquota_share\tcpu_share\tusage_usec\tnr_periods\tnr_throttled\tthrottled_usec")
+        .expect("Failed to write to file");
+    println!("\n{:<32} {:>7} {:>7} {:>10} {:>10} {:>10} {:>14}",
+        "group", "quota", "share", "usage [ms]", "periods", "throttled", "throttled [ms]");
+    let mut series = Vec::new();
+    for (((spec, processes), workers), stat) in groups.iter().zip(&members).zip(&outcome.cpu_stats) {
+        let share: f64 = workers.iter().map(|&worker| timelines[worker].cpu_share(window_ms)).sum();
+        let quota = spec.quota_share().map_or("-".to_string(), |quota| format!("{:.1}%", quota * 100.0));
+        println!("{:<32} {:>7} {:>6.1}% {:>10.1} {:>10} {:>10} {:>14.1}",
+            spec.label(), quota, share * 100.0, stat.usage_usec as f64 / 1000.0,
+            stat.nr_periods, stat.nr_throttled, stat.throttled_usec as f64 / 1000.0);
+        writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            spec.name, processes, spec.weight(), spec.quota_share().unwrap_or(0.0), share,
+            stat.usage_usec, stat.nr_periods, stat.nr_throttled, stat.throttled_usec)
+            .expect("Failed to write to file");
+
+        let traces: Vec<_> = workers.iter().map(|&worker| &outcome.traces[worker]).collect();
+        series.push((spec.label(), trace::combined_progress(&traces)));
+    }
+    println!("\nShares are of CPU 0 over {:.1} ms. A group over its quota runs in bursts of the quota", window_ms);
+    println!("and then waits for the next period: each throttled period is one tooth of the sawtooth.");
+
+    let caption = "Progress of cgroups (cpu.weight, cpu.max)";
+    if let Err(e) = plot::plot_series_progress("cgroup-progress.png", caption, &series) {
+        eprintln!("Failed to plot: {}", e);
+    } else {
+        println!("Graph saved to: cgroup-progress.png");
+    }
+
+    let labels: Vec<String> = outcome.traces.iter()
+        .map(|trace| {
+            let group = members.iter().position(|workers| workers.contains(&trace.worker)).expect("Every worker is in a group");
+            format!("{} #{}", groups[group].0.name, trace.worker)
+        })
+        .collect();
+    if let Err(e) = plot::plot_timeline("cgroup-timeline.png", "Timeline of cgroups", &timelines, &labels) {
+        eprintln!("Failed to plot: {}", e);
+    } else {
+        println!("Graph saved to: cgroup-timeline.png");
+    }
+}
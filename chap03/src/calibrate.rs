@@ -0,0 +1,248 @@
+//! Work units of the workers and the calibration of the busy loop.
+//!
+//! A unit of work is either a fixed number of busy loop iterations,
+//! calibrated to take a given time on this CPU, or spinning until the
+//! thread's CPU time (CLOCK_THREAD_CPUTIME_ID) has advanced by that time.
+//! Loops do the same work on every run of an experiment, but how long they
+//! take follows the CPU frequency; CPU time takes the same time on any
+//! machine, whatever the frequency. Reading the thread's CPU time is a
+//! system call, and returning from it lets the scheduler preempt the worker
+//! right away instead of at the next timer tick, so timeslices observed with
+//! CPU time units can be shorter than with loops.
+//!
+//! The calibration times short rounds of the loop against the thread's CPU
+//! time, so that time spent preempted does not count, and repeats them until
+//! a few consecutive rounds agree. It records the frequency of the CPU in
+//! every round to tell whether scaling or turbo moved it meanwhile.
+
+use crate::cpufreq;
+use std::hint::black_box;
+use std::time::Duration;
+
+/// CPU time of one calibration round
+const ROUND: Duration = Duration::from_millis(20);
+/// Consecutive rounds that have to agree
+const STABLE_ROUNDS: usize = 5;
+/// How far these rounds may be from their median
+const TOLERANCE: f64 = 0.02;
+const MAX_ROUNDS: usize = 100;
+/// Frequency change between rounds worth reporting
+const FREQ_TOLERANCE: f64 = 0.05;
+
+/// What a worker repeats as one unit of work
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkUnit {
+    /// Calibrated busy loop iterations
+    #[default]
+    Loops,
+    /// Spinning until the thread's CPU time has advanced by the unit
+    CpuTime,
+}
+
+impl WorkUnit {
+    pub const ALL: [WorkUnit; 2] = [WorkUnit::Loops, WorkUnit::CpuTime];
+
+    pub fn parse(name: &str) -> Option<WorkUnit> {
+        WorkUnit::ALL.into_iter().find(|unit| unit.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkUnit::Loops => "loops",
+            WorkUnit::CpuTime => "cputime",
+        }
+    }
+}
+
+/// Spin `nloop` iterations of an empty loop
+pub fn busy_loop(nloop: u64) {
+    for i in 0..nloop {
+        black_box(i);
+    }
+}
+
+/// CPU time the calling thread has used
+pub fn thread_cpu_time() -> Duration {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Spin until the calling thread has used `cpu_time` more CPU time
+pub fn busy_for(cpu_time: Duration) {
+    let end = thread_cpu_time() + cpu_time;
+    while thread_cpu_time() < end {
+        black_box(());
+    }
+}
+
+/// Result of calibrating the busy loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    /// Loops per millisecond of CPU time, the median of the stable rounds
+    pub loops_per_msec: u64,
+    /// Loops per millisecond of every round
+    pub rounds: Vec<f64>,
+    /// Whether the last rounds agreed before giving up
+    pub stable: bool,
+    /// CPU the calibration ran on
+    pub cpu: usize,
+    /// Frequency of the CPU after every round in kHz, empty without cpufreq
+    pub freqs_khz: Vec<u64>,
+    /// Whether turbo was allowed, if the system tells
+    pub boost: Option<bool>,
+    /// Base frequency of the CPU, if the driver reports it
+    pub base_khz: Option<u64>,
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted[sorted.len() / 2]
+}
+
+/// Whether `rates` agree within the tolerance of their median
+fn agree(rates: &[f64]) -> bool {
+    let median = median(rates);
+    rates.iter().all(|rate| (rate - median).abs() <= TOLERANCE * median)
+}
+
+/// Time rounds of the busy loop until they agree
+pub fn calibrate() -> Calibration {
+    let cpu = unsafe { libc::sched_getcpu() }.max(0) as usize;
+
+    // Grow a round until it takes long enough to time precisely
+    let mut nloop: u64 = 1 << 16;
+    loop {
+        let start = thread_cpu_time();
+        busy_loop(nloop);
+        if thread_cpu_time() - start >= ROUND / 4 {
+            break;
+        }
+        nloop *= 2;
+    }
+
+    let mut rounds = Vec::new();
+    let mut freqs_khz = Vec::new();
+    let mut stable = false;
+    while rounds.len() < MAX_ROUNDS {
+        let start = thread_cpu_time();
+        busy_loop(nloop);
+        let elapsed = thread_cpu_time() - start;
+        let rate = nloop as f64 / (elapsed.as_secs_f64() * 1000.0);
+        // Aim the next round at ROUND
+        nloop = ((rate * ROUND.as_secs_f64() * 1000.0) as u64).max(1);
+        rounds.push(rate);
+        if let Some(khz) = cpufreq::cur_khz(cpu) {
+            freqs_khz.push(khz);
+        }
+        if rounds.len() >= STABLE_ROUNDS && agree(&rounds[rounds.len() - STABLE_ROUNDS..]) {
+            stable = true;
+            break;
+        }
+    }
+
+    let last = &rounds[rounds.len().saturating_sub(STABLE_ROUNDS)..];
+    Calibration {
+        loops_per_msec: median(last) as u64,
+        stable,
+        cpu,
+        freqs_khz,
+        boost: cpufreq::boost_enabled(),
+        base_khz: cpufreq::read(cpu).ok().and_then(|freq| freq.base_khz),
+        rounds,
+    }
+}
+
+impl Calibration {
+    /// Whether the frequency moved by more than a few percent between rounds
+    pub fn freq_changed(&self) -> bool {
+        let min = self.freqs_khz.iter().copied().min().unwrap_or(0);
+        let max = self.freqs_khz.iter().copied().max().unwrap_or(0);
+        max as f64 > min as f64 * (1.0 + FREQ_TOLERANCE)
+    }
+
+    /// Whether some round ran above the base frequency
+    pub fn boosted(&self) -> bool {
+        self.base_khz.is_some_and(|base| self.freqs_khz.iter().any(|&khz| khz > base))
+    }
+
+    /// Lines describing the calibration and what may skew it
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Calibrated: {} loops/ms of CPU time on CPU {} after {} rounds{}",
+            self.loops_per_msec,
+            self.cpu,
+            self.rounds.len(),
+            if self.stable { "" } else { " (not stable, the machine is busy or changing frequency)" },
+        )];
+        match (self.freqs_khz.iter().min(), self.freqs_khz.iter().max()) {
+            (Some(min), Some(max)) => lines.push(format!("CPU frequency: {} - {} MHz during calibration", min / 1000, max / 1000)),
+            _ => lines.push("CPU frequency: unknown, no cpufreq in sysfs (e.g. a virtual machine)".to_string()),
+        }
+        if self.freq_changed() {
+            lines.push("Warning: the frequency changed during calibration, so loops are not a fixed time;".to_string());
+            lines.push("         consider the performance governor or CPU time work units".to_string());
+        }
+        if self.boosted() {
+            lines.push("Warning: turbo ran the CPU above its base frequency, which lasts only while".to_string());
+            lines.push("         power and temperature allow".to_string());
+        } else if self.boost == Some(true) {
+            lines.push("Turbo is enabled and may raise the frequency during the experiment".to_string());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration(freqs_khz: &[u64], base_khz: Option<u64>) -> Calibration {
+        Calibration {
+            loops_per_msec: 100_000,
+            rounds: vec![100_000.0; STABLE_ROUNDS],
+            stable: true,
+            cpu: 0,
+            freqs_khz: freqs_khz.to_vec(),
+            boost: None,
+            base_khz,
+        }
+    }
+
+    #[test]
+    fn median_of_rounds() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        // The upper of the two middle values
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 3.0);
+        assert_eq!(median(&[7.0]), 7.0);
+    }
+
+    #[test]
+    fn rounds_agree_within_the_tolerance() {
+        assert!(agree(&[100.0, 101.0, 99.0, 102.0, 98.0]));
+        assert!(!agree(&[100.0, 101.0, 99.0, 102.0, 97.0]));
+        // One slow round, e.g. a frequency drop, breaks the agreement
+        assert!(!agree(&[100.0, 100.0, 100.0, 100.0, 80.0]));
+    }
+
+    #[test]
+    fn frequency_changes_are_noticed() {
+        assert!(!calibration(&[], None).freq_changed());
+        assert!(!calibration(&[2_000_000, 2_050_000, 2_100_000], None).freq_changed());
+        assert!(calibration(&[2_000_000, 2_200_000], None).freq_changed());
+    }
+
+    #[test]
+    fn rounds_above_the_base_frequency_are_boosted() {
+        let freqs = [2_000_000, 3_500_000];
+        assert!(calibration(&freqs, Some(2_000_000)).boosted());
+        assert!(!calibration(&freqs, Some(3_500_000)).boosted());
+        // Without a base frequency there is nothing to compare with
+        assert!(!calibration(&freqs, None).boosted());
+
+        let summary = calibration(&freqs, Some(2_000_000)).summary();
+        assert_eq!(summary[1], "CPU frequency: 2000 - 3500 MHz during calibration");
+        assert!(summary.iter().any(|line| line.starts_with("Warning: turbo")));
+    }
+}
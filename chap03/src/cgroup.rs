@@ -0,0 +1,369 @@
+//! Temporary cgroup v2 groups for CPU control experiments.
+//!
+//! The cpu controller shares the CPU between groups in two ways. `cpu.weight`
+//! (1 to 10000, default 100) divides the CPU among busy groups in
+//! proportion to their weights, like nice values divide it among tasks, and
+//! costs nothing while the CPU is idle. `cpu.max` caps a group at a quota of
+//! CPU time per period whether the CPU is idle or not: once the group's tasks
+//! have used the quota, they are throttled until the next period starts, so
+//! progress comes in bursts followed by stalls, a sawtooth. `cpu.stat`
+//! counts the periods, how many of them were throttled and for how long.
+//!
+//! A session creates `chap03-<pid>` below the cgroup2 mount and one child per
+//! group in it; the workers are moved into their group before they start.
+//!
+//! Only the v2 interface is used; cgroup v1 had the same knobs as
+//! `cpu.shares` and `cpu.cfs_quota_us` over `cpu.cfs_period_us`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Default of cpu.weight
+pub const DEFAULT_WEIGHT: u32 = 100;
+/// Default period of cpu.max
+pub const DEFAULT_PERIOD: Duration = Duration::from_millis(100);
+
+/// Prefix of the session groups below the cgroup2 mount
+const SESSION_PREFIX: &str = "chap03-";
+
+/// One group of workers and how it shares the CPU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSpec {
+    /// Name of the group's directory; letters, digits, '-' and '_'
+    pub name: String,
+    /// cpu.weight from 1 to 10000
+    pub weight: Option<u32>,
+    /// CPU time per period of cpu.max; `None` means "max", i.e. unlimited
+    pub quota: Option<Duration>,
+    pub period: Duration,
+}
+
+impl GroupSpec {
+    pub fn new(name: &str) -> GroupSpec {
+        GroupSpec { name: name.to_string(), weight: None, quota: None, period: DEFAULT_PERIOD }
+    }
+
+    /// Parse "<name>[,weight=N][,max=QUOTA_MS][,period=MS]", e.g. "web,max=20"
+    pub fn parse(spec: &str) -> Option<GroupSpec> {
+        let mut fields = spec.split(',');
+        let name = fields.next().filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })?;
+        let mut group = GroupSpec::new(name);
+        let ms = |value: &str| {
+            value.parse::<f64>().ok().filter(|&ms| ms > 0.0).map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        };
+        for field in fields {
+            match field.split_once('=')? {
+                ("weight", weight) => group.weight = Some(weight.parse().ok().filter(|weight| (1..=10000).contains(weight))?),
+                ("max", quota) => group.quota = Some(ms(quota)?),
+                ("period", period) => group.period = ms(period)?,
+                _ => return None,
+            }
+        }
+        Some(group)
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(DEFAULT_WEIGHT)
+    }
+
+    /// Line written to cpu.max, e.g. "20000 100000" or "max 100000"
+    pub fn cpu_max(&self) -> String {
+        let quota = self.quota.map_or("max".to_string(), |quota| quota.as_micros().to_string());
+        format!("{} {}", quota, self.period.as_micros())
+    }
+
+    /// Fraction of one CPU the quota allows, if there is one
+    pub fn quota_share(&self) -> Option<f64> {
+        self.quota.map(|quota| quota.as_secs_f64() / self.period.as_secs_f64())
+    }
+
+    /// Name and settings for labels, e.g. "web (weight 100, max 20/100 ms)"
+    pub fn label(&self) -> String {
+        let mut settings = vec![format!("weight {}", self.weight())];
+        if let Some(quota) = self.quota {
+            settings.push(format!("max {}/{} ms", quota.as_secs_f64() * 1000.0, self.period.as_secs_f64() * 1000.0));
+        }
+        format!("{} ({})", self.name, settings.join(", "))
+    }
+}
+
+/// Parse "<name>[:<processes>][,<settings>]" as `GroupSpec::parse`, with the
+/// number of processes of the group (default 1), e.g. "batch:2,weight=50"
+pub fn parse_group(spec: &str) -> Option<(GroupSpec, usize)> {
+    let (head, settings) = match spec.split_once(',') {
+        Some((head, settings)) => (head, Some(settings)),
+        None => (spec, None),
+    };
+    let (name, processes) = match head.split_once(':') {
+        Some((name, processes)) => (name, processes.parse().ok().filter(|&n| n > 0)?),
+        None => (head, 1),
+    };
+    let group = GroupSpec::parse(&settings.map_or(name.to_string(), |settings| format!("{},{}", name, settings)))?;
+    Some((group, processes))
+}
+
+/// Counters of cpu.stat
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuStat {
+    pub usage_usec: u64,
+    pub user_usec: u64,
+    pub system_usec: u64,
+    /// Periods of cpu.max in which the group was runnable
+    pub nr_periods: u64,
+    /// Periods in which the group ran out of quota
+    pub nr_throttled: u64,
+    /// Time the group's tasks waited for the next period
+    pub throttled_usec: u64,
+}
+
+impl CpuStat {
+    /// Parse "<key> <value>" lines; the cpu.max counters are missing
+    /// without the cpu controller and stay 0
+    pub fn parse(content: &str) -> CpuStat {
+        let mut stat = CpuStat::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse() else {
+                continue;
+            };
+            match key {
+                "usage_usec" => stat.usage_usec = value,
+                "user_usec" => stat.user_usec = value,
+                "system_usec" => stat.system_usec = value,
+                "nr_periods" => stat.nr_periods = value,
+                "nr_throttled" => stat.nr_throttled = value,
+                "throttled_usec" => stat.throttled_usec = value,
+                _ => {}
+            }
+        }
+        stat
+    }
+}
+
+/// Where cgroup v2 is mounted, from /proc/self/mountinfo
+pub fn mount_point() -> io::Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    mountinfo
+        .lines()
+        .find_map(|line| {
+            // "<id> <parent> <dev> <root> <mount point> <options> ... - <fstype> <source> <options>"
+            let (fields, rest) = line.split_once(" - ")?;
+            (rest.split_whitespace().next()? == "cgroup2").then(|| fields.split_whitespace().nth(4).map(PathBuf::from))?
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cgroup v2 is not mounted"))
+}
+
+/// The session group or one of the worker groups in it
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: PathBuf,
+}
+
+impl Cgroup {
+    fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    fn write(&self, name: &str, value: &str) -> io::Result<()> {
+        fs::write(self.file(name), value).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to write {:?} to {}: {}", value, self.file(name).display(), e))
+        })
+    }
+
+    /// Controllers the parent hands down to this group, from cgroup.controllers
+    pub fn controllers(&self) -> io::Result<Vec<String>> {
+        let content = fs::read_to_string(self.file("cgroup.controllers"))?;
+        Ok(content.split_whitespace().map(str::to_string).collect())
+    }
+
+    /// Give the children the files of `controller` through
+    /// cgroup.subtree_control; false if they already had them
+    pub fn enable_controller(&self, controller: &str) -> io::Result<bool> {
+        let enabled = fs::read_to_string(self.file("cgroup.subtree_control"))?;
+        if enabled.split_whitespace().any(|c| c == controller) {
+            return Ok(false);
+        }
+        self.write("cgroup.subtree_control", &format!("+{}", controller))?;
+        Ok(true)
+    }
+
+    pub fn set_weight(&self, weight: u32) -> io::Result<()> {
+        self.write("cpu.weight", &weight.clamp(1, 10000).to_string())
+    }
+
+    pub fn set_max(&self, spec: &GroupSpec) -> io::Result<()> {
+        self.write("cpu.max", &spec.cpu_max())
+    }
+
+    pub fn cpu_stat(&self) -> io::Result<CpuStat> {
+        Ok(CpuStat::parse(&fs::read_to_string(self.file("cpu.stat"))?))
+    }
+
+    /// Move the process `pid` into the group
+    pub fn add_process(&self, pid: libc::pid_t) -> io::Result<()> {
+        self.write("cgroup.procs", &pid.to_string())
+    }
+
+    /// Remove the group; rmdir fails with EBUSY until reaped workers have
+    /// left it, so retry for up to a second
+    fn remove_dir(path: &Path) -> io::Result<()> {
+        let mut attempts = 0;
+        loop {
+            match fs::remove_dir(path) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) && attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Remove the `chap03-<pid>` groups, and the worker groups in them, that a
+/// killed run left behind before its `Session` was dropped
+fn remove_stale_sessions(root: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|name| name.strip_prefix(SESSION_PREFIX)) else {
+            continue;
+        };
+        if pid.parse::<u32>().is_err() || Path::new("/proc").join(pid).exists() {
+            continue;
+        }
+        if let Ok(children) = fs::read_dir(entry.path()) {
+            for child in children.flatten().filter(|child| child.path().is_dir()) {
+                let _ = Cgroup::remove_dir(&child.path());
+            }
+        }
+        let _ = Cgroup::remove_dir(&entry.path());
+    }
+}
+
+/// The group of this process's experiments, with one child per worker group
+pub struct Session {
+    root: Cgroup,
+    group: Cgroup,
+    /// Whether the cpu controller was enabled in the root group by us
+    enabled_root_cpu: bool,
+    groups: Vec<Cgroup>,
+}
+
+impl Session {
+    /// Create `chap03-<pid>` below the cgroup2 mount with the cpu controller
+    /// enabled for its children
+    pub fn create() -> io::Result<Session> {
+        let root = Cgroup { path: mount_point()? };
+        remove_stale_sessions(&root.path);
+        if !root.controllers()?.iter().any(|c| c == "cpu") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "The cpu controller is not available in {}; cgroup v2 cannot use it while a v1 \
+                     hierarchy such as cpu,cpuacct mounts it",
+                    root.path.display()
+                ),
+            ));
+        }
+
+        let enabled_root_cpu = root.enable_controller("cpu")?;
+        let path = root.path.join(format!("{}{}", SESSION_PREFIX, std::process::id()));
+        fs::create_dir(&path).map_err(|e| io::Error::new(e.kind(), format!("Failed to create {}: {}", path.display(), e)))?;
+        let group = Cgroup { path };
+        let session = Session { root, group, enabled_root_cpu, groups: Vec::new() };
+        session.group.enable_controller("cpu")?;
+        Ok(session)
+    }
+
+    /// Create a group with its weight and quota
+    pub fn add_group(&mut self, spec: &GroupSpec) -> io::Result<&Cgroup> {
+        let path = self.group.path.join(&spec.name);
+        fs::create_dir(&path).map_err(|e| io::Error::new(e.kind(), format!("Failed to create {}: {}", path.display(), e)))?;
+        self.groups.push(Cgroup { path });
+        let group = &self.groups[self.groups.len() - 1];
+        group.set_weight(spec.weight())?;
+        group.set_max(spec)?;
+        Ok(group)
+    }
+
+    /// The groups, in the order they were added
+    pub fn groups(&self) -> &[Cgroup] {
+        &self.groups
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for group in self.groups.drain(..) {
+            if let Err(e) = Cgroup::remove_dir(&group.path) {
+                eprintln!("Failed to remove {}: {}", group.path.display(), e);
+            }
+        }
+        if let Err(e) = Cgroup::remove_dir(&self.group.path) {
+            eprintln!("Failed to remove {}: {}", self.group.path.display(), e);
+        }
+        if self.enabled_root_cpu {
+            // Refused while other groups below the root still enable cpu for
+            // their children; leaving it on is harmless then
+            let _ = self.root.write("cgroup.subtree_control", "-cpu");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_settings_are_parsed() {
+        let web = GroupSpec::parse("web,max=20").unwrap();
+        assert_eq!(web.name, "web");
+        assert_eq!(web.weight(), DEFAULT_WEIGHT);
+        assert_eq!(web.quota.map(|quota| quota.as_micros()), Some(20000));
+        assert_eq!(web.cpu_max(), "20000 100000");
+        assert!((web.quota_share().unwrap() - 0.2).abs() < 1e-9);
+
+        let batch = GroupSpec::parse("batch,weight=50,period=50").unwrap();
+        assert_eq!(batch.weight, Some(50));
+        assert_eq!(batch.cpu_max(), "max 50000");
+        assert_eq!(batch.quota_share(), None);
+    }
+
+    #[test]
+    fn bad_groups_are_rejected() {
+        for spec in ["web,weight=0", "web,weight=10001", "web,weight=x", "web,max=0", "web,max", "web,nice=5", "", "we b", "web/1"] {
+            assert_eq!(GroupSpec::parse(spec), None, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn process_counts_are_split_off() {
+        let (batch, processes) = parse_group("batch:2,weight=50").unwrap();
+        assert_eq!((batch.name.as_str(), batch.weight, processes), ("batch", Some(50), 2));
+        assert_eq!(parse_group("web").map(|(spec, processes)| (spec.name, processes)), Some(("web".to_string(), 1)));
+        assert_eq!(parse_group("web:0"), None);
+        assert_eq!(parse_group("web:x,max=20"), None);
+        assert_eq!(parse_group("web:2,bogus=1"), None);
+    }
+
+    #[test]
+    fn cpu_stat_without_the_cpu_controller_has_no_throttling() {
+        let stat = CpuStat::parse("usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n");
+        assert_eq!(stat, CpuStat { usage_usec: 1500, user_usec: 1000, system_usec: 500, ..CpuStat::default() });
+
+        let stat = CpuStat::parse(
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 10\nnr_throttled 4\nthrottled_usec 320000\nnr_bursts 0\n",
+        );
+        assert_eq!((stat.nr_periods, stat.nr_throttled, stat.throttled_usec), (10, 4, 320000));
+    }
+}
@@ -0,0 +1,72 @@
+//! CPU frequency scaling as described by sysfs.
+//!
+//! A busy loop calibrated at one frequency does more or less work per
+//! millisecond at another, so the calibration watches the frequency of its
+//! CPU. Turbo (boost) lets a CPU run above its base frequency while power
+//! and temperature allow, which is the usual reason for the frequency to
+//! change in the middle of an experiment. Virtual machines and some
+//! containers have no cpufreq directory at all.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const CPUFREQ_DIR: &str = "/sys/devices/system/cpu/cpufreq";
+const INTEL_PSTATE_DIR: &str = "/sys/devices/system/cpu/intel_pstate";
+
+/// Frequency scaling of one CPU, frequencies in kHz as sysfs reports them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuFreq {
+    pub driver: String,
+    pub governor: String,
+    pub cur_khz: u64,
+    /// Hardware limits, boost frequencies included
+    pub min_khz: u64,
+    pub max_khz: u64,
+    /// Highest frequency without boost, if the driver reports it
+    pub base_khz: Option<u64>,
+}
+
+impl CpuFreq {
+    /// Whether the CPU currently runs above its base frequency
+    pub fn is_boosted(&self) -> bool {
+        self.base_khz.is_some_and(|base| self.cur_khz > base)
+    }
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+fn read_khz(path: &Path) -> io::Result<u64> {
+    read_trimmed(path)?
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid frequency in {}", path.display())))
+}
+
+/// Frequency scaling of `cpu`; fails with NotFound without cpufreq
+pub fn read(cpu: usize) -> io::Result<CpuFreq> {
+    let dir = Path::new("/sys/devices/system/cpu").join(format!("cpu{}", cpu)).join("cpufreq");
+    Ok(CpuFreq {
+        driver: read_trimmed(&dir.join("scaling_driver")).unwrap_or_default(),
+        governor: read_trimmed(&dir.join("scaling_governor")).unwrap_or_default(),
+        cur_khz: read_khz(&dir.join("scaling_cur_freq"))?,
+        min_khz: read_khz(&dir.join("cpuinfo_min_freq"))?,
+        max_khz: read_khz(&dir.join("cpuinfo_max_freq"))?,
+        base_khz: read_khz(&dir.join("base_frequency")).ok(),
+    })
+}
+
+/// Current frequency of `cpu` in kHz, if cpufreq reports it
+pub fn cur_khz(cpu: usize) -> Option<u64> {
+    read_khz(&Path::new("/sys/devices/system/cpu").join(format!("cpu{}", cpu)).join("cpufreq/scaling_cur_freq")).ok()
+}
+
+/// Whether turbo is allowed, from cpufreq's boost switch or from
+/// intel_pstate's no_turbo; `None` if neither exists
+pub fn boost_enabled() -> Option<bool> {
+    if let Ok(boost) = read_trimmed(&Path::new(CPUFREQ_DIR).join("boost")) {
+        return Some(boost == "1");
+    }
+    read_trimmed(&Path::new(INTEL_PSTATE_DIR).join("no_turbo")).ok().map(|no_turbo| no_turbo == "0")
+}
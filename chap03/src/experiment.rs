@@ -0,0 +1,361 @@
+//! Scheduler experiments: busy worker processes sharing a set of CPUs.
+//!
+//! Each worker is a forked process doing the same amount of busy loop work
+//! in equal units, and recording when it finished each unit into memory
+//! shared with the parent, so no file is written while the workers compete.
+//! The workers are all forked and given their scheduling policy first, then
+//! released together by closing a pipe they wait on.
+//!
+//! A unit is calibrated busy loop iterations unless the experiment uses CPU
+//! time units, see `calibrate`. Workers can also be put in cgroup v2 groups
+//! with their own CPU weight and quota, see `cgroup`.
+//!
+//! ```no_run
+//! use chap03::experiment::Experiment;
+//!
+//! let outcome = Experiment::new(2).cpus(&[0]).nice(1, 5).run().expect("Experiment failed");
+//! for trace in &outcome.traces {
+//!     println!("worker {} finished after {:.1} ms", trace.worker, trace.end_ms());
+//! }
+//! ```
+
+use crate::affinity;
+use crate::calibrate::{self, Calibration, WorkUnit};
+use crate::cgroup::{CpuStat, GroupSpec, Session};
+use crate::policy::{self, Policy, RrTimeslice, Sched};
+use crate::trace::{CtxtSwitches, Trace};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Work units of a worker unless set otherwise
+pub const DEFAULT_UNITS: usize = 100;
+/// Work per unit unless set otherwise
+pub const DEFAULT_UNIT: Duration = Duration::from_millis(1);
+
+/// Workers and how they are run; build with `new` and the setters, then `run`
+#[derive(Debug, Clone)]
+pub struct Experiment {
+    /// Scheduling of each worker; `None` keeps what it inherits from the caller
+    workers: Vec<Option<Sched>>,
+    cpus: Vec<usize>,
+    loops_per_msec: Option<u64>,
+    work: WorkUnit,
+    units: usize,
+    unit: Duration,
+    rr_timeslice: Option<Duration>,
+    /// cgroups and the workers in each
+    groups: Vec<(GroupSpec, Vec<usize>)>,
+}
+
+/// What an experiment measured
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// Busy loop iterations taken as 1 ms of work, `None` with CPU time units
+    pub loops_per_msec: Option<u64>,
+    /// Calibration done for the run, unless the loops were given or unused
+    pub calibration: Option<Calibration>,
+    /// One trace per worker, in worker order
+    pub traces: Vec<Trace>,
+    /// cpu.stat of every group once its workers finished, in group order
+    pub cpu_stats: Vec<CpuStat>,
+}
+
+/// Timestamps written by the workers, in a shared anonymous mapping
+struct SharedTimes {
+    ptr: *mut f64,
+    len: usize,
+}
+
+impl SharedTimes {
+    fn new(len: usize) -> io::Result<SharedTimes> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len.max(1) * size_of::<f64>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SharedTimes { ptr: ptr as *mut f64, len })
+    }
+
+    fn set(&self, index: usize, value: f64) {
+        assert!(index < self.len);
+        unsafe { self.ptr.add(index).write_volatile(value) };
+    }
+
+    fn get(&self, index: usize) -> f64 {
+        assert!(index < self.len);
+        unsafe { self.ptr.add(index).read_volatile() }
+    }
+}
+
+impl Drop for SharedTimes {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len.max(1) * size_of::<f64>()) };
+    }
+}
+
+/// Kill and reap the workers forked so far
+fn abort(pids: &[libc::pid_t]) {
+    for &pid in pids {
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+/// Retry `call` while it fails with EINTR
+fn retry(mut call: impl FnMut() -> libc::c_int) -> io::Result<()> {
+    loop {
+        if call() >= 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Wait for the worker `pid` to exit and reap it, returning its context
+/// switches and wait status
+fn reap(pid: libc::pid_t) -> io::Result<(Option<CtxtSwitches>, libc::c_int)> {
+    // Leave the worker a zombie until its context switches are read
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    retry(|| unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) })?;
+    let ctxt_switches = CtxtSwitches::read(pid).ok();
+
+    let mut status = 0;
+    retry(|| unsafe { libc::waitpid(pid, &mut status, 0) })?;
+    Ok((ctxt_switches, status))
+}
+
+impl Experiment {
+    /// `workers` workers on CPU 0, with the caller's policy and nice value
+    pub fn new(workers: usize) -> Experiment {
+        Experiment {
+            workers: vec![None; workers],
+            cpus: vec![0],
+            loops_per_msec: None,
+            work: WorkUnit::default(),
+            units: DEFAULT_UNITS,
+            unit: DEFAULT_UNIT,
+            rr_timeslice: None,
+            groups: Vec::new(),
+        }
+    }
+
+    /// CPUs the workers share; empty leaves the affinity unchanged
+    pub fn cpus(mut self, cpus: &[usize]) -> Experiment {
+        self.cpus = cpus.to_vec();
+        self
+    }
+
+    /// Policy and nice value of worker `worker`
+    pub fn sched(mut self, worker: usize, sched: Sched) -> Experiment {
+        self.workers[worker] = Some(sched);
+        self
+    }
+
+    pub fn nice(mut self, worker: usize, nice: i32) -> Experiment {
+        self.workers[worker].get_or_insert_default().nice = nice;
+        self
+    }
+
+    pub fn policy(mut self, worker: usize, policy: Policy) -> Experiment {
+        self.workers[worker].get_or_insert_default().policy = policy;
+        self
+    }
+
+    /// Use a known calibration instead of estimating it before the run
+    pub fn loops_per_msec(mut self, loops_per_msec: u64) -> Experiment {
+        self.loops_per_msec = Some(loops_per_msec);
+        self
+    }
+
+    /// What a unit of work is
+    pub fn work(mut self, work: WorkUnit) -> Experiment {
+        self.work = work;
+        self
+    }
+
+    /// Number of work units, i.e. timestamps, of each worker
+    pub fn units(mut self, units: usize) -> Experiment {
+        self.units = units;
+        self
+    }
+
+    /// Work per unit: the finer, the more precisely the traces show when a
+    /// worker was preempted
+    pub fn unit(mut self, unit: Duration) -> Experiment {
+        self.unit = unit;
+        self
+    }
+
+    /// SCHED_RR timeslice during the run; the system's timeslice is
+    /// restored afterwards, and on SIGINT/SIGTERM once the caller installed
+    /// `policy::install_signal_handler`
+    pub fn rr_timeslice(mut self, timeslice: Duration) -> Experiment {
+        self.rr_timeslice = Some(timeslice);
+        self
+    }
+
+    /// Run `workers` in a cgroup of their own, created for the run
+    pub fn group(mut self, spec: GroupSpec, workers: &[usize]) -> Experiment {
+        assert!(workers.iter().all(|&worker| worker < self.workers.len()), "No such worker in {:?}", workers);
+        self.groups.push((spec, workers.to_vec()));
+        self
+    }
+
+    /// Scheduling set for each worker, `None` where it is inherited
+    pub fn workers(&self) -> &[Option<Sched>] {
+        &self.workers
+    }
+
+    /// Fork the workers, run them to completion and collect their traces.
+    /// The calling process stays on the experiment's CPUs until it returns.
+    pub fn run(&self) -> io::Result<Outcome> {
+        // Restored on every return from here on, the sysctl is system wide
+        let timeslice = self.rr_timeslice.map(RrTimeslice::set).transpose()?;
+        let allowed = affinity::allowed_cpus()?;
+        if !self.cpus.is_empty() {
+            affinity::pin_to(&self.cpus)?;
+        }
+        let outcome = self.run_pinned();
+        let unpinned = affinity::pin_to(&allowed);
+        timeslice.map_or(Ok(()), RrTimeslice::restore)?;
+        unpinned?;
+        outcome
+    }
+
+    fn run_pinned(&self) -> io::Result<Outcome> {
+        let calibration = match (self.work, self.loops_per_msec) {
+            (WorkUnit::Loops, None) => Some(calibrate::calibrate()),
+            _ => None,
+        };
+        let loops_per_msec = match self.work {
+            WorkUnit::Loops => calibration.as_ref().map(|calibration| calibration.loops_per_msec).or(self.loops_per_msec),
+            WorkUnit::CpuTime => None,
+        };
+        let loops_per_unit = loops_per_msec.map(|loops| (loops as u128 * self.unit.as_nanos() / 1_000_000).max(1) as u64);
+        let times = SharedTimes::new(self.workers.len() * self.units)?;
+        let mut session = None;
+        if !self.groups.is_empty() {
+            let session = session.insert(Session::create()?);
+            for (spec, _) in &self.groups {
+                session.add_group(spec)?;
+            }
+        }
+
+        let mut gate = [0; 2];
+        if unsafe { libc::pipe(gate.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [gate_read, gate_write] = gate;
+
+        let base = Instant::now();
+        let mut pids = Vec::new();
+        for worker in 0..self.workers.len() {
+            let pid = unsafe { libc::fork() };
+            if pid < 0 {
+                let e = io::Error::last_os_error();
+                abort(&pids);
+                unsafe {
+                    libc::close(gate_read);
+                    libc::close(gate_write);
+                }
+                return Err(e);
+            }
+            if pid == 0 {
+                // Wait until the parent closes the pipe: read returns 0 at EOF
+                unsafe {
+                    // The parent may block these for its signal handling thread
+                    let signals = policy::termination_signals();
+                    libc::pthread_sigmask(libc::SIG_UNBLOCK, &signals, std::ptr::null_mut());
+                    libc::close(gate_write);
+                    let mut byte = 0u8;
+                    libc::read(gate_read, &mut byte as *mut u8 as *mut libc::c_void, 1);
+                }
+                for unit in 0..self.units {
+                    match loops_per_unit {
+                        Some(nloop) => calibrate::busy_loop(nloop),
+                        None => calibrate::busy_for(self.unit),
+                    }
+                    times.set(worker * self.units + unit, base.elapsed().as_secs_f64() * 1000.0);
+                }
+                unsafe { libc::_exit(0) };
+            }
+            pids.push(pid);
+        }
+        unsafe { libc::close(gate_read) };
+
+        if let Some(session) = &session {
+            for (group, (spec, workers)) in session.groups().iter().zip(&self.groups) {
+                for &worker in workers {
+                    if let Err(e) = group.add_process(pids[worker]) {
+                        abort(&pids);
+                        unsafe { libc::close(gate_write) };
+                        return Err(io::Error::new(e.kind(), format!("Failed to move worker {} into {}: {}", worker, spec.name, e)));
+                    }
+                }
+            }
+        }
+        // Workers nobody configured keep the caller's settings, which an
+        // unprivileged caller could not set back if they were raised
+        for (worker, (sched, &pid)) in self.workers.iter().zip(&pids).enumerate() {
+            let Some(sched) = sched else {
+                continue;
+            };
+            if let Err(e) = sched.apply(pid) {
+                abort(&pids);
+                unsafe { libc::close(gate_write) };
+                return Err(io::Error::new(e.kind(), format!("Failed to set {} for worker {}: {}", sched.label(), worker, e)));
+            }
+        }
+
+        let start_ms = base.elapsed().as_secs_f64() * 1000.0;
+        unsafe { libc::close(gate_write) };
+
+        let mut failed = None;
+        let mut ctxt_switches = Vec::new();
+        for (worker, &pid) in pids.iter().enumerate() {
+            let (switches, status) = match reap(pid) {
+                Ok(reaped) => reaped,
+                Err(e) => {
+                    abort(&pids[worker..]);
+                    return Err(io::Error::new(e.kind(), format!("Failed to wait for worker {}: {}", worker, e)));
+                }
+            };
+            ctxt_switches.push(switches);
+            if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+                failed.get_or_insert(worker);
+            }
+        }
+        if let Some(worker) = failed {
+            return Err(io::Error::other(format!("Worker {} did not exit normally", worker)));
+        }
+
+        let cpu_stats = match &session {
+            Some(session) => session.groups().iter().map(|group| group.cpu_stat()).collect::<io::Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let traces = pids.iter()
+            .enumerate()
+            .map(|(worker, &pid)| Trace {
+                worker,
+                pid,
+                times_ms: (0..self.units).map(|unit| times.get(worker * self.units + unit) - start_ms).collect(),
+                ctxt_switches: ctxt_switches[worker],
+            })
+            .collect();
+        Ok(Outcome { loops_per_msec, calibration, traces, cpu_stats })
+    }
+}
@@ -0,0 +1,11 @@
+//! Building blocks for the chapter 3 process scheduler experiments.
+
+pub mod affinity;
+pub mod calibrate;
+pub mod cgroup;
+pub mod cpufreq;
+pub mod experiment;
+pub mod plot;
+pub mod policy;
+pub mod timeline;
+pub mod trace;
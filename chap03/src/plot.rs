@@ -0,0 +1,138 @@
+//! Plots of the progress traces and of the timelines reconstructed from them.
+
+use crate::timeline::Timeline;
+use crate::trace::Trace;
+use plotters::prelude::*;
+
+/// Colors of the workers, in worker order
+pub const COLORS: [RGBColor; 6] = [RED, BLUE, GREEN, MAGENTA, CYAN, YELLOW];
+
+/// Scatter plot of the progress of every trace over time, labelled with
+/// `labels` in the legend
+pub fn plot_progress(path: &str, caption: &str, traces: &[Trace], labels: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, (800, 600)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let max_x = traces.iter().map(Trace::end_ms).fold(0.0, f64::max);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption, ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0.0..max_x, 0.0..100.0)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Elapsed Time [ms]")
+        .y_desc("Progress [%]")
+        .draw()?;
+
+    for (i, (trace, label)) in traces.iter().zip(labels).enumerate() {
+        let color = COLORS[i % COLORS.len()];
+
+        chart
+            .draw_series(PointSeries::of_element(
+                trace.progress(),
+                1,
+                color,
+                &|coord, size, style| {
+                    EmptyElement::at(coord) + Circle::new((0, 0), size, style.filled())
+                },
+            ))?
+            .label(label)
+            .legend(move |(x, y)| Circle::new((x, y), 3, color.filled()));
+    }
+
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+/// Line plot of the progress of every series over time, e.g. one per group
+pub fn plot_series_progress(path: &str, caption: &str, series: &[(String, Vec<(f64, f64)>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, (800, 600)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let max_x = series.iter().filter_map(|(_, points)| points.last()).map(|&(x, _)| x).fold(0.0, f64::max);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption, ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0.0..max_x, 0.0..100.0)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Elapsed Time [ms]")
+        .y_desc("Progress [%]")
+        .draw()?;
+
+    for (i, (label, points)) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), color.stroke_width(2)))?
+            .label(label)
+            .legend(move |(x, y)| PathElement::new(vec![(x - 10, y), (x + 10, y)], color.stroke_width(2)));
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+/// Gantt chart of when each worker ran, one row per timeline labelled with
+/// `labels`, from the first worker at the top
+pub fn plot_timeline(path: &str, caption: &str, timelines: &[Timeline], labels: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, (1024, 600)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let max_x = timelines.iter().filter_map(|timeline| timeline.slices.last()).map(|slice| slice.end_ms).fold(0.0, f64::max);
+    let rows = timelines.len();
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption, ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(180)
+        .build_cartesian_2d(0.0..max_x, 0.0..rows as f64)?;
+
+    // Row i spans [rows - i - 1, rows - i); its label sits in the middle
+    let row_label = |y: &f64| {
+        let row = rows as f64 - y - 0.5;
+        if row >= 0.0 && (row - row.round()).abs() < 1e-6 {
+            labels.get(row.round() as usize).cloned().unwrap_or_default()
+        } else {
+            String::new()
+        }
+    };
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(2 * rows + 1)
+        .y_label_formatter(&row_label)
+        .x_desc("Elapsed Time [ms]")
+        .draw()?;
+
+    for (i, timeline) in timelines.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let y = (rows - i - 1) as f64;
+        chart.draw_series(timeline.slices.iter().map(|slice| {
+            Rectangle::new([(slice.start_ms, y + 0.15), (slice.end_ms, y + 0.85)], color.filled())
+        }))?;
+    }
+
+    root.present()?;
+    Ok(())
+}
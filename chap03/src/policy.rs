@@ -0,0 +1,303 @@
+//! Scheduling policy and nice value of a worker.
+//!
+//! Every policy is set with sched_setattr(2), the only interface to
+//! SCHED_DEADLINE. The fair policies are open to any user, and so is raising
+//! the nice value. The real-time policies need CAP_SYS_NICE, or an
+//! RLIMIT_RTPRIO at least as high as the priority, and SCHED_DEADLINE always
+//! needs CAP_SYS_NICE. The kernel answers a missing privilege with a bare
+//! EPERM, so `Sched::apply` adds what the policy needs to the error.
+//!
+//! The SCHED_RR timeslice is a sysctl of the whole system. `RrTimeslice`
+//! changes it for as long as it lives, and `install_signal_handler` makes
+//! SIGINT and SIGTERM put it back before exiting.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// SCHED_RR timeslice of the whole system
+const RR_TIMESLICE: &str = "/proc/sys/kernel/sched_rr_timeslice_ms";
+
+/// Highest priority of SCHED_FIFO and SCHED_RR
+pub const MAX_RT_PRIORITY: u32 = 99;
+
+/// How the scheduler picks a worker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// SCHED_OTHER, the default fair policy
+    #[default]
+    Other,
+    /// SCHED_BATCH: never preempts a waking task, for CPU bound work
+    Batch,
+    /// SCHED_IDLE: only gets the CPU that other tasks leave
+    Idle,
+    /// SCHED_FIFO: runs until it blocks or a higher priority task wakes up
+    Fifo { priority: u32 },
+    /// SCHED_RR: like SCHED_FIFO, but yields to tasks of the same priority
+    /// after each timeslice
+    Rr { priority: u32 },
+    /// SCHED_DEADLINE: gets `runtime` of CPU within `deadline` of the start
+    /// of every `period`, ahead of every other policy
+    Deadline { runtime: Duration, deadline: Duration, period: Duration },
+}
+
+impl Policy {
+    /// Parse "other", "batch", "idle", "fifo:<priority>", "rr:<priority>"
+    /// or "deadline:<runtime>/<deadline>/<period>" in milliseconds
+    pub fn parse(spec: &str) -> Option<Policy> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let priority = || params.parse().ok().filter(|priority| (1..=MAX_RT_PRIORITY).contains(priority));
+        match (name, params) {
+            ("other", "") => Some(Policy::Other),
+            ("batch", "") => Some(Policy::Batch),
+            ("idle", "") => Some(Policy::Idle),
+            ("fifo", _) => Some(Policy::Fifo { priority: priority()? }),
+            ("rr", _) => Some(Policy::Rr { priority: priority()? }),
+            ("deadline", _) => {
+                let times = params.split('/')
+                    .map(|ms| ms.parse::<f64>().ok().filter(|&ms| ms > 0.0).map(|ms| Duration::from_secs_f64(ms / 1000.0)))
+                    .collect::<Option<Vec<_>>>()?;
+                match times[..] {
+                    [runtime, deadline, period] => Some(Policy::Deadline { runtime, deadline, period }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Policy::Other => "other",
+            Policy::Batch => "batch",
+            Policy::Idle => "idle",
+            Policy::Fifo { .. } => "fifo",
+            Policy::Rr { .. } => "rr",
+            Policy::Deadline { .. } => "deadline",
+        }
+    }
+
+    fn value(&self) -> libc::c_int {
+        match self {
+            Policy::Other => libc::SCHED_OTHER,
+            Policy::Batch => libc::SCHED_BATCH,
+            Policy::Idle => libc::SCHED_IDLE,
+            Policy::Fifo { .. } => libc::SCHED_FIFO,
+            Policy::Rr { .. } => libc::SCHED_RR,
+            Policy::Deadline { .. } => libc::SCHED_DEADLINE,
+        }
+    }
+}
+
+/// How a worker is scheduled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sched {
+    pub policy: Policy,
+    /// -20 (highest weight) to 19, for SCHED_OTHER and SCHED_BATCH;
+    /// lowering it below 0 needs CAP_SYS_NICE or RLIMIT_NICE
+    pub nice: i32,
+}
+
+impl Sched {
+    /// Parse a policy as `Policy::parse`, followed by ",nice=<n>" for the
+    /// fair policies, e.g. "batch,nice=5"
+    pub fn parse(spec: &str) -> Option<Sched> {
+        let (policy, nice) = match spec.split_once(",nice=") {
+            Some((policy, nice)) => (policy, nice.parse().ok().filter(|nice| (-20..=19).contains(nice))?),
+            None => (spec, 0),
+        };
+        let policy = Policy::parse(policy)?;
+        if nice != 0 && !matches!(policy, Policy::Other | Policy::Batch) {
+            return None;
+        }
+        Some(Sched { policy, nice })
+    }
+
+    /// Short description such as "nice=5", "batch, nice=0" or "fifo 50"
+    pub fn label(&self) -> String {
+        match self.policy {
+            Policy::Other => format!("nice={}", self.nice),
+            Policy::Batch => format!("batch, nice={}", self.nice),
+            Policy::Idle => "idle".to_string(),
+            Policy::Fifo { priority } | Policy::Rr { priority } => format!("{} {}", self.policy.name(), priority),
+            Policy::Deadline { runtime, deadline, period } => format!(
+                "deadline {}/{}/{} ms",
+                runtime.as_secs_f64() * 1000.0,
+                deadline.as_secs_f64() * 1000.0,
+                period.as_secs_f64() * 1000.0,
+            ),
+        }
+    }
+
+    /// Apply to the process `pid`
+    pub fn apply(&self, pid: libc::pid_t) -> io::Result<()> {
+        let mut attr: libc::sched_attr = unsafe { std::mem::zeroed() };
+        attr.size = size_of::<libc::sched_attr>() as u32;
+        attr.sched_policy = self.policy.value() as u32;
+        attr.sched_nice = self.nice;
+        match self.policy {
+            Policy::Fifo { priority } | Policy::Rr { priority } => attr.sched_priority = priority,
+            Policy::Deadline { runtime, deadline, period } => {
+                attr.sched_runtime = runtime.as_nanos() as u64;
+                attr.sched_deadline = deadline.as_nanos() as u64;
+                attr.sched_period = period.as_nanos() as u64;
+            }
+            Policy::Other | Policy::Batch | Policy::Idle => {}
+        }
+        if unsafe { libc::syscall(libc::SYS_sched_setattr, pid, &attr as *const libc::sched_attr, 0) } < 0 {
+            let e = io::Error::last_os_error();
+            return Err(match self.hint(&e) {
+                Some(hint) => io::Error::new(e.kind(), format!("{}. {}", e, hint)),
+                None => e,
+            });
+        }
+        Ok(())
+    }
+
+    /// Why the kernel may have refused this policy with `e`
+    fn hint(&self, e: &io::Error) -> Option<String> {
+        match (self.policy, e.raw_os_error()?) {
+            (Policy::Fifo { priority } | Policy::Rr { priority }, libc::EPERM) => Some(format!(
+                "SCHED_FIFO and SCHED_RR need CAP_SYS_NICE, or RLIMIT_RTPRIO of at least {} \
+                 (soft limit now {}, see `ulimit -r` and /etc/security/limits.conf). \
+                 In a container, root may lack CAP_SYS_NICE or real-time runtime in its cgroup",
+                priority,
+                rlimit_label(libc::RLIMIT_RTPRIO),
+            )),
+            (Policy::Fifo { .. } | Policy::Rr { .. }, libc::EINVAL) => {
+                Some(format!("Real-time priorities go from 1 to {}", MAX_RT_PRIORITY))
+            }
+            (Policy::Deadline { .. }, libc::EPERM) => Some(
+                "SCHED_DEADLINE needs CAP_SYS_NICE, whatever RLIMIT_RTPRIO allows, and workers \
+                 allowed on every CPU of their root domain, i.e. no restricted CPU list"
+                    .to_string(),
+            ),
+            (Policy::Deadline { .. }, libc::EBUSY) => Some(
+                "Admission control refused the bandwidth: all deadline tasks together may \
+                 reserve at most sched_rt_runtime_us per sched_rt_period_us of each CPU"
+                    .to_string(),
+            ),
+            (Policy::Deadline { .. }, libc::EINVAL) => Some(
+                "SCHED_DEADLINE needs runtime <= deadline <= period and a runtime of at least 1024 ns".to_string(),
+            ),
+            (Policy::Other | Policy::Batch, libc::EPERM | libc::EACCES) if self.nice < 0 => Some(format!(
+                "A negative nice value needs CAP_SYS_NICE, or RLIMIT_NICE of at least {} (soft limit now {})",
+                20 - self.nice,
+                rlimit_label(libc::RLIMIT_NICE),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Soft limit of `resource` for messages, "unlimited" or a number
+fn rlimit_label(resource: libc::__rlimit_resource_t) -> String {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(resource, &mut limit) } < 0 {
+        return "unknown".to_string();
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        limit.rlim_cur.to_string()
+    }
+}
+
+/// SCHED_RR timeslice, from /proc/sys/kernel/sched_rr_timeslice_ms
+pub fn rr_timeslice() -> io::Result<Duration> {
+    let ms: u64 = fs::read_to_string(RR_TIMESLICE)?
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid {}", RR_TIMESLICE)))?;
+    Ok(Duration::from_millis(ms))
+}
+
+/// Set the SCHED_RR timeslice of the whole system, rounded to jiffies by
+/// the kernel; needs root
+pub fn set_rr_timeslice(timeslice: Duration) -> io::Result<()> {
+    fs::write(RR_TIMESLICE, timeslice.as_millis().max(1).to_string())
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to write {}: {}", RR_TIMESLICE, e)))
+}
+
+/// Timeslice to put back, shared with the signal handling thread
+static SAVED_TIMESLICE: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Put back the saved timeslice, if nobody did yet
+fn restore_saved_timeslice() -> io::Result<()> {
+    let saved = SAVED_TIMESLICE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    saved.map_or(Ok(()), set_rr_timeslice)
+}
+
+/// The SCHED_RR timeslice changed for as long as the guard lives
+pub struct RrTimeslice(());
+
+impl RrTimeslice {
+    /// Save the current timeslice and set `timeslice`
+    pub fn set(timeslice: Duration) -> io::Result<RrTimeslice> {
+        let saved = rr_timeslice()?;
+        // Saved first, so that a signal arriving in between puts it back
+        *SAVED_TIMESLICE.lock().unwrap_or_else(|e| e.into_inner()) = Some(saved);
+        if let Err(e) = set_rr_timeslice(timeslice) {
+            SAVED_TIMESLICE.lock().unwrap_or_else(|e| e.into_inner()).take();
+            return Err(e);
+        }
+        Ok(RrTimeslice(()))
+    }
+
+    /// Put back the saved timeslice now and report a failure
+    pub fn restore(self) -> io::Result<()> {
+        restore_saved_timeslice()
+    }
+}
+
+impl Drop for RrTimeslice {
+    fn drop(&mut self) {
+        if let Err(e) = restore_saved_timeslice() {
+            eprintln!("Failed to restore the SCHED_RR timeslice: {}", e);
+        }
+    }
+}
+
+/// Put back a timeslice changed by `RrTimeslice` on SIGINT/SIGTERM, then exit.
+///
+/// Call this before spawning other threads: the signals are blocked in the
+/// calling thread (and inherited by later threads) and handled by a
+/// dedicated thread with sigwait. Workers unblock them again after fork.
+pub fn install_signal_handler() -> io::Result<()> {
+    let set = termination_signals();
+    let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+
+    thread::spawn(move || {
+        let mut sig: libc::c_int = 0;
+        while unsafe { libc::sigwait(&set, &mut sig) } != 0 {}
+
+        // Keep the lock until exit so no guard restores concurrently
+        let mut saved = SAVED_TIMESLICE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(timeslice) = saved.take() {
+            eprintln!("\nCaught signal {}, restoring the SCHED_RR timeslice of {} ms...", sig, timeslice.as_millis());
+            if let Err(e) = set_rr_timeslice(timeslice) {
+                eprintln!("{}", e);
+            }
+        }
+        std::process::exit(128 + sig);
+    });
+
+    Ok(())
+}
+
+/// SIGINT and SIGTERM, as handled by `install_signal_handler`
+pub(crate) fn termination_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        set
+    }
+}
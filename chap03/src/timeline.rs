@@ -0,0 +1,170 @@
+//! Running and preempted periods of a worker, reconstructed from its trace.
+//!
+//! While a worker runs, its timestamps follow each other one work unit
+//! apart. A gap much longer than a unit is time the worker spent off the
+//! CPU, so every gap ends one running period, i.e. one observed timeslice,
+//! and the next period starts with the context switch back. Interrupts also
+//! stretch a unit a little, so only gaps longer than a few units and than a
+//! minimum count as preemption.
+
+use crate::trace::Trace;
+
+/// Shortest gap taken as preemption unless set otherwise
+pub const DEFAULT_MIN_GAP_MS: f64 = 0.05;
+/// Gaps of up to this many units are taken as the worker still running
+const GAP_UNITS: f64 = 3.0;
+
+/// A period during which the worker ran without interruption
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slice {
+    pub start_ms: f64,
+    pub end_ms: f64,
+}
+
+impl Slice {
+    pub fn len_ms(&self) -> f64 {
+        self.end_ms - self.start_ms
+    }
+}
+
+/// When a worker ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    pub worker: usize,
+    /// Typical time of one work unit while running, the median gap
+    pub unit_ms: f64,
+    pub slices: Vec<Slice>,
+}
+
+/// Median of `values`, 0 without any
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    match sorted.len() {
+        0 => 0.0,
+        n if n % 2 == 1 => sorted[n / 2],
+        n => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0,
+    }
+}
+
+impl Timeline {
+    /// Split `trace` into running periods at every gap longer than
+    /// `min_gap_ms` and a few units
+    pub fn from_trace(trace: &Trace, min_gap_ms: f64) -> Timeline {
+        let times = &trace.times_ms;
+        let gaps: Vec<f64> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let unit_ms = if gaps.is_empty() { times.first().copied().unwrap_or(0.0) } else { median(&gaps) };
+        let threshold = f64::max(GAP_UNITS * unit_ms, min_gap_ms);
+
+        let mut slices = Vec::new();
+        let Some(&first) = times.first() else {
+            return Timeline { worker: trace.worker, unit_ms, slices };
+        };
+        // A unit started one unit before its timestamp
+        let mut slice = Slice { start_ms: f64::max(first - unit_ms, 0.0), end_ms: first };
+        for pair in times.windows(2) {
+            if pair[1] - pair[0] > threshold {
+                slices.push(slice);
+                slice = Slice { start_ms: pair[1] - unit_ms, end_ms: pair[1] };
+            } else {
+                slice.end_ms = pair[1];
+            }
+        }
+        slices.push(slice);
+        Timeline { worker: trace.worker, unit_ms, slices }
+    }
+
+    /// Total time the worker ran
+    pub fn running_ms(&self) -> f64 {
+        self.slices.iter().map(Slice::len_ms).sum()
+    }
+
+    /// Times the worker was switched out before it finished
+    pub fn switches(&self) -> usize {
+        self.slices.len().saturating_sub(1)
+    }
+
+    /// Fraction of `window_ms` during which the worker ran
+    pub fn cpu_share(&self, window_ms: f64) -> f64 {
+        if window_ms > 0.0 { self.running_ms() / window_ms } else { 0.0 }
+    }
+
+    /// Shortest, median and longest timeslice, excluding the last one, which
+    /// ends with the work rather than with a preemption; `None` if the worker
+    /// was never preempted
+    pub fn timeslices_ms(&self) -> Option<(f64, f64, f64)> {
+        let lens: Vec<f64> = self.slices[..self.slices.len().saturating_sub(1)].iter().map(Slice::len_ms).collect();
+        if lens.is_empty() {
+            return None;
+        }
+        let min = lens.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = lens.iter().copied().fold(0.0, f64::max);
+        Some((min, median(&lens), max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(times_ms: &[f64]) -> Trace {
+        Trace { worker: 3, times_ms: times_ms.to_vec(), ..Trace::default() }
+    }
+
+    /// Units of 1 ms, preempted after 3 ms and 2 ms of running
+    const PREEMPTED: [f64; 9] = [1.0, 2.0, 3.0, 10.0, 11.0, 20.0, 21.0, 22.0, 23.0];
+
+    #[test]
+    fn gaps_split_the_slices() {
+        let timeline = Timeline::from_trace(&trace(&PREEMPTED), DEFAULT_MIN_GAP_MS);
+        assert_eq!(timeline.worker, 3);
+        assert_eq!(timeline.unit_ms, 1.0);
+        assert_eq!(
+            timeline.slices,
+            [
+                Slice { start_ms: 0.0, end_ms: 3.0 },
+                Slice { start_ms: 9.0, end_ms: 11.0 },
+                Slice { start_ms: 19.0, end_ms: 23.0 },
+            ]
+        );
+        assert_eq!(timeline.switches(), 2);
+        assert_eq!(timeline.running_ms(), 9.0);
+        assert_eq!(timeline.cpu_share(18.0), 0.5);
+    }
+
+    #[test]
+    fn short_gaps_are_not_preemption() {
+        // Gaps of up to three units, or below the minimum gap, keep the slice running
+        let timeline = Timeline::from_trace(&trace(&[1.0, 2.0, 5.0, 6.0]), DEFAULT_MIN_GAP_MS);
+        assert_eq!(timeline.slices, [Slice { start_ms: 0.0, end_ms: 6.0 }]);
+
+        let timeline = Timeline::from_trace(&trace(&PREEMPTED), 10.0);
+        assert_eq!(timeline.slices.len(), 1);
+        assert_eq!(timeline.timeslices_ms(), None);
+    }
+
+    #[test]
+    fn first_slice_starts_one_unit_before_its_timestamp() {
+        let timeline = Timeline::from_trace(&trace(&[4.0, 5.0, 6.0]), DEFAULT_MIN_GAP_MS);
+        assert_eq!(timeline.slices[0].start_ms, 3.0);
+
+        // ...but not before the workers started
+        let timeline = Timeline::from_trace(&trace(&[0.5, 1.5, 2.5]), DEFAULT_MIN_GAP_MS);
+        assert_eq!(timeline.slices[0].start_ms, 0.0);
+    }
+
+    #[test]
+    fn timeslices_exclude_the_last_slice() {
+        let timeline = Timeline::from_trace(&trace(&PREEMPTED), DEFAULT_MIN_GAP_MS);
+        // The last slice (4 ms) ends with the work, so only 3 ms and 2 ms count
+        assert_eq!(timeline.timeslices_ms(), Some((2.0, 2.5, 3.0)));
+    }
+
+    #[test]
+    fn empty_trace_has_no_slices() {
+        let timeline = Timeline::from_trace(&trace(&[]), DEFAULT_MIN_GAP_MS);
+        assert!(timeline.slices.is_empty());
+        assert_eq!(timeline.switches(), 0);
+        assert_eq!(timeline.timeslices_ms(), None);
+    }
+}
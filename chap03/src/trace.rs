@@ -0,0 +1,132 @@
+//! Progress traces of the workers.
+//!
+//! A worker does its work in equal units and records the time it finished
+//! each of them, so that the trace only advances while the worker runs: a
+//! gap between two timestamps is time it spent preempted.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+
+/// Context switches the kernel counted for a process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CtxtSwitches {
+    /// Switches because the process blocked or yielded
+    pub voluntary: u64,
+    /// Switches because the process was preempted
+    pub nonvoluntary: u64,
+}
+
+impl CtxtSwitches {
+    /// Parse the voluntary_ctxt_switches and nonvoluntary_ctxt_switches
+    /// lines of /proc/<pid>/status
+    pub fn parse(status: &str) -> Option<CtxtSwitches> {
+        let field = |name: &str| {
+            status.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.trim().parse().ok())
+        };
+        Some(CtxtSwitches { voluntary: field("voluntary_ctxt_switches")?, nonvoluntary: field("nonvoluntary_ctxt_switches")? })
+    }
+
+    /// Read /proc/<pid>/status, which a zombie still has
+    pub fn read(pid: i32) -> io::Result<CtxtSwitches> {
+        let path = format!("/proc/{}/status", pid);
+        CtxtSwitches::parse(&fs::read_to_string(&path)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No context switches in {}", path)))
+    }
+}
+
+/// Progress of one worker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub worker: usize,
+    pub pid: i32,
+    /// Time each work unit finished, in milliseconds since the workers started
+    pub times_ms: Vec<f64>,
+    /// Context switches of the worker when it exited, if they could be read
+    pub ctxt_switches: Option<CtxtSwitches>,
+}
+
+impl Trace {
+    /// Time the last unit finished
+    pub fn end_ms(&self) -> f64 {
+        self.times_ms.last().copied().unwrap_or(0.0)
+    }
+
+    /// (elapsed time [ms], progress [%]) of every unit
+    pub fn progress(&self) -> Vec<(f64, f64)> {
+        let units = self.times_ms.len() as f64;
+        self.times_ms.iter().enumerate().map(|(i, &ms)| (ms, (i + 1) as f64 * 100.0 / units)).collect()
+    }
+
+    /// Write "<elapsed ms>\t<unit>" lines, the format of the `<id>.data` files
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        for (i, &elapsed_ms) in self.times_ms.iter().enumerate() {
+            writeln!(file, "{}\t{}", elapsed_ms, i)?;
+        }
+        Ok(())
+    }
+
+    /// Read a trace written by `save`
+    pub fn load(worker: usize, path: &str) -> io::Result<Trace> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut times_ms = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(ms) = line.split('\t').next().and_then(|ms| ms.parse().ok()) {
+                times_ms.push(ms);
+            }
+        }
+        Ok(Trace { worker, pid: 0, times_ms, ctxt_switches: None })
+    }
+}
+
+/// (elapsed time [ms], progress [%]) of the work of `traces` together, e.g.
+/// of the workers of one group
+pub fn combined_progress(traces: &[&Trace]) -> Vec<(f64, f64)> {
+    let mut times: Vec<f64> = traces.iter().flat_map(|trace| trace.times_ms.iter().copied()).collect();
+    times.sort_by(f64::total_cmp);
+    let units = times.len() as f64;
+    times.iter().enumerate().map(|(i, &ms)| (ms, (i + 1) as f64 * 100.0 / units)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "Name:\t03_sched\nState:\tZ (zombie)\nTgid:\t4242\n\
+        voluntary_ctxt_switches:\t3\nnonvoluntary_ctxt_switches:\t117\n";
+
+    #[test]
+    fn ctxt_switches_are_parsed_from_status() {
+        assert_eq!(CtxtSwitches::parse(STATUS), Some(CtxtSwitches { voluntary: 3, nonvoluntary: 117 }));
+        assert_eq!(CtxtSwitches::parse("Name:\tx\nvoluntary_ctxt_switches:\t3\n"), None);
+        assert_eq!(CtxtSwitches::parse("voluntary_ctxt_switches:\tmany\nnonvoluntary_ctxt_switches:\t1\n"), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1.data");
+        let path = path.to_str().unwrap();
+        let trace = Trace { worker: 1, pid: 4242, times_ms: vec![0.5, 1.25, 3.0, 10.125], ctxt_switches: None };
+        trace.save(path).unwrap();
+
+        let loaded = Trace::load(1, path).unwrap();
+        assert_eq!(loaded, Trace { pid: 0, ..trace });
+        assert_eq!(fs::read_to_string(path).unwrap().lines().next(), Some("0.5\t0"));
+    }
+
+    #[test]
+    fn progress_counts_units() {
+        let trace = Trace { times_ms: vec![1.0, 2.0, 4.0, 8.0], ..Trace::default() };
+        assert_eq!(trace.progress(), [(1.0, 25.0), (2.0, 50.0), (4.0, 75.0), (8.0, 100.0)]);
+        assert_eq!(trace.end_ms(), 8.0);
+
+        let other = Trace { times_ms: vec![3.0, 5.0], ..Trace::default() };
+        let combined = combined_progress(&[&trace, &other]);
+        assert_eq!(combined.len(), 6);
+        assert_eq!(combined[2], (3.0, 50.0));
+    }
+}
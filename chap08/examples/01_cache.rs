@@ -1,135 +1,603 @@
+use std::env;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::time::Instant;
-use std::hint::black_box;
+use std::time::Duration;
 use plotters::prelude::*;
+use chap08::affinity;
+use chap08::bandwidth;
+use chap08::buffer::{Buffer, PageMode};
+use chap08::chase;
+use chap08::chase::Timing;
+use chap08::levels::{self, Knee};
+use chap08::numa::{self, Node};
+use chap08::perf::Counters;
+use chap08::topology::{self, format_size, Cache, CACHE_LINE_SIZE};
 
-const CACHE_LINE_SIZE: usize = 64;
+const PAGE_SIZE: usize = 4096;
 const NACCESS: usize  = 128 * 1024 * 1024; // 128 MiB
+/// Smallest buffer swept, 2^2 KiB
+const MIN_LOG2_KIB: f64 = 2.0;
+/// Largest buffer swept by default, 2^16 KiB, unless the caches are bigger
+const DEFAULT_MAX_LOG2_KIB: f64 = 16.0;
+/// Dependent loads timed per point, scaled with the number of slots
+const MIN_LOADS: usize = 1 << 20;
+const MAX_LOADS: usize = 1 << 23;
+/// Smallest buffer of the NUMA matrix, unless the caches are bigger
+const MIN_NUMA_SIZE: usize = 256 << 20;
+/// Time spent reading the buffer for each bandwidth cell
+const NUMA_SECONDS: f64 = 1.0;
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--max-size MIB] [--strides LIST] [--pages LIST] [--counters]", prog_name);
+    eprintln!("       {} numa [--size MIB] [--pages MODE]", prog_name);
+    eprintln!();
+    eprintln!("  Sweep buffer sizes from 4 KiB up, timing a sequential sweep and pointer chasing");
+    eprintln!("  at each stride, detect the cache levels and compare them with sysfs.");
+    eprintln!("  Each page mode repeats the sweep with buffers backed by other pages.");
+    eprintln!();
+    eprintln!("  numa measures latency and read bandwidth from the CPUs of each NUMA node to a");
+    eprintln!("  buffer bound to each node, as a local vs remote matrix.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --max-size MIB: Largest buffer (default: 64 MiB, or twice the largest cache)");
+    eprintln!("  --strides LIST: Pointer chasing strides in bytes (default: {},{},{})", CACHE_LINE_SIZE, 2 * CACHE_LINE_SIZE, PAGE_SIZE);
+    eprintln!("  --pages LIST: Pages backing the buffers out of base, thp and hugetlb (default: all)");
+    eprintln!("                hugetlb needs a pool reserved in /proc/sys/vm/nr_hugepages");
+    eprintln!("                numa uses the first one");
+    eprintln!("  --counters: Also record hardware counters per access in out.txt, when perf_event_open allows");
+    eprintln!("  --size MIB: Buffer of the NUMA matrix (default: 256 MiB, or twice the largest cache)");
+    std::process::exit(1);
+}
+
+/// One curve of the sweep
+struct Series {
+    label: String,
+    /// Bytes between two accesses
+    stride: usize,
+    /// Buffer size and ns per access
+    points: Vec<(usize, f64)>,
+}
+
+/// Curves of one page mode
+struct Sweep {
+    pages: PageMode,
+    sequential: Series,
+    chases: Vec<Series>,
+    /// Buffer size and bytes of it backed by huge pages
+    huge: Vec<(usize, usize)>,
+}
+
+impl Sweep {
+    fn new(pages: PageMode, strides: &[usize]) -> Sweep {
+        let series = |pattern: &str, stride: usize| Series {
+            label: format!("{}, {}, {} B stride", pages.name(), pattern, stride),
+            stride,
+            points: Vec::new(),
+        };
+        Sweep {
+            pages,
+            sequential: series("sequential", CACHE_LINE_SIZE),
+            chases: strides.iter().map(|&stride| series("pointer chase", stride)).collect(),
+            huge: Vec::new(),
+        }
+    }
+}
+
+/// Time `f`, counting hardware events around it when counters are given;
+/// events per access, None for those that could not be read
+fn measure(counters: Option<&Counters>, f: impl FnOnce() -> Timing) -> (Timing, Vec<Option<f64>>) {
+    let Some(counters) = counters else {
+        return (f(), Vec::new());
+    };
+    let started = counters.start().is_ok();
+    let timing = f();
+    let values = match counters.stop() {
+        Ok(values) if started => values.into_iter()
+            .map(|(_, value)| value.map(|value| value as f64 / timing.accesses as f64))
+            .collect(),
+        _ => vec![None; counters.events().len()],
+    };
+    (timing, values)
+}
+
+fn log2_kib(size: usize) -> f64 {
+    (size as f64 / 1024.0).log2()
+}
+
+/// Name the level ending at the `index`th knee after the sysfs data caches
+fn level_name(caches: &[&Cache], index: usize) -> String {
+    caches.get(index).map_or_else(|| format!("level {}", index + 1), |cache| cache.name())
+}
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut max_size = None;
+    let mut strides = vec![CACHE_LINE_SIZE, 2 * CACHE_LINE_SIZE, PAGE_SIZE];
+    let mut pages = PageMode::ALL.to_vec();
+    let mut use_counters = false;
+    let numa = args.get(1).is_some_and(|arg| arg == "numa");
+    let mut numa_size = None;
+    let mut rest = args[if numa { 2 } else { 1 }..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--size" if numa => {
+                numa_size = rest.next()
+                    .and_then(|mib| mib.parse::<usize>().ok())
+                    .filter(|&mib| mib > 0)
+                    .map(|mib| mib << 20)
+                    .or_else(|| usage(prog_name))
+            }
+            "--max-size" if !numa => {
+                max_size = rest.next()
+                    .and_then(|mib| mib.parse::<usize>().ok())
+                    .filter(|&mib| mib > 0)
+                    .map(|mib| mib << 20)
+                    .or_else(|| usage(prog_name))
+            }
+            "--strides" if !numa => {
+                strides = rest.next()
+                    .and_then(|list| list.split(',').map(|s| s.parse().ok()).collect::<Option<Vec<usize>>>())
+                    .filter(|list| list.iter().all(|&s| s >= size_of::<usize>()))
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--pages" => {
+                pages = rest.next()
+                    .and_then(|list| list.split(',').map(PageMode::parse).collect::<Option<Vec<_>>>())
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--counters" if !numa => use_counters = true,
+            _ => usage(prog_name),
+        }
+    }
+
+    let caches = topology::caches(0).unwrap_or_else(|e| {
+        eprintln!("Failed to read the cache hierarchy from sysfs: {}", e);
+        Vec::new()
+    });
+    let data_caches: Vec<&Cache> = caches.iter().filter(|cache| cache.holds_data()).collect();
+    for cache in &caches {
+        println!("{}: {} ({}-byte lines)", cache.name(), format_size(cache.size), cache.line_size);
+    }
+
+    // Go far enough past the last level cache to see the DRAM plateau
+    let largest = data_caches.iter().map(|cache| cache.size).max().unwrap_or(0);
+    if numa {
+        // One page mode for the whole matrix, base pages unless asked otherwise
+        let pages = pages.first().copied().unwrap_or(PageMode::Base);
+        run_numa(numa_size.unwrap_or(MIN_NUMA_SIZE.max(2 * largest)), pages);
+        return;
+    }
+    let max_log2_kib = match max_size {
+        Some(size) => log2_kib(size),
+        None => DEFAULT_MAX_LOG2_KIB.max(log2_kib(2 * largest).ceil()),
+    };
+
+    // Counters are optional: without them (VMs without a virtual PMU,
+    // perf_event_paranoid above 2) only timing is recorded
+    let counters = use_counters.then(Counters::open);
+    if let Some(counters) = &counters {
+        for (event, e) in &counters.unavailable {
+            println!("Counter {} unavailable: {}", event.name(), e);
+        }
+        if counters.is_empty() {
+            println!("No hardware counters available; recording timing only");
+        }
+    }
+    let counters = counters.filter(|counters| !counters.is_empty());
+    let events = counters.as_ref().map(Counters::events).unwrap_or_default();
+
     // Remove old output file
     let _ = std::fs::remove_file("out.txt");
 
     // Open output file
     let mut file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open("out.txt")
         .expect("Failed to open out.txt");
+    let counter_columns: String = events.iter().map(|event| format!("\t{}_per_access", event.name())).collect();
+    writeln!(file, "# log2_kib\tpages\tpattern\tstride\tns_per_access\taccess_per_ns\thuge_kib{}", counter_columns)
+        .expect("Failed to write to file");
 
-    let mut results: Vec<(f64, f64)> = Vec::new();
+    let mut sweeps: Vec<Sweep> = pages.iter().map(|&pages| Sweep::new(pages, &strides)).collect();
 
-    // Test different buffer sizes from 2^2 to 2^16 KB (4KB to 64MB)
-    let mut i = 2.0;
-    while i <= 16.0 {
+    // Test different buffer sizes, 4 per octave, every page mode in turn
+    let mut i = MIN_LOG2_KIB;
+    while i <= max_log2_kib + 1e-9 {
         let buf_size = (2.0_f64.powf(i) * 1024.0) as usize;
+        println!("Buffer size 2^{:.2}({}) KB:", i, buf_size / 1024);
+
+        for sweep in sweeps.iter_mut() {
+            // Allocate buffer using mmap (anonymous mapping)
+            let buf = match Buffer::with_pages(buf_size, sweep.pages) {
+                Ok(buf) => buf,
+                // The hugetlb pool may be too small for the larger buffers
+                Err(e) if sweep.pages == PageMode::Hugetlb => {
+                    println!("  {:<8} mmap(MAP_HUGETLB) failed: {}", sweep.pages.name(), e);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("mmap() failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            print!("  {:<8}", sweep.pages.name());
+
+            let (timing, values) = measure(counters.as_ref(), || chase::sequential(&buf, CACHE_LINE_SIZE, NACCESS));
+            let ns = timing.ns_per_access;
+            print!(" sequential {:.2} ns", ns);
+            sweep.sequential.points.push((buf_size, ns));
+            let mut lines = vec![("sequential", CACHE_LINE_SIZE, ns, values)];
 
-        // Allocate buffer using mmap (anonymous mapping)
-        let data = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(), 
-                buf_size, 
-                libc::PROT_READ | libc::PROT_WRITE, 
-                libc::MAP_ANONYMOUS | libc::MAP_PRIVATE, 
-                -1, 
-                0
-            )
+            for series in sweep.chases.iter_mut().filter(|series| series.stride <= buf_size) {
+                let slots = chase::build_ring(&buf, series.stride, buf_size as u64);
+                let loads = (4 * slots).clamp(MIN_LOADS, MAX_LOADS);
+                let (timing, values) = measure(counters.as_ref(), || chase::chase(&buf, loads));
+                let ns = timing.ns_per_access;
+                print!(", chase/{} {:.2} ns", series.stride, ns);
+                series.points.push((buf_size, ns));
+                lines.push(("chase", series.stride, ns, values));
+            }
+
+            // Every page has been touched by now
+            let huge = buf.huge_bytes().unwrap_or(0);
+            println!(", huge pages {}", format_size(huge));
+            sweep.huge.push((buf_size, huge));
+
+            for (pattern, stride, ns, values) in lines {
+                let values: String = values.iter()
+                    .map(|value| value.map_or("\t-".to_string(), |value| format!("\t{:.4}", value)))
+                    .collect();
+                writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}{}", i, sweep.pages.name(), pattern, stride, ns, 1.0 / ns, huge >> 10, values)
+                    .expect("Failed to write to file");
+            }
+        }
+
+        i += 0.25;
+    }
+
+    // Cache levels show best with base pages and one access per line;
+    // larger strides add TLB misses
+    let reference = sweeps.iter().find(|sweep| sweep.pages == PageMode::Base).unwrap_or(&sweeps[0]);
+    let knees = reference.chases.iter()
+        .find(|series| series.stride == CACHE_LINE_SIZE)
+        .or(reference.chases.first())
+        .map(|series| levels::detect(&series.points))
+        .unwrap_or_default();
+
+    println!("\n=== Detected levels ({} pages) ===", reference.pages.name());
+    for (index, knee) in knees.iter().enumerate() {
+        let sysfs = match data_caches.get(index) {
+            Some(cache) => format!("sysfs {} ({:.2}x)", format_size(cache.size), knee.size as f64 / cache.size as f64),
+            None => "no sysfs cache".to_string(),
         };
+        println!("{}: up to {}, {:.2} ns -> {:.2} ns; {}",
+            level_name(&data_caches, index), format_size(knee.size), knee.latency, knee.next_latency, sysfs);
+    }
+    if knees.len() < data_caches.len() {
+        // A cache shared with other tenants (e.g. in a VM) may never form a plateau
+        println!("Fewer knees than sysfs data caches: the sweep may end too early (see --max-size),");
+        println!("or the remaining caches are too contended to show");
+    }
+
+    // With one access per page, a knee is where the page walks no longer fit the TLB
+    for series in reference.chases.iter().filter(|series| series.stride >= PAGE_SIZE) {
+        for knee in levels::detect(&series.points) {
+            println!("{}: knee at {} ({} pages)", series.label, format_size(knee.size), knee.size / series.stride);
+        }
+    }
+
+    print_tlb_cost(&sweeps);
+
+    println!("\nData collection complete. Generatin graph...");
+    let mut all = vec![&reference.sequential];
+    all.extend(&reference.chases);
+    if let Err(e) = plot_cache("cache.png", "Cache Memory Effect Visualization", &all, &knees, &data_caches) {
+        eprintln!("Failed to generate graph: {}", e);
+        std::process::exit(1);
+    }
+    println!("Graph generated successfully: cache.png");
 
-        if data == libc::MAP_FAILED {
-            eprintln!("mmap() failed ");
+    if sweeps.len() > 1 {
+        let chases: Vec<&Series> = sweeps.iter()
+            .flat_map(|sweep| sweep.chases.iter())
+            .filter(|series| !series.points.is_empty())
+            .collect();
+        if let Err(e) = plot_cache("pages.png", "Base vs Huge Pages", &chases, &[], &data_caches) {
+            eprintln!("Failed to generate graph: {}", e);
             std::process::exit(1);
         }
+        println!("Graph generated successfully: pages.png");
+    }
+}
 
-        let ptr = data as *mut u8;
+/// One cell of the NUMA matrix
+struct NumaCell {
+    cpu_node: usize,
+    mem_node: usize,
+    ns_per_load: f64,
+    gb_per_second: f64,
+}
 
-        println!("Collecting data for buffer size 2^{:.2}({}) KB", i, buf_size / 1024);
+/// One of the values of a NUMA cell
+type CellValue = fn(&NumaCell) -> f64;
 
-        let start = Instant::now();
+/// Measure every pair of a node with CPUs and a node with memory
+fn run_numa(size: usize, pages: PageMode) {
+    let cpus = affinity::allowed_cpus().unwrap_or_else(|e| {
+        eprintln!("sched_getaffinity() failed: {}", e);
+        std::process::exit(1);
+    });
+    let nodes = numa::nodes(&cpus).unwrap_or_else(|e| {
+        eprintln!("Failed to read the NUMA nodes from sysfs: {}", e);
+        std::process::exit(1);
+    });
 
-        // Access memory: iterate through entire buffer in cache line steps
-        let iterations = NACCESS / (buf_size / CACHE_LINE_SIZE);
-        for _ in 0..iterations {
-            for j in (0..buf_size).step_by(CACHE_LINE_SIZE) {
-                unsafe {
-                    *ptr.add(j) = 0;
-                    black_box(*ptr.add(j)); // Prevent optimization
+    println!("\n=== NUMA nodes ===");
+    for node in &nodes {
+        println!("node {}: CPUs {:?}, memory {}, distances {:?}", node.id, node.cpus, format_size(node.memory as usize), node.distances);
+    }
+    // Only the CPUs this process may use; nodes without memory are no targets
+    let cpu_nodes: Vec<(&Node, Vec<usize>)> = nodes.iter()
+        .map(|node| (node, node.cpus.iter().copied().filter(|cpu| cpus.contains(cpu)).collect::<Vec<_>>()))
+        .filter(|(_, cpus)| !cpus.is_empty())
+        .collect();
+    let mem_nodes: Vec<&Node> = nodes.iter().filter(|node| node.memory > 0).collect();
+    if nodes.len() == 1 {
+        println!("Single NUMA node: all memory is local, so the matrix is one cell of local latency and bandwidth");
+    }
+    if !numa::available() {
+        println!("Kernel without NUMA support; buffers are not bound");
+    }
+
+    println!("\n=== NUMA matrix ({} buffers, {} pages) ===", format_size(size), pages.name());
+    let mut cells = Vec::new();
+    for (cpu_node, node_cpus) in &cpu_nodes {
+        if let Err(e) = affinity::pin_to(node_cpus) {
+            eprintln!("Failed to pin to the CPUs of node {}: {}", cpu_node.id, e);
+            std::process::exit(1);
+        }
+        for mem_node in &mem_nodes {
+            let buf = Buffer::with_pages(size, pages).unwrap_or_else(|e| {
+                eprintln!("mmap() failed: {}", e);
+                std::process::exit(1);
+            });
+            if numa::available() {
+                if let Err(e) = numa::bind(&buf, mem_node.id) {
+                    println!("CPUs of node {} -> memory of node {}: mbind() failed: {}", cpu_node.id, mem_node.id, e);
+                    continue;
                 }
             }
-        }
 
-        let elapsed = start.elapsed();
-        let access_per_ns = NACCESS as f64 / elapsed.as_nanos() as f64;
+            // Building the ring touches, and so allocates, every page
+            let slots = chase::build_ring(&buf, CACHE_LINE_SIZE, size as u64);
+            let placed = numa::node_of(&buf).ok();
+            let ns_per_load = chase::chase(&buf, (4 * slots).clamp(MIN_LOADS, MAX_LOADS)).ns_per_access;
+            let gb_per_second = bandwidth::read_gb_per_second(&buf, Duration::from_secs_f64(NUMA_SECONDS));
 
-        // Write result to file
-        writeln!(file, "{}\t{}", i, access_per_ns)
-            .expect("Failed to write to file");
+            let placement = match placed {
+                Some(node) if node != mem_node.id => format!(" (pages landed on node {})", node),
+                _ => String::new(),
+            };
+            println!("CPUs of node {} -> memory of node {}: {:.2} ns per load, {:.2} GB/s read{}",
+                cpu_node.id, mem_node.id, ns_per_load, gb_per_second, placement);
+            cells.push(NumaCell { cpu_node: cpu_node.id, mem_node: mem_node.id, ns_per_load, gb_per_second });
+        }
+    }
+    let _ = affinity::pin_to(&cpus);
 
-        // Store for plotting
-        results.push((i, access_per_ns));
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open("numa.txt")
+        .expect("Failed to open numa.txt");
+    writeln!(file, "# cpu_node\tmem_node\tdistance\tns_per_load\tgb_per_s").expect("Failed to write to file");
+    for cell in &cells {
+        let distance = nodes.iter()
+            .find(|node| node.id == cell.cpu_node)
+            .and_then(|node| node.distance(cell.mem_node))
+            .map_or("-".to_string(), |distance| distance.to_string());
+        writeln!(file, "{}\t{}\t{}\t{}\t{}", cell.cpu_node, cell.mem_node, distance, cell.ns_per_load, cell.gb_per_second)
+            .expect("Failed to write to file");
+    }
 
-        // CLeanup
-        unsafe {
-            libc::munmap(data, buf_size);
+    // Rows are where the thread runs, columns where its memory is
+    let row_ids: Vec<usize> = cpu_nodes.iter().map(|(node, _)| node.id).collect();
+    let column_ids: Vec<usize> = mem_nodes.iter().map(|node| node.id).collect();
+    let tables: [(&str, CellValue); 2] = [
+        ("Latency [ns / load]", |cell| cell.ns_per_load),
+        ("Read bandwidth [GB/s]", |cell| cell.gb_per_second),
+    ];
+    for (title, value) in tables {
+        println!("\n{} (rows: CPU node, columns: memory node)", title);
+        print!("{:>6}", "");
+        for id in &column_ids {
+            print!("{:>10}", format!("node {}", id));
+        }
+        println!();
+        for &row in &row_ids {
+            print!("{:>6}", format!("node {}", row));
+            for &column in &column_ids {
+                match cells.iter().find(|cell| cell.cpu_node == row && cell.mem_node == column) {
+                    Some(cell) => print!("{:>10.2}", value(cell)),
+                    None => print!("{:>10}", "-"),
+                }
+            }
+            println!();
         }
-
-        i += 0.25;
     }
 
-    println!("\nData collection complete. Generatin graph...");
-    if let Err(e) = plot_cache(&results) {
+    if let Err(e) = plot_numa(&cells, &row_ids, &column_ids) {
         eprintln!("Failed to generate graph: {}", e);
         std::process::exit(1);
     }
+    println!("\nGraph generated successfully: numa.png");
+}
+
+/// Heat maps of the NUMA matrix, latency on the left and bandwidth on the right
+fn plot_numa(cells: &[NumaCell], rows: &[usize], columns: &[usize]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new("numa.png", (1024, 512)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let panels = root.split_evenly((1, 2));
+
+    let maps: [(&str, CellValue, RGBColor); 2] = [
+        ("Latency [ns / load]", |cell| cell.ns_per_load, RED),
+        ("Read Bandwidth [GB/s]", |cell| cell.gb_per_second, BLUE),
+    ];
+    for (panel, (caption, value, color)) in panels.iter().zip(maps) {
+        let max = cells.iter().map(value).fold(0.0, f64::max);
+        let mut chart = ChartBuilder::on(panel)
+            .caption(caption, ("sans-serif", 24).into_font())
+            .margin(15)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(-0.5f64..columns.len() as f64 - 0.5, -0.5f64..rows.len() as f64 - 0.5)?;
+
+        let label = |ids: &[usize], x: f64| {
+            if (x - x.round()).abs() > 1e-6 || x < 0.0 {
+                return String::new();
+            }
+            ids.get(x.round() as usize).map_or(String::new(), |id| format!("node {}", id))
+        };
+        chart.configure_mesh()
+            .disable_mesh()
+            .x_labels(2 * columns.len() + 1)
+            .y_labels(2 * rows.len() + 1)
+            .x_label_formatter(&|x| label(columns, *x))
+            .y_label_formatter(&|y| label(rows, *y))
+            .x_desc("Memory node")
+            .y_desc("CPU node")
+            .draw()?;
+
+        for cell in cells {
+            let (Some(x), Some(y)) = (
+                columns.iter().position(|&id| id == cell.mem_node),
+                rows.iter().position(|&id| id == cell.cpu_node),
+            ) else {
+                continue;
+            };
+            let (x, y) = (x as f64, y as f64);
+            let shade = if max > 0.0 { 0.15 + 0.7 * value(cell) / max } else { 0.15 };
+            chart.draw_series(std::iter::once(Rectangle::new([(x - 0.5, y - 0.5), (x + 0.5, y + 0.5)], color.mix(shade).filled())))?;
+            chart.draw_series(std::iter::once(Text::new(
+                format!("{:.1}", value(cell)),
+                (x - 0.1, y),
+                ("sans-serif", 20).into_font(),
+            )))?;
+        }
+    }
 
-    println!("Graph generated successfully: cache.png")
+    root.present()?;
+
+    Ok(())
 }
 
-fn plot_cache(data: &[(f64, f64)]) -> Result<(), Box<dyn std::error::Error>> {
-    let root = BitMapBackend::new("cache.png", (1024, 768)).into_drawing_area();
+/// Compare each page mode with base pages at the largest buffer they share,
+/// where base pages miss the TLB the most
+fn print_tlb_cost(sweeps: &[Sweep]) {
+    let Some(base) = sweeps.iter().find(|sweep| sweep.pages == PageMode::Base) else {
+        return;
+    };
+
+    println!("\n=== TLB miss cost ===");
+    for sweep in sweeps.iter().filter(|sweep| sweep.pages != PageMode::Base) {
+        let Some(&(size, huge)) = sweep.huge.last() else {
+            println!("{}: no buffer could be mapped", sweep.pages.name());
+            continue;
+        };
+        let coverage = huge as f64 / size.next_multiple_of(PAGE_SIZE) as f64;
+        println!("{} at {}: {:.0}% in huge pages", sweep.pages.name(), format_size(size), 100.0 * coverage.min(1.0));
+        if huge == 0 {
+            println!("  No huge pages were obtained; the curves show base pages only");
+        }
+
+        for (series, base_series) in sweep.chases.iter().zip(&base.chases) {
+            let at = |series: &Series| series.points.iter().find(|(s, _)| *s == size).map(|(_, ns)| *ns);
+            let (Some(ns), Some(base_ns)) = (at(series), at(base_series)) else {
+                continue;
+            };
+            println!("  chase/{}: {:.2} ns vs {:.2} ns per load with base pages, {:.2} ns ({:.0}%) spent on TLB misses",
+                series.stride, ns, base_ns, base_ns - ns, 100.0 * (base_ns - ns) / base_ns);
+        }
+    }
+}
+
+fn plot_cache(path: &str, caption: &str, series: &[&Series], knees: &[Knee], caches: &[&Cache]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, (1024, 768)).into_drawing_area();
     root.fill(&WHITE)?;
 
     // Find min/max for axes
-    let min_x = data.iter().map(|(x, _)| *x).fold(f64::INFINITY, f64::min);
-    let max_x = data.iter().map(|(x, _)| *x).fold(f64::NEG_INFINITY, f64::max);
-    let min_y = data.iter().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
-    let max_y = data.iter().map(|(_, y)| *y).fold(f64::NEG_INFINITY, f64::max);
+    let points = || series.iter().flat_map(|series| series.points.iter());
+    let min_x = points().map(|(size, _)| log2_kib(*size)).fold(f64::INFINITY, f64::min);
+    let max_x = points().map(|(size, _)| log2_kib(*size)).fold(f64::NEG_INFINITY, f64::max);
+    let min_y = points().map(|(_, ns)| *ns).fold(f64::INFINITY, f64::min);
+    let max_y = points().map(|(_, ns)| *ns).fold(f64::NEG_INFINITY, f64::max);
 
-    // Add some padding
-    let y_padding = (max_y - min_y) * 0.1;
+    // Add some padding, latencies span orders of magnitude
+    let (min_y, max_y) = (min_y / 1.5, max_y * 2.0);
 
     let mut chart = ChartBuilder::on(&root)
-        .caption("Cache Memory Effect Visualization", ("sans-selif", 40).into_font())
+        .caption(caption, ("sans-serif", 40).into_font())
         .margin(15)
         .x_label_area_size(50)
         .y_label_area_size(70)
-        .build_cartesian_2d(
-            min_x..max_x,
-            (min_y - y_padding)..(max_y + y_padding)
-        )?;
+        .build_cartesian_2d(min_x..max_x, (min_y..max_y).log_scale())?;
 
     chart.configure_mesh()
-        .x_desc("Buffer Size [2^x KiB")
-        .y_desc("Access Speef [accesses / nanosecond]")
+        .x_desc("Buffer Size [2^x KiB]")
+        .y_desc("Access Time [ns / access]")
         .x_label_style(("sans-serif", 15))
         .y_label_style(("sans-serif", 15))
         .draw()?;
-    
-    // Draw scatter plot
-    chart.draw_series(
-        data.iter().map(|(x, y)| Circle::new((*x, *y), 3, BLUE.filled()))
-    )?
-    .label("Access Speed")
-    .legend(|(x, y)| Circle::new((x, y), 3, BLUE.filled()));
+
+    // Cache sizes from sysfs, dotted, labelled at the bottom
+    for cache in caches {
+        let x = log2_kib(cache.size);
+        if x < min_x || x > max_x {
+            continue;
+        }
+        chart.draw_series(DashedLineSeries::new(vec![(x, min_y), (x, max_y)], 2, 4, BLACK.mix(0.5).into()))?;
+        chart.draw_series(std::iter::once(Text::new(
+            format!("{} {} (sysfs)", cache.name(), format_size(cache.size)),
+            (x, min_y * 1.05),
+            ("sans-serif", 14).into_font(),
+        )))?;
+    }
+
+    // Detected levels, dashed, labelled at the top
+    for (index, knee) in knees.iter().enumerate() {
+        let x = log2_kib(knee.size);
+        chart.draw_series(DashedLineSeries::new(vec![(x, min_y), (x, max_y)], 8, 4, RED.into()))?;
+        chart.draw_series(std::iter::once(Text::new(
+            format!("{} ~ {}", level_name(caches, index), format_size(knee.size)),
+            (x, max_y / 1.1),
+            ("sans-serif", 14).into_font().color(&RED),
+        )))?;
+    }
+
+    for (index, series) in series.iter().enumerate() {
+        let color = Palette99::pick(index).to_rgba();
+        chart.draw_series(LineSeries::new(
+            series.points.iter().map(|(size, ns)| (log2_kib(*size), *ns)),
+            color.stroke_width(2),
+        ))?
+        .label(&series.label)
+        .legend(move |(x, y)| PathElement::new(vec![(x - 10, y), (x + 10, y)], color.stroke_width(2)));
+        chart.draw_series(
+            series.points.iter().map(|(size, ns)| Circle::new((log2_kib(*size), *ns), 3, color.filled()))
+        )?;
+    }
 
     chart.configure_series_labels()
-        .background_style(&WHITE.mix(0.8))
-        .border_style(&BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
         .draw()?;
 
     root.present()?;
-    
+
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,198 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+use plotters::prelude::*;
+use chap08::affinity;
+use chap08::bandwidth::{self, Op, Sharing};
+use chap08::false_sharing::{self, Layout};
+use chap08::topology::CACHE_LINE_SIZE;
+
+/// Buffer per thread, well beyond the caches of most machines
+const DEFAULT_SIZE_MIB: usize = 256;
+const DEFAULT_SECONDS: f64 = 1.0;
+const DEFAULT_INCREMENTS: u64 = 100_000_000;
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--threads LIST] [--size MIB] [--seconds S] [--increments N]", prog_name);
+    eprintln!();
+    eprintln!("  Measure the aggregate memory bandwidth of pinned threads reading, writing and");
+    eprintln!("  copying disjoint or shared buffers, then the cost of false sharing between");
+    eprintln!("  counters on one cache line against counters padded to {} bytes.", CACHE_LINE_SIZE);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --threads LIST: Thread counts, e.g. 1,2,4 (default: powers of two up to the allowed CPUs)");
+    eprintln!("  --size MIB: Buffer per thread, or shared by all (default: {})", DEFAULT_SIZE_MIB);
+    eprintln!("  --seconds S: Time each thread streams per measurement (default: {})", DEFAULT_SECONDS);
+    eprintln!("  --increments N: Counter increments per thread (default: {})", DEFAULT_INCREMENTS);
+    std::process::exit(1);
+}
+
+/// 1, 2, 4, ... below `cpus`, and `cpus` itself
+fn default_thread_counts(cpus: usize) -> Vec<usize> {
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2)).take_while(|&n| n < cpus).collect();
+    counts.push(cpus);
+    counts
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut thread_counts = None;
+    let mut size = DEFAULT_SIZE_MIB << 20;
+    let mut seconds = DEFAULT_SECONDS;
+    let mut increments = DEFAULT_INCREMENTS;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--threads" => {
+                thread_counts = rest.next()
+                    .and_then(|list| list.split(',').map(|n| n.parse().ok()).collect::<Option<Vec<usize>>>())
+                    .filter(|counts| counts.iter().all(|&n| n > 0))
+                    .or_else(|| usage(prog_name))
+            }
+            "--size" => {
+                size = rest.next()
+                    .and_then(|mib| mib.parse::<usize>().ok())
+                    .filter(|&mib| mib > 0)
+                    .map(|mib| mib << 20)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--seconds" => {
+                seconds = rest.next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|&s| s > 0.0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--increments" => {
+                increments = rest.next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            _ => usage(prog_name),
+        }
+    }
+
+    let cpus = affinity::allowed_cpus().unwrap_or_else(|e| {
+        eprintln!("sched_getaffinity() failed: {}", e);
+        std::process::exit(1);
+    });
+    let thread_counts = thread_counts.unwrap_or_else(|| default_thread_counts(cpus.len()));
+    println!("Allowed CPUs: {:?}", cpus);
+    if thread_counts.iter().any(|&n| n > cpus.len()) {
+        println!("More threads than CPUs: some threads will share a CPU and time slice it");
+    }
+    // Thread k runs on the k-th allowed CPU, wrapping around
+    let assign = |threads: usize| -> Vec<usize> { (0..threads).map(|k| cpus[k % cpus.len()]).collect() };
+
+    println!("\n=== Memory bandwidth ({} MiB buffers, {} s per run) ===", size >> 20, seconds);
+    let mut file = File::create("bandwidth.txt").expect("Failed to create bandwidth.txt");
+    writeln!(file, "# op\tsharing\tthreads\tgb_per_s").expect("Failed to write to file");
+    let mut bandwidth_series = Vec::new();
+    for op in Op::ALL {
+        for sharing in Sharing::ALL {
+            let mut points = Vec::new();
+            for &threads in &thread_counts {
+                let results = bandwidth::run(op, sharing, &assign(threads), size, Duration::from_secs_f64(seconds))
+                    .unwrap_or_else(|e| {
+                        eprintln!("Bandwidth run failed: {}", e);
+                        std::process::exit(1);
+                    });
+                let total = bandwidth::aggregate_gb_per_second(&results);
+                let per_thread: Vec<f64> = results.iter().map(|result| result.gb_per_second()).collect();
+                println!("{:<5} {:<8} {:>3} threads: {:7.2} GB/s (per thread {:.2} - {:.2})",
+                    op.name(), sharing.name(), threads, total,
+                    per_thread.iter().copied().fold(f64::INFINITY, f64::min),
+                    per_thread.iter().copied().fold(0.0, f64::max));
+                writeln!(file, "{}\t{}\t{}\t{}", op.name(), sharing.name(), threads, total)
+                    .expect("Failed to write to file");
+                points.push((threads, total));
+            }
+            bandwidth_series.push((format!("{}, {}", op.name(), sharing.name()), points));
+        }
+    }
+
+    println!("\n=== False sharing ({} increments per thread) ===", increments);
+    let mut file = File::create("false_sharing.txt").expect("Failed to create false_sharing.txt");
+    writeln!(file, "# layout\tthreads\tseconds\tmillion_increments_per_s").expect("Failed to write to file");
+    let mut sharing_series = Vec::new();
+    for layout in Layout::ALL {
+        let mut points = Vec::new();
+        for &threads in &thread_counts {
+            let elapsed = false_sharing::run(layout, &assign(threads), increments).unwrap_or_else(|e| {
+                eprintln!("False sharing run failed: {}", e);
+                std::process::exit(1);
+            });
+            let rate = (threads as u64 * increments) as f64 / elapsed / 1e6;
+            println!("{:<6} {:>3} threads: {:8.2} M increments/s, {:.2} ns per increment",
+                layout.name(), threads, rate, elapsed * 1e9 / increments as f64);
+            writeln!(file, "{}\t{}\t{}\t{}", layout.name(), threads, elapsed, rate)
+                .expect("Failed to write to file");
+            points.push((threads, rate));
+        }
+        sharing_series.push((format!("{} counters", layout.name()), points));
+    }
+
+    println!("\nData collection complete. Generatin graph...");
+    let plots = [
+        ("bandwidth.png", "Aggregate Memory Bandwidth", "Bandwidth [GB/s]", &bandwidth_series),
+        ("false_sharing.png", "False Sharing", "Throughput [million increments / s]", &sharing_series),
+    ];
+    for (path, caption, y_desc, series) in plots {
+        if let Err(e) = plot_per_threads(path, caption, y_desc, series) {
+            eprintln!("Failed to generate graph: {}", e);
+            std::process::exit(1);
+        }
+        println!("Graph generated successfully: {}", path);
+    }
+}
+
+/// Plot one line per series over the thread counts
+fn plot_per_threads(path: &str, caption: &str, y_desc: &str, series: &[(String, Vec<(usize, f64)>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let root = BitMapBackend::new(path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let points = || series.iter().flat_map(|(_, points)| points.iter());
+    let max_x = points().map(|(threads, _)| *threads).max().unwrap_or(1);
+    let max_y = points().map(|(_, y)| *y).fold(0.0, f64::max);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption, ("sans-serif", 40).into_font())
+        .margin(15)
+        .x_label_area_size(50)
+        .y_label_area_size(70)
+        .build_cartesian_2d(0.5f64..max_x as f64 + 0.5, 0f64..max_y * 1.1)?;
+
+    chart.configure_mesh()
+        .x_desc("Threads")
+        .y_desc(y_desc)
+        .x_label_formatter(&|x| if x.fract() == 0.0 { format!("{}", x) } else { String::new() })
+        .x_label_style(("sans-serif", 15))
+        .y_label_style(("sans-serif", 15))
+        .draw()?;
+
+    for (index, (label, points)) in series.iter().enumerate() {
+        let color = Palette99::pick(index).to_rgba();
+        chart.draw_series(LineSeries::new(
+            points.iter().map(|(threads, y)| (*threads as f64, *y)),
+            color.stroke_width(2),
+        ))?
+        .label(label)
+        .legend(move |(x, y)| PathElement::new(vec![(x - 10, y), (x + 10, y)], color.stroke_width(2)));
+        chart.draw_series(
+            points.iter().map(|(threads, y)| Circle::new((*threads as f64, *y), 4, color.filled()))
+        )?;
+    }
+
+    chart.configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Aggregate memory bandwidth of several pinned threads.
+//!
+//! Every thread streams over a buffer larger than the caches for a fixed
+//! time, reading it, writing it or copying it to a second one. With
+//! disjoint buffers each thread allocates and first touches its own, so the
+//! threads only compete for the memory controllers; with shared buffers they
+//! all stream over the same ones, which the last level cache may hold for
+//! readers, while writers keep stealing the lines from each other.
+//!
+//! Writes are relaxed atomic stores of 64-bit words, so that threads writing
+//! the same words at once is not a data race; they compile to plain stores
+//! on x86-64 and arm64. Both sharing modes use them, so that only the
+//! sharing differs between them.
+
+use crate::affinity;
+use crate::buffer::Buffer;
+use std::hint::black_box;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What each thread does with its buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Sum the buffer as 64-bit words
+    Read,
+    /// Store to every 64-bit word of the buffer
+    Write,
+    /// Copy the buffer word by word to a second one, counted as read plus write
+    Copy,
+}
+
+impl Op {
+    pub const ALL: [Op; 3] = [Op::Read, Op::Write, Op::Copy];
+
+    pub fn parse(name: &str) -> Option<Op> {
+        Op::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Copy => "copy",
+        }
+    }
+
+    /// Bytes moved by one pass over a buffer of `size` bytes
+    fn bytes_per_pass(&self, size: usize) -> u64 {
+        match self {
+            Op::Read | Op::Write => size as u64,
+            Op::Copy => 2 * size as u64,
+        }
+    }
+}
+
+/// Whether the threads share their buffers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sharing {
+    Disjoint,
+    Shared,
+}
+
+impl Sharing {
+    pub const ALL: [Sharing; 2] = [Sharing::Disjoint, Sharing::Shared];
+
+    pub fn parse(name: &str) -> Option<Sharing> {
+        Sharing::ALL.into_iter().find(|sharing| sharing.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sharing::Disjoint => "disjoint",
+            Sharing::Shared => "shared",
+        }
+    }
+}
+
+/// Bytes one thread moved and how long it took
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadResult {
+    pub cpu: usize,
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+impl ThreadResult {
+    pub fn gb_per_second(&self) -> f64 {
+        self.bytes as f64 / self.seconds / 1e9
+    }
+}
+
+/// Aggregate bandwidth of a run in GB/s: all bytes over the slowest thread
+pub fn aggregate_gb_per_second(results: &[ThreadResult]) -> f64 {
+    let bytes: u64 = results.iter().map(|result| result.bytes).sum();
+    let seconds = results.iter().map(|result| result.seconds).fold(0.0, f64::max);
+    if seconds == 0.0 { 0.0 } else { bytes as f64 / seconds / 1e9 }
+}
+
+/// Map a buffer and write every page of it, so that it is allocated (on the
+/// NUMA node of the calling thread) before being measured
+fn touched_buffer(size: usize) -> io::Result<Buffer> {
+    let buf = Buffer::new(size)?;
+    unsafe { std::ptr::write_bytes(buf.as_mut_ptr(), 1, size) };
+    Ok(buf)
+}
+
+/// Sum `size` bytes at `src` as 64-bit words
+fn read_pass(src: *const u8, size: usize) {
+    let words = unsafe { std::slice::from_raw_parts(src as *const u64, size / size_of::<u64>()) };
+    black_box(words.iter().fold(0u64, |sum, &word| sum.wrapping_add(word)));
+}
+
+/// `buf` as 64-bit words that several threads may load and store at once
+fn words(buf: &Buffer) -> &[AtomicU64] {
+    // Buffers are page aligned, and AtomicU64 has the size and alignment of u64
+    unsafe { std::slice::from_raw_parts(buf.as_mut_ptr() as *const AtomicU64, buf.len() / size_of::<u64>()) }
+}
+
+/// Read bandwidth of the calling thread over `buf`, in GB/s, e.g. to
+/// compare where the buffer is placed
+pub fn read_gb_per_second(buf: &Buffer, duration: Duration) -> f64 {
+    let start = Instant::now();
+    let mut passes = 0;
+    while start.elapsed() < duration {
+        read_pass(buf.as_mut_ptr(), buf.len());
+        passes += 1;
+    }
+    (passes * buf.len()) as f64 / start.elapsed().as_secs_f64() / 1e9
+}
+
+/// Buffers one thread works on; copies need a destination
+struct Buffers {
+    src: Buffer,
+    dst: Option<Buffer>,
+}
+
+impl Buffers {
+    fn new(op: Op, size: usize) -> io::Result<Buffers> {
+        let dst = match op {
+            Op::Copy => Some(touched_buffer(size)?),
+            Op::Read | Op::Write => None,
+        };
+        Ok(Buffers { src: touched_buffer(size)?, dst })
+    }
+
+    /// One pass of `op` over the buffers
+    fn pass(&self, op: Op, round: usize) {
+        match (op, &self.dst) {
+            // Nothing writes the source, so plain loads are fine
+            (Op::Read, _) => read_pass(self.src.as_mut_ptr(), self.src.len()),
+            (Op::Write, _) => {
+                let value = (round as u64).wrapping_mul(0x0101_0101_0101_0101);
+                for word in words(&self.src) {
+                    word.store(value, Ordering::Relaxed);
+                }
+            }
+            (Op::Copy, Some(dst)) => {
+                for (from, to) in words(&self.src).iter().zip(words(dst)) {
+                    to.store(from.load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+            }
+            (Op::Copy, None) => unreachable!("Copy without a destination"),
+        }
+        black_box(&self.src);
+    }
+}
+
+/// Run one thread per entry of `cpus`, pinned to it, doing `op` over
+/// buffers of `size` bytes for `duration`
+pub fn run(op: Op, sharing: Sharing, cpus: &[usize], size: usize, duration: Duration) -> io::Result<Vec<ThreadResult>> {
+    let shared = match sharing {
+        Sharing::Shared => Some(Buffers::new(op, size)?),
+        Sharing::Disjoint => None,
+    };
+    let barrier = Barrier::new(cpus.len());
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = cpus.iter()
+            .map(|&cpu| {
+                let (shared, barrier) = (&shared, &barrier);
+                scope.spawn(move || -> io::Result<ThreadResult> {
+                    // Pin before allocating, so that first touch places the pages near the CPU
+                    let pinned = affinity::pin_to(&[cpu]);
+                    let own = shared.is_none().then(|| Buffers::new(op, size));
+                    // Every thread has to reach the barrier, even after an error
+                    barrier.wait();
+                    pinned?;
+                    let own = own.transpose()?;
+                    let buffers = shared.as_ref().or(own.as_ref()).expect("Buffers are shared or own");
+
+                    let start = Instant::now();
+                    let mut passes = 0;
+                    while start.elapsed() < duration {
+                        buffers.pass(op, passes);
+                        passes += 1;
+                    }
+                    Ok(ThreadResult { cpu, bytes: passes as u64 * op.bytes_per_pass(size), seconds: start.elapsed().as_secs_f64() })
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().expect("Benchmark thread panicked")).collect()
+    })
+}
@@ -0,0 +1,159 @@
+//! Anonymous memory mappings used as benchmark buffers.
+//!
+//! Buffers can be backed by base pages, transparent huge pages or hugetlbfs
+//! pages. Each huge page covers 512 base pages with a single TLB entry, so
+//! comparing them shows what TLB misses cost. Whether huge pages were really
+//! obtained is read back from /proc/self/smaps: THP depends on the sysfs
+//! policy and on free contiguous memory, hugetlb on the reserved pool.
+
+use std::fs;
+use std::io;
+
+/// Size of a PMD-level huge page with 4 KiB base pages (x86-64, arm64)
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// Pages backing a buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageMode {
+    /// Base pages only, transparent huge pages disabled with MADV_NOHUGEPAGE
+    Base,
+    /// Transparent huge pages requested with MADV_HUGEPAGE
+    Thp,
+    /// Pages of the hugetlb pool mapped with MAP_HUGETLB
+    Hugetlb,
+}
+
+impl PageMode {
+    pub const ALL: [PageMode; 3] = [PageMode::Base, PageMode::Thp, PageMode::Hugetlb];
+
+    pub fn parse(name: &str) -> Option<PageMode> {
+        PageMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PageMode::Base => "base",
+            PageMode::Thp => "thp",
+            PageMode::Hugetlb => "hugetlb",
+        }
+    }
+}
+
+/// Private anonymous mapping, unmapped on drop
+pub struct Buffer {
+    ptr: *mut u8,
+    len: usize,
+    /// Length of the mapping, `len` rounded up to the page size
+    map_len: usize,
+}
+
+fn mmap_anonymous(len: usize, flags: libc::c_int) -> io::Result<*mut u8> {
+    let data = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | flags,
+            -1,
+            0,
+        )
+    };
+    if data == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(data as *mut u8)
+}
+
+impl Buffer {
+    /// Map `len` bytes of zeroed memory, with the system's THP policy
+    pub fn new(len: usize) -> io::Result<Buffer> {
+        Ok(Buffer { ptr: mmap_anonymous(len, 0)?, len, map_len: len })
+    }
+
+    /// Map `len` bytes of zeroed memory backed by the given pages
+    pub fn with_pages(len: usize, mode: PageMode) -> io::Result<Buffer> {
+        let map_len = len.next_multiple_of(HUGE_PAGE_SIZE);
+        match mode {
+            PageMode::Base => {
+                let buf = Buffer::new(len)?;
+                buf.madvise(libc::MADV_NOHUGEPAGE)?;
+                Ok(buf)
+            }
+            PageMode::Thp => {
+                // Huge pages need aligned memory: map one more and trim both ends
+                let data = mmap_anonymous(map_len + HUGE_PAGE_SIZE, 0)?;
+                let head = data.align_offset(HUGE_PAGE_SIZE);
+                let ptr = unsafe { data.add(head) };
+                unsafe {
+                    if head > 0 {
+                        libc::munmap(data as *mut libc::c_void, head);
+                    }
+                    libc::munmap(ptr.add(map_len) as *mut libc::c_void, HUGE_PAGE_SIZE - head);
+                }
+                let buf = Buffer { ptr, len, map_len };
+                buf.madvise(libc::MADV_HUGEPAGE)?;
+                Ok(buf)
+            }
+            PageMode::Hugetlb => Ok(Buffer { ptr: mmap_anonymous(map_len, libc::MAP_HUGETLB)?, len, map_len }),
+        }
+    }
+
+    fn madvise(&self, advice: libc::c_int) -> io::Result<()> {
+        if unsafe { libc::madvise(self.ptr as *mut libc::c_void, self.map_len, advice) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes of the mapping currently backed by huge pages, transparent
+    /// (AnonHugePages) or hugetlb (Private_Hugetlb), as of /proc/self/smaps
+    pub fn huge_bytes(&self) -> io::Result<usize> {
+        let smaps = fs::read_to_string("/proc/self/smaps")?;
+        let addr = self.ptr as usize;
+        let mut inside = false;
+        let mut kib = 0;
+        for line in smaps.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(first) = fields.next() else {
+                continue;
+            };
+            // Mapping headers start with "start-end", fields with "Name:"
+            if let Some((start, end)) = first.split_once('-') {
+                if let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+                    // Neighbouring mappings with the same flags may have been merged with ours
+                    inside = start <= addr && addr < end;
+                    continue;
+                }
+            }
+            if inside && (first == "AnonHugePages:" || first == "Private_Hugetlb:") {
+                kib += fields.next().and_then(|value| value.parse::<usize>().ok()).unwrap_or(0);
+            }
+        }
+        Ok((kib << 10).min(self.map_len))
+    }
+}
+
+// The mapping only hands out raw pointers; synchronising access through
+// them is up to the users
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.map_len);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Access patterns over a buffer: sequential sweeps and pointer chasing.
+//!
+//! A sequential sweep lets the hardware prefetchers and out-of-order
+//! execution overlap the accesses, so it measures throughput. Pointer chasing
+//! loads the address of the next access from the current one, in a random
+//! order, so every load waits for the previous one and the time per load is
+//! the latency of the level the buffer fits in.
+
+use crate::buffer::Buffer;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Small xorshift generator, good enough to shuffle the chase order
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// Result of a timed access loop
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub accesses: usize,
+    pub ns_per_access: f64,
+}
+
+/// Write a byte and read it back every `stride` bytes until `naccess`
+/// accesses are done
+pub fn sequential(buf: &Buffer, stride: usize, naccess: usize) -> Timing {
+    let ptr = buf.as_mut_ptr();
+    let per_pass = buf.len().div_ceil(stride);
+    let iterations = (naccess / per_pass).max(1);
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for j in (0..buf.len()).step_by(stride) {
+            unsafe {
+                *ptr.add(j) = 0;
+                black_box(*ptr.add(j)); // Prevent optimization
+            }
+        }
+    }
+    let accesses = iterations * per_pass;
+    Timing { accesses, ns_per_access: start.elapsed().as_nanos() as f64 / accesses as f64 }
+}
+
+/// Link one slot every `stride` bytes of `buf` into a single random cycle:
+/// each slot holds the address of the next one. Writing the links also
+/// brings the buffer into the caches and the TLB as far as it fits, so no
+/// further warm-up is needed. Returns the number of slots.
+pub fn build_ring(buf: &Buffer, stride: usize, seed: u64) -> usize {
+    assert!(stride >= size_of::<usize>() && stride <= buf.len(), "Invalid stride {}", stride);
+    let slots = buf.len() / stride;
+
+    // Fisher-Yates shuffle of the visiting order
+    let mut order: Vec<usize> = (0..slots).collect();
+    let mut rng = XorShift::new(seed);
+    for i in (1..slots).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+
+    let base = buf.as_mut_ptr();
+    for (i, &slot) in order.iter().enumerate() {
+        let next = order[(i + 1) % slots];
+        unsafe {
+            *(base.add(slot * stride) as *mut usize) = base.add(next * stride) as usize;
+        }
+    }
+    slots
+}
+
+/// Follow the ring built by `build_ring` for `loads` dependent loads
+pub fn chase(buf: &Buffer, loads: usize) -> Timing {
+    let mut p = buf.as_mut_ptr() as usize;
+
+    let start = Instant::now();
+    for _ in 0..loads {
+        p = unsafe { *(p as *const usize) };
+    }
+    black_box(p);
+    Timing { accesses: loads, ns_per_access: start.elapsed().as_nanos() as f64 / loads as f64 }
+}
@@ -0,0 +1,81 @@
+//! False sharing between threads updating their own counters.
+//!
+//! Each thread only ever touches its own counter, so no synchronisation is
+//! needed. Yet when the counters sit on the same cache line, every increment
+//! has to take the line away from the other cores first, and the threads
+//! slow each other down as if they were sharing data. Padding every counter
+//! to its own line removes the effect.
+
+use crate::affinity;
+use crate::buffer::Buffer;
+use crate::topology::CACHE_LINE_SIZE;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::Instant;
+
+/// Where the counters of the threads are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Next to each other, eight to a cache line
+    Packed,
+    /// One cache line per counter
+    Padded,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 2] = [Layout::Packed, Layout::Padded];
+
+    pub fn parse(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Packed => "packed",
+            Layout::Padded => "padded",
+        }
+    }
+
+    /// Distance between two counters in bytes
+    fn spacing(&self) -> usize {
+        match self {
+            Layout::Packed => size_of::<AtomicU64>(),
+            Layout::Padded => CACHE_LINE_SIZE,
+        }
+    }
+}
+
+/// Run one thread per entry of `cpus`, pinned to it, each incrementing its
+/// counter `increments` times; returns the seconds of the slowest thread
+pub fn run(layout: Layout, cpus: &[usize], increments: u64) -> io::Result<f64> {
+    // A fresh mapping is page aligned, so counter 0 starts a cache line
+    let buf = Buffer::new(cpus.len() * layout.spacing())?;
+    let barrier = Barrier::new(cpus.len());
+
+    let results: Vec<io::Result<f64>> = thread::scope(|scope| {
+        let workers: Vec<_> = cpus.iter()
+            .enumerate()
+            .map(|(index, &cpu)| {
+                let (buf, barrier) = (&buf, &barrier);
+                scope.spawn(move || {
+                    let pinned = affinity::pin_to(&[cpu]);
+                    let counter = unsafe { &*(buf.as_mut_ptr().add(index * layout.spacing()) as *const AtomicU64) };
+                    barrier.wait();
+                    pinned?;
+
+                    // A plain load and store, not a locked read-modify-write:
+                    // the counter is private, only its cache line is not
+                    let start = Instant::now();
+                    for _ in 0..increments {
+                        counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+                    }
+                    Ok(start.elapsed().as_secs_f64())
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().expect("Benchmark thread panicked")).collect()
+    });
+    results.into_iter().try_fold(0.0, |slowest, seconds| Ok(f64::max(slowest, seconds?)))
+}
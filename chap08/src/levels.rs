@@ -0,0 +1,144 @@
+//! Detection of the memory hierarchy levels in a latency curve.
+//!
+//! The latency of pointer chasing stays flat while the buffer fits in a
+//! level and climbs to the next plateau once it spills over. The climb is
+//! gradual (associativity, TLB misses, other tenants of a shared cache), so
+//! the capacity of the level is taken where the curve crosses the geometric
+//! mean of the two plateaus.
+
+/// Latency has to exceed the plateau by this factor to leave it
+const JUMP: f64 = 1.5;
+/// A climb ends once the following points rise less than this factor
+const FLAT: f64 = 1.1;
+/// Points that have to stay flat to end a climb
+const FLAT_POINTS: usize = 2;
+
+/// End of a plateau in the latency curve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Knee {
+    /// Estimated capacity of the level, in bytes: the largest buffer size
+    /// still below the midpoint of the climb
+    pub size: usize,
+    /// Latency of the plateau, in ns
+    pub latency: f64,
+    /// Latency of the next plateau (or of the largest buffer), in ns
+    pub next_latency: f64,
+}
+
+/// Median of each point and its neighbours, to drop single outliers
+fn smooth(points: &[(usize, f64)]) -> Vec<(usize, f64)> {
+    (0..points.len())
+        .map(|i| {
+            let mut window: Vec<f64> = points[i.saturating_sub(1)..(i + 2).min(points.len())].iter().map(|p| p.1).collect();
+            window.sort_by(f64::total_cmp);
+            (points[i].0, window[window.len() / 2])
+        })
+        .collect()
+}
+
+/// Find the knees of a latency curve given as (buffer size, ns per load)
+/// points sorted by size, a few points per octave
+pub fn detect(points: &[(usize, f64)]) -> Vec<Knee> {
+    let points = smooth(points);
+    let mut knees = Vec::new();
+    let Some(&(_, mut plateau)) = points.first() else {
+        return knees;
+    };
+
+    let mut start = 0;
+    let mut i = 1;
+    while i < points.len() {
+        if points[i].1 <= plateau * JUMP {
+            // Still on the plateau, whose level is its fastest point
+            plateau = plateau.min(points[i].1);
+            i += 1;
+            continue;
+        }
+
+        // Climb until the next points stay flat
+        while i + 1 < points.len()
+            && points[i + 1..(i + 1 + FLAT_POINTS).min(points.len())].iter().any(|p| p.1 > points[i].1 * FLAT)
+        {
+            i += 1;
+        }
+        let next = points[i].1;
+        let midpoint = (plateau * next).sqrt();
+        let size = points[start..=i].iter().rev().find(|p| p.1 <= midpoint).map_or(points[start].0, |p| p.0);
+        knees.push(Knee { size, latency: plateau, next_latency: next });
+
+        start = i;
+        plateau = next;
+        i += 1;
+    }
+    knees
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+
+    /// Two points per octave from 4 KiB to 256 MiB of a hierarchy given as
+    /// (capacity, latency) levels; sizes beyond the last level take its latency
+    fn curve(levels: &[(usize, f64)]) -> Vec<(usize, f64)> {
+        let mut points = Vec::new();
+        let mut size = 4 * KIB;
+        while size <= 256 * MIB {
+            for size in [size, size + size / 2] {
+                let latency = levels.iter().find(|&&(capacity, _)| size <= capacity).unwrap_or(levels.last().unwrap()).1;
+                points.push((size, latency));
+            }
+            size *= 2;
+        }
+        points
+    }
+
+    #[test]
+    fn flat_curve_has_no_knee() {
+        assert!(detect(&curve(&[(usize::MAX, 1.2)])).is_empty());
+        assert!(detect(&[]).is_empty());
+    }
+
+    #[test]
+    fn one_knee() {
+        let knees = detect(&curve(&[(32 * KIB, 1.0), (usize::MAX, 80.0)]));
+        assert_eq!(knees, [Knee { size: 32 * KIB, latency: 1.0, next_latency: 80.0 }]);
+    }
+
+    #[test]
+    fn three_knees() {
+        let knees = detect(&curve(&[(48 * KIB, 1.2), (MIB, 4.0), (32 * MIB, 15.0), (usize::MAX, 90.0)]));
+        let sizes: Vec<usize> = knees.iter().map(|knee| knee.size).collect();
+        assert_eq!(sizes, [48 * KIB, MIB, 32 * MIB]);
+        let latencies: Vec<(f64, f64)> = knees.iter().map(|knee| (knee.latency, knee.next_latency)).collect();
+        assert_eq!(latencies, [(1.2, 4.0), (4.0, 15.0), (15.0, 90.0)]);
+    }
+
+    #[test]
+    fn gradual_climb_ends_at_the_midpoint() {
+        // 2 ns up to 64 KiB, then 6, 20 and 60 ns before a flat 60 ns
+        let mut points = curve(&[(64 * KIB, 2.0), (usize::MAX, 60.0)]);
+        let first = points.iter().position(|p| p.0 > 64 * KIB).unwrap();
+        points[first].1 = 6.0;
+        points[first + 1].1 = 20.0;
+        let knees = detect(&points);
+        assert_eq!(knees.len(), 1);
+        // The geometric mean of 2 and 60 is about 11 ns, passed between 6 and 20 ns
+        assert_eq!(knees[0].size, points[first].0);
+    }
+
+    #[test]
+    fn single_outlier_is_ignored() {
+        let mut points = curve(&[(usize::MAX, 1.2)]);
+        points[10].1 = 50.0;
+        assert!(detect(&points).is_empty());
+
+        let mut points = curve(&[(32 * KIB, 1.0), (usize::MAX, 80.0)]);
+        points[20].1 = 400.0;
+        let knees = detect(&points);
+        assert_eq!(knees.len(), 1);
+        assert_eq!(knees[0].size, 32 * KIB);
+    }
+}
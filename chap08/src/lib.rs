@@ -0,0 +1,11 @@
+//! Building blocks for the chapter 8 memory hierarchy experiments.
+
+pub mod affinity;
+pub mod bandwidth;
+pub mod buffer;
+pub mod chase;
+pub mod false_sharing;
+pub mod levels;
+pub mod numa;
+pub mod perf;
+pub mod topology;
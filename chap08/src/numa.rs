@@ -0,0 +1,134 @@
+//! NUMA nodes from sysfs and placement of buffers on them.
+//!
+//! A buffer is bound to a node with mbind(2) before its pages are touched,
+//! so the first touch allocates them there whichever CPU touches them, and
+//! get_mempolicy(2) tells where a page really ended up. Kernels built
+//! without NUMA support have no /sys/devices/system/node; the machine is
+//! then described as a single node holding every CPU.
+
+use crate::buffer::Buffer;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+
+const MPOL_BIND: libc::c_int = 2;
+const MPOL_MF_STRICT: libc::c_uint = 1 << 0;
+const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+const MPOL_F_NODE: libc::c_ulong = 1 << 0;
+const MPOL_F_ADDR: libc::c_ulong = 1 << 1;
+
+/// One NUMA node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+    /// MemTotal of the node in bytes, 0 for a node without memory
+    pub memory: u64,
+    /// Relative access cost from this node to each node, by node id
+    /// (10 for local), as reported by the firmware
+    pub distances: Vec<u32>,
+}
+
+impl Node {
+    /// Distance from this node to `other`, if the firmware reports it
+    pub fn distance(&self, other: usize) -> Option<u32> {
+        self.distances.get(other).copied()
+    }
+}
+
+/// Parse a CPU or node list such as "0-3,8-11"
+pub fn parse_list(list: &str) -> Option<Vec<usize>> {
+    let mut items = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => items.extend(first.parse::<usize>().ok()?..=last.parse::<usize>().ok()?),
+            None => items.push(range.parse().ok()?),
+        }
+    }
+    Some(items)
+}
+
+/// MemTotal in bytes from /proc/meminfo or a node's meminfo
+/// ("Node 0 MemTotal:       16318048 kB")
+fn mem_total(path: &Path) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .lines()
+        .find(|line| line.contains("MemTotal:"))
+        .and_then(|line| line.split_whitespace().rev().nth(1)?.parse::<u64>().ok())
+        .map(|kib| kib << 10)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No MemTotal in {}", path.display())))
+}
+
+fn read_node(dir: &Path, id: usize) -> io::Result<Node> {
+    let cpus = parse_list(&fs::read_to_string(dir.join("cpulist"))?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid cpulist of node {}", id)))?;
+    let memory = mem_total(&dir.join("meminfo"))?;
+    let distances = fs::read_to_string(dir.join("distance"))
+        .map(|distances| distances.split_whitespace().filter_map(|d| d.parse().ok()).collect())
+        .unwrap_or_default();
+    Ok(Node { id, cpus, memory, distances })
+}
+
+/// Online NUMA nodes by id, or a single node 0 with `all_cpus` on kernels
+/// without NUMA support
+pub fn nodes(all_cpus: &[usize]) -> io::Result<Vec<Node>> {
+    let online = match fs::read_to_string(Path::new(NODE_DIR).join("online")) {
+        Ok(online) => online,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let memory = mem_total(Path::new("/proc/meminfo"))?;
+            return Ok(vec![Node { id: 0, cpus: all_cpus.to_vec(), memory, distances: vec![10] }]);
+        }
+        Err(e) => return Err(e),
+    };
+    let ids = parse_list(&online).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid list of online nodes"))?;
+    ids.into_iter().map(|id| read_node(&Path::new(NODE_DIR).join(format!("node{}", id)), id)).collect()
+}
+
+/// Whether the kernel has NUMA support at all
+pub fn available() -> bool {
+    Path::new(NODE_DIR).exists()
+}
+
+/// Bind the pages of `buf` to `node`; pages already touched are moved
+pub fn bind(buf: &Buffer, node: usize) -> io::Result<()> {
+    let bits = usize::BITS as usize;
+    let mut mask = vec![0 as libc::c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            buf.as_mut_ptr(),
+            buf.len(),
+            MPOL_BIND,
+            mask.as_ptr(),
+            // The kernel ignores the last bit of maxnode
+            mask.len() * bits + 1,
+            MPOL_MF_STRICT | MPOL_MF_MOVE,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Node holding the first page of `buf`, which has to be touched already
+pub fn node_of(buf: &Buffer) -> io::Result<usize> {
+    let mut node: libc::c_int = -1;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_get_mempolicy,
+            &mut node as *mut libc::c_int,
+            std::ptr::null_mut::<libc::c_ulong>(),
+            0 as libc::c_ulong,
+            buf.as_mut_ptr(),
+            MPOL_F_NODE | MPOL_F_ADDR,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(node as usize)
+}
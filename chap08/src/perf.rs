@@ -0,0 +1,189 @@
+//! Hardware performance counters through perf_event_open(2).
+//!
+//! Each counter is opened on its own for the calling thread and counts user
+//! space only, which perf_event_paranoid allows up to 2. Counters the CPU or
+//! the hypervisor does not expose fail to open and are left out. When there
+//! are more counters than hardware registers the kernel multiplexes them,
+//! and the values are scaled by the share of time each one was counting.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+
+const PERF_COUNT_HW_CACHE_L1D: u64 = 0;
+const PERF_COUNT_HW_CACHE_DTLB: u64 = 3;
+const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+/// Bits of perf_event_attr.flags
+const FLAG_DISABLED: u64 = 1 << 0;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: libc::c_ulong = 0x2403;
+
+/// First version of struct perf_event_attr (PERF_ATTR_SIZE_VER0), which
+/// every kernel accepts; the later fields default to zero
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// Counted event, named as by perf-list(1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    CacheReferences,
+    CacheMisses,
+    L1dLoadMisses,
+    DtlbLoadMisses,
+    Instructions,
+    Cycles,
+}
+
+impl Event {
+    pub const ALL: [Event; 6] = [
+        Event::CacheReferences,
+        Event::CacheMisses,
+        Event::L1dLoadMisses,
+        Event::DtlbLoadMisses,
+        Event::Instructions,
+        Event::Cycles,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::CacheReferences => "cache-references",
+            Event::CacheMisses => "cache-misses",
+            Event::L1dLoadMisses => "L1-dcache-load-misses",
+            Event::DtlbLoadMisses => "dTLB-load-misses",
+            Event::Instructions => "instructions",
+            Event::Cycles => "cycles",
+        }
+    }
+
+    fn type_config(&self) -> (u32, u64) {
+        let cache_miss = |cache: u64| cache | PERF_COUNT_HW_CACHE_OP_READ << 8 | PERF_COUNT_HW_CACHE_RESULT_MISS << 16;
+        match self {
+            Event::CacheReferences => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_REFERENCES),
+            Event::CacheMisses => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES),
+            Event::L1dLoadMisses => (PERF_TYPE_HW_CACHE, cache_miss(PERF_COUNT_HW_CACHE_L1D)),
+            Event::DtlbLoadMisses => (PERF_TYPE_HW_CACHE, cache_miss(PERF_COUNT_HW_CACHE_DTLB)),
+            Event::Instructions => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+            Event::Cycles => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+        }
+    }
+
+    /// Open a disabled counter of this event for the calling thread
+    fn open(&self) -> io::Result<File> {
+        let (type_, config) = self.type_config();
+        let attr = PerfEventAttr {
+            type_,
+            size: size_of::<PerfEventAttr>() as u32,
+            config,
+            read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+            flags: FLAG_DISABLED | FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV,
+            ..Default::default()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                0 as libc::pid_t,
+                -1 as libc::c_int,
+                -1 as libc::c_int,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+    }
+}
+
+/// Set of counters started and stopped together
+pub struct Counters {
+    counters: Vec<(Event, File)>,
+    /// Events that could not be opened, with the reason
+    pub unavailable: Vec<(Event, io::Error)>,
+}
+
+impl Counters {
+    /// Open every event the system allows
+    pub fn open() -> Counters {
+        let mut counters = Vec::new();
+        let mut unavailable = Vec::new();
+        for event in Event::ALL {
+            match event.open() {
+                Ok(file) => counters.push((event, file)),
+                Err(e) => unavailable.push((event, e)),
+            }
+        }
+        Counters { counters, unavailable }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Events being counted, in a stable order
+    pub fn events(&self) -> Vec<Event> {
+        self.counters.iter().map(|(event, _)| *event).collect()
+    }
+
+    fn ioctl_all(&self, request: libc::c_ulong) -> io::Result<()> {
+        for (_, file) in &self.counters {
+            if unsafe { libc::ioctl(file.as_raw_fd(), request, 0) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero and enable every counter
+    pub fn start(&self) -> io::Result<()> {
+        self.ioctl_all(PERF_EVENT_IOC_RESET)?;
+        self.ioctl_all(PERF_EVENT_IOC_ENABLE)
+    }
+
+    /// Disable every counter and read it, scaled for multiplexing; None for
+    /// a counter that never got a hardware register
+    pub fn stop(&self) -> io::Result<Vec<(Event, Option<u64>)>> {
+        self.ioctl_all(PERF_EVENT_IOC_DISABLE)?;
+        let mut values = Vec::with_capacity(self.counters.len());
+        for (event, mut file) in self.counters.iter().map(|(event, file)| (*event, file)) {
+            let mut raw = [0u8; 24];
+            file.read_exact(&mut raw)?;
+            let field = |i: usize| u64::from_ne_bytes(raw[i * 8..i * 8 + 8].try_into().unwrap());
+            let (value, enabled, running) = (field(0), field(1), field(2));
+            let scaled = (running > 0).then(|| (value as f64 * enabled as f64 / running as f64) as u64);
+            values.push((event, scaled));
+        }
+        Ok(values)
+    }
+}
@@ -0,0 +1,89 @@
+//! Cache hierarchy of a CPU as described by sysfs.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Cache line size assumed for strides and padding, the coherency_line_size
+/// of x86-64 and most arm64 CPUs
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// One cache of /sys/devices/system/cpu/cpuN/cache/indexM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    pub level: u32,
+    /// "Data", "Instruction" or "Unified"
+    pub kind: String,
+    /// Capacity in bytes
+    pub size: usize,
+    pub line_size: usize,
+}
+
+impl Cache {
+    /// Whether data loads go through this cache
+    pub fn holds_data(&self) -> bool {
+        self.kind != "Instruction"
+    }
+
+    /// Short name such as "L1d" or "L3"
+    pub fn name(&self) -> String {
+        match self.kind.as_str() {
+            "Data" => format!("L{}d", self.level),
+            "Instruction" => format!("L{}i", self.level),
+            _ => format!("L{}", self.level),
+        }
+    }
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Parse a sysfs cache size such as "48K" or "2048K"
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let value: usize = digits.parse().ok()?;
+    match unit {
+        "" => Some(value),
+        "K" => Some(value << 10),
+        "M" => Some(value << 20),
+        "G" => Some(value << 30),
+        _ => None,
+    }
+}
+
+/// Caches of `cpu` ordered by level, instruction caches included
+pub fn caches(cpu: usize) -> io::Result<Vec<Cache>> {
+    let dir = format!("/sys/devices/system/cpu/cpu{}/cache", cpu);
+    let mut caches = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("index")) {
+            continue;
+        }
+        let size = read_trimmed(&path.join("size"))?;
+        caches.push(Cache {
+            level: read_trimmed(&path.join("level"))?.parse().map_err(|_| io::Error::other("Invalid cache level"))?,
+            kind: read_trimmed(&path.join("type"))?,
+            size: parse_size(&size).ok_or_else(|| io::Error::other(format!("Invalid cache size: {}", size)))?,
+            line_size: read_trimmed(&path.join("coherency_line_size"))?.parse().unwrap_or(0),
+        });
+    }
+    caches.sort_by(|a, b| (a.level, &a.kind).cmp(&(b.level, &b.kind)));
+    Ok(caches)
+}
+
+/// Human readable size in KiB, MiB or GiB
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [(usize, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+    for (unit, name) in UNITS {
+        if bytes >= unit {
+            let value = bytes as f64 / unit as f64;
+            return if value.fract() == 0.0 { format!("{} {}", value, name) } else { format!("{:.1} {}", value, name) };
+        }
+    }
+    format!("{} B", bytes)
+}
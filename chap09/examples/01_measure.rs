@@ -1,192 +1,357 @@
+use chap09::blockdev::{BlockDevError, BlockDevice, Sysfs, TargetKind};
+use chap09::cgroup::{IoCost, IoStat, Pressure, Session};
+use chap09::histogram::LatencyStats;
+use chap09::iostat::{IostatSample, IostatSeries, Sampler};
+use chap09::loadgen::{self, Engine, IoMode, JobSpec, Pattern, RwMode};
+use chap09::loopdev::{self, Sandbox, SandboxSpec};
+use chap09::pagecache::{self, CacheSnapshot, CacheUsage};
+use chap09::report::{OutputFormat, Report, Table};
+use chap09::results::{self, Measurement, Record, ResultStore, Summary, SystemInfo, Verdict};
+use chap09::scenario::{self, DeviceEntry, PlotKind, RunParams, Scenario, Workload};
+use chap09::stats;
+use chap09::trace::Trace;
+use chap09::tunables::{self, TunableGuard};
+use plotters::coord::Shift;
+use plotters::element::ErrorBarOrientV;
 use plotters::prelude::*;
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::env;
 use std::error::Error;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
-use std::process::Command;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Scenario file used when none is given on the command line
+const DEFAULT_SCENARIO: &str = "scenario.json";
+
+/// Directory holding one subdirectory of raw outputs and plots per run
+const RUNS_DIR: &str = "chap09-runs";
+
+/// Test file created in directory targets
+const TEST_FILE: &str = "chap09-test.dat";
+
+/// Significance level of `compare`
+const DEFAULT_ALPHA: f64 = 0.05;
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!("Usage: {} [--fio] [--store FILE] [--format LIST] [scenario.json]", prog_name);
+    eprintln!("       {} compare [--store FILE] [--alpha A] [RUN_BEFORE RUN_AFTER]", prog_name);
+    eprintln!();
+    eprintln!("  Run the benchmark matrix described by the scenario file (default: {}).", DEFAULT_SCENARIO);
+    eprintln!("  Results are appended to the result store; raw outputs and plots go to {}/<run>.", RUNS_DIR);
+    eprintln!();
+    eprintln!("  compare lists the runs in the store and compares two of them (default: the last two).");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --fio: Use the external fio binary instead of the built-in load generator");
+    eprintln!("  --store FILE: Result store (default: {})", results::DEFAULT_STORE);
+    eprintln!("  --format LIST: Plot formats out of png, svg and html, e.g. png,html (default: the scenario's)");
+    eprintln!("  --alpha A: Significance level of the comparison (default: {})", DEFAULT_ALPHA);
+    std::process::exit(1);
+}
 
 /// COnfiguration structure for block device benchmark
 struct Config {
-    device: String,
-    device_name: String,
+    scenario: Scenario,
+    /// Use the external fio binary instead of the built-in load generator
+    use_fio: bool,
+    store: ResultStore,
+    /// Id of this invocation in the store, see `results::new_run_id`
+    run: u64,
+    system: SystemInfo,
+    /// Where raw outputs and plots of this run are written
+    run_dir: PathBuf,
 }
 
-/// Load configuration from config fie
-fn load_config() -> Result<Config, Box<dyn Error>> {
-    let config_str = fs::read_to_string("config")?;
-    let lines: Vec<&str> = config_str.lines().collect();
-
-    if lines.len() < 2 {
-        return Err("Invalid config file format".into());
-    }
+/// Load configuration from the scenario file
+fn load_config(scenario_path: &str, use_fio: bool, store: ResultStore) -> Result<Config, Box<dyn Error>> {
+    let scenario = Scenario::load(scenario_path)?;
+
+    // Claiming the run directory keeps the id unique among concurrent invocations
+    fs::create_dir_all(RUNS_DIR)?;
+    let mut run = results::new_run_id();
+    let run_dir = loop {
+        let dir = Path::new(RUNS_DIR).join(run.to_string());
+        match fs::create_dir(&dir) {
+            Ok(()) => break dir,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => run += 1,
+            Err(e) => return Err(format!("Failed to create {}: {}", dir.display(), e).into()),
+        }
+    };
 
     Ok(Config {
-        device: lines[0].trim().to_string(),
-        device_name: lines[1].trim().to_string(),
-     })
+        scenario,
+        use_fio,
+        store,
+        run,
+        system: SystemInfo::current(),
+        run_dir,
+    })
 }
 
-/// Check if the device exists and is a block device
-fn validate_device(device: &str) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(device);
-
-    if !path.exists() {
-        return Err(format!("Device {} does not exist", device).into());
-    }
+/// A plot rendered for the HTML report
+struct Chart {
+    /// Device and workload the plot belongs to
+    device_name: String,
+    workload: String,
+    name: String,
+    svg: String,
+}
 
-    // Check if it's a block device using stat
-    let output = Command::new("stat")
-        .arg("-c")
-        .arg("%F")
-        .arg(device)
-        .output()?;
+/// Where the plots of this run go
+struct Output {
+    dir: PathBuf,
+    formats: Vec<OutputFormat>,
+    /// Plots collected for the HTML report
+    charts: Vec<Chart>,
+}
 
-    let file_type = String::from_utf8_lossy(&output.stdout);
+/// Draw a plot in every requested format
+///
+/// `$draw` is expanded once per backend with `$root` bound to its drawing
+/// area, so it may call any function generic over `DrawingBackend`.
+macro_rules! render {
+    ($output:expr, ($device_name:expr, $workload:expr), $name:expr, $size:expr, |$root:ident| $draw:block) => {{
+        let output: &mut Output = $output;
+        let name: String = $name;
+        for format in output.formats.clone() {
+            match format {
+                OutputFormat::Png => {
+                    let file = output.dir.join(format!("{}.png", name));
+                    let $root = BitMapBackend::new(&file, $size).into_drawing_area();
+                    $draw
+                    $root.present()?;
+                    println!("Generated: {}", file.display());
+                }
+                OutputFormat::Svg => {
+                    let file = output.dir.join(format!("{}.svg", name));
+                    let $root = SVGBackend::new(&file, $size).into_drawing_area();
+                    $draw
+                    $root.present()?;
+                    println!("Generated: {}", file.display());
+                }
+                OutputFormat::Html => {
+                    let mut svg = String::new();
+                    {
+                        let $root = SVGBackend::with_string(&mut svg, $size).into_drawing_area();
+                        $draw
+                        $root.present()?;
+                    }
+                    output.charts.push(Chart {
+                        device_name: $device_name.to_string(),
+                        workload: $workload.to_string(),
+                        name: name.clone(),
+                        svg,
+                    });
+                }
+            }
+        }
+    }};
+}
 
-    if !file_type.contains("block special file") && !file_type.contains("regular file") {
-        return Err(format!("{} is not a block device", device).into());
+/// Create the loop device sandbox of a device entry and return the entry to benchmark
+fn setup_sandbox(device: &DeviceEntry, spec: &SandboxSpec) -> Result<(Sandbox, DeviceEntry), Box<dyn Error>> {
+    println!("\nCreating sandbox for {} ({} MiB)...", device.name, spec.size_mb);
+    let sandbox = Sandbox::create(spec)?;
+    println!(
+        "Sandbox: {} ({} direct I/O on the backing file)",
+        sandbox.device.path.display(),
+        if sandbox.device.direct_io() { "with" } else { "without" }
+    );
+    if let Some(fs) = &spec.fs {
+        println!("Formatted as {} and mounted on {}", fs, sandbox.target().display());
     }
 
-    println!("Device type: {}", file_type.trim());
-
-    Ok(())
+    let entry = DeviceEntry {
+        path: sandbox.target().to_string_lossy().into_owned(),
+        // Leave room for the filesystem's own metadata
+        file_size_mb: device.file_size_mb.or(Some(spec.size_mb / 2)),
+        ..device.clone()
+    };
+    Ok((sandbox, entry))
 }
 
-/// Get current I/O scheduler for the device
-fn get_scheduler(device: &str) -> Result<String, Box<dyn Error>> {
-    // Check if it's a regular file
-    let output = Command::new("stat")
-        .arg("-c")
-        .arg("%F")
-        .arg(device)
-        .output()?;
-
-    let file_type = String::from_utf8_lossy(&output.stdout);
-
-    if file_type.contains("regular file") {
-        return Ok("none".to_string());  // Regular files don't have schedulers
+/// Lay out the test file of a directory target and return the entry to benchmark
+///
+/// Other targets are returned unchanged. The test file is kept for the next
+/// invocation and only rewritten when its size changes.
+fn prepare_target(device: &DeviceEntry) -> Result<DeviceEntry, Box<dyn Error>> {
+    let dir = Path::new(&device.path);
+    if !dir.is_dir() {
+        return Ok(device.clone());
     }
 
-    // Extract device name (e.g., sda from /dev/sda)
-    let dev_name = device.trim_start_matches("/dev/");
-    let scheduler_path = format!("/sys/block/{}/queue/scheduler", dev_name);
+    let path = dir.join(TEST_FILE);
+    let size = device.file_size_mb.unwrap_or(scenario::DEFAULT_FILE_SIZE_MB) << 20;
+    if fs::metadata(&path).map_or(true, |meta| meta.len() != size) {
+        println!("Laying out {} ({} MiB)", path.display(), size >> 20);
+        // Written out rather than sparse, so reads hit real blocks
+        let mut file = File::create(&path)?;
+        let chunk = vec![0xa5u8; 1 << 20];
+        for _ in 0..size >> 20 {
+            file.write_all(&chunk)?;
+        }
+        file.sync_all()?;
+    }
 
-    let content = fs::read_to_string(&scheduler_path)?;
+    Ok(DeviceEntry { path: path.to_string_lossy().into_owned(), ..device.clone() })
+}
 
-    // Extract current scheduler (matked wwith brackets)
-    for part in content.split_whitespace() {
-        if part.starts_with('[') && part.ends_with(']') {
-            return Ok(part.trim_matches(|c| c == '[' || c== ']').to_string());
+/// Resolve the benchmark target to its block device and print what was found
+///
+/// Returns `None` for files on a filesystem without a block device (tmpfs and
+/// the like), which have no tunables.
+fn validate_device(device: &str) -> Result<Option<BlockDevice>, Box<dyn Error>> {
+    let bdev = match Sysfs::new().resolve(device) {
+        Ok(bdev) => bdev,
+        Err(e @ BlockDevError::NoBackingDevice { .. }) => {
+            println!("{}; tunables are left alone", e);
+            return Ok(None);
         }
+        Err(e) => return Err(e.into()),
+    };
+
+    let kind = match bdev.kind {
+        TargetKind::Disk => "disk",
+        TargetKind::Partition => "partition",
+        TargetKind::File => "regular file",
+    };
+    println!("Device type: {} (queue of {})", kind, bdev.name);
+    if let Some(model) = bdev.model() {
+        println!("Model: {}", model);
+    }
+    println!("Size: {} bytes, logical block size: {} bytes", bdev.size()?, bdev.logical_block_size()?);
+    let slaves = bdev.slaves()?;
+    if !slaves.is_empty() {
+        println!("Stacked on: {}", slaves.join(", "));
     }
 
-    Err("Could not determine current scheduler".into())
+    Ok(Some(bdev))
 }
 
 /// Set I/O scheduler for the device
-fn set_scheduler(device: &str, scheduler: &str) -> Result<(), Box<dyn Error>> {
-    // Check if it's a regular file
-    let output = Command::new("stat")
-        .arg("-c")
-        .arg("%F")
-        .arg(device)
-        .output()?;
-
-    let file_type = String::from_utf8_lossy(&output.stdout);
-
-    if file_type.contains("regular file") {
-        println!("Skipping scheduler setting for regular file");
-        return Ok(());
+///
+/// Stacked devices usually have no scheduler to choose, so it is set on their
+/// member disks instead.
+fn set_scheduler(bdev: &BlockDevice, scheduler: &str) -> Result<(), Box<dyn Error>> {
+    match bdev.set_scheduler(scheduler) {
+        Err(BlockDevError::InvalidValue { .. }) if !bdev.slaves()?.is_empty() => {
+            for disk in bdev.backing_disks()? {
+                disk.set_scheduler(scheduler)?;
+                println!("Set scheduler of {} to: {}", disk.name, scheduler);
+            }
+        }
+        result => {
+            result?;
+            println!("Set scheduler to: {}", scheduler);
+        }
     }
-
-    let dev_name = device.trim_start_matches("/dev/");
-    let scheduler_path = format!("sys/block/{}/queue/scheduler", dev_name);
-
-    let mut file = File::create(&scheduler_path)?;
-    file.write_all(scheduler.as_bytes())?;
-
-    println!("Set scheduler to: {}", scheduler);
     Ok(())
 }
 
-/// Get current read-ahead value for the device
-fn get_read_ahead(device: &str) -> Result<u32, Box<dyn Error>> {
-    //Check if it's a regular file
-    let output = Command::new("stat")
-        .arg("-c")
-        .arg("%F")
-        .arg(device)
-        .output()?;
+/// Set read-ahead value (in 512-byte sectors) for the device
+fn set_read_ahead(bdev: &BlockDevice, ra_value: u32) -> Result<(), Box<dyn Error>> {
+    bdev.set_read_ahead(ra_value)?;
+    println!("Set read-ahead to: {}", ra_value);
+    Ok(())
+}
 
-    let file_type = String::from_utf8_lossy(&output.stdout);
-    if file_type.contains("regular file") {
-        return Ok(0); // Regular file don't have read-ahead
-    }
+fn print_result(result: &Measurement) {
+    let lat = &result.latency;
+    println!(
+        "  Latency (usec): mean={:.2} p50={:.2} p90={:.2} p99={:.2} p99.9={:.2} max={:.2}",
+        lat.mean, lat.p50, lat.p90, lat.p99, lat.p999, lat.max
+    );
+    println!("  IOPS: {:.2}, BW: {:.2} MiB/s", result.iops, result.bw_bytes / 1024.0 / 1024.0);
+}
 
-    let output = Command::new("blockdev")
-        .arg("--getra")
-        .arg(device)
-        .output()?;
+/// Print mean, standard deviation and confidence interval of repeated runs
+fn print_summary(summary: &Summary) {
+    let line = |name: &str, mean: f64, stddev: f64, ci: f64| {
+        if summary.n < 2 {
+            println!("    {}: {:.2}", name, mean);
+        } else {
+            println!("    {}: {:.2} ± {:.2} ({:.1}%), stddev {:.2}", name, mean, ci, ci / mean * 100.0, stddev);
+        }
+    };
+    let (m, s, ci) = (&summary.mean, &summary.stddev, &summary.ci);
 
-    let ra_str = String::from_utf8_lossy(&output.stdout);
-    let ra = ra_str.trim().parse::<u32>()?;
+    println!("  Summary of {} repetitions ({:.0}% CI):", summary.n, results::CONFIDENCE * 100.0);
+    line("IOPS", m.iops, s.iops, ci.iops);
+    line("BW (MiB/s)", m.bw_bytes / 1048576.0, s.bw_bytes / 1048576.0, ci.bw_bytes / 1048576.0);
+    line("Latency p50 (usec)", m.latency.p50, s.latency.p50, ci.latency.p50);
+    line("Latency p99 (usec)", m.latency.p99, s.latency.p99, ci.latency.p99);
+}
 
-    Ok(ra)
+/// Print how much page cache a run consumed
+fn print_page_cache(usage: &CacheUsage) {
+    let mib = |bytes: f64| bytes / 1048576.0;
+    println!(
+        "  Page cache: target {:.1} -> {:.1} MiB resident, system {:+.1} MiB",
+        mib(usage.target_before as f64), mib(usage.target_after as f64), mib(usage.system_delta as f64)
+    );
 }
 
-/// Set read-ahead value for the device
-fn set_read_ahead(device: &str, ra_value: u32) -> Result<(), Box<dyn Error>> {
-    //Check if it's a regular file
-    let output = Command::new("stat")
-        .arg("-c")
-        .arg("%F")
-        .arg(device)
-        .output()?;
+/// Print the device-side view of a run
+fn print_iostat(total: &IostatSample) {
+    println!(
+        "  Device: util {:.1}%, aqu-sz {:.2}, r_await {:.3} ms, w_await {:.3} ms, rrqm/s {:.1}, wrqm/s {:.1}",
+        total.util, total.aqu_sz, total.r_await, total.w_await, total.rrqm_s, total.wrqm_s
+    );
+}
 
-    let file_type = String::from_utf8_lossy(&output.stdout);
-    if file_type.contains("regular file") {
-        println!("Skipping read-ahead setting for regular file");
-        return Ok(()); // Regular file don't have read-ahead
+/// Run fio benchmark and extract results
+fn run_fio(run: &RunParams, output_file: &Path) -> Result<Measurement, Box<dyn Error>> {
+    println!(
+        "Running fio: {} ({}, {}, bs={}, iodepth={}) with {}...",
+        run.rw, run.engine.name(), run.io_mode.name(), run.block_size, run.queue_depth, run.num_jobs
+    );
+
+    if run.access.trace.is_some() {
+        return Err("Trace replay is only supported by the native load generator".into());
     }
 
-    let status = Command::new("blockdev")
-        .arg("--setra")
-        .arg(ra_value.to_string())
-        .arg(device)
-        .status()?;
-
-    if !status.success() {
-        return Err(format!("Failed to set read-ahead to {}", ra_value).into());
+    let mut fio = Command::new("fio");
+    let access = &run.access;
+    if let Some(read_pct) = access.read_pct {
+        fio.arg(format!("--rwmixread={}", read_pct));
     }
-
-    println!("Set read-ahead to: {}", ra_value);
-    Ok(())
-}
-
-/// Run fio benchmark and extract results
-fn run_fio (
-    device: &str,
-    rw_type: &str,
-    num_jobs: u32,
-    output_file: &str,
-) -> Result<(f64, f64), Box<dyn Error>> {
-    println!("Running fio: {} with {}...", rw_type, num_jobs);
+    if access.distribution.is_some() {
+        fio.arg(format!("--random_distribution={}", access.distribution()?.fio_name()));
+    }
+    if let Some(fsync) = access.fsync {
+        fio.arg(format!("--fsync={}", fsync));
+    }
+    match run.io_mode {
+        IoMode::Direct => fio.arg("--direct=1"),
+        IoMode::Buffered | IoMode::Mmap => fio.arg("--direct=0"),
+        IoMode::Dsync => fio.args(["--direct=0", "--sync=dsync"]),
+    };
+    // fio implements mmap access as an engine of its own
+    let ioengine = match run.io_mode {
+        IoMode::Mmap => "mmap",
+        _ => run.engine.fio_name(),
+    };
 
     // Run fio benchmark
-    let status = Command::new("fio")
+    let status = fio
         .arg("--name=test")
-        .arg(format!("--filename={}", device))
-        .arg("--ioengine=libaio")
-        .arg(format!("--rw={}", rw_type))
-        .arg("--bs=4k")
-        .arg("--direct=1")
-        .arg(format!("--numjobs={}", num_jobs))
+        .arg(format!("--filename={}", run.device))
+        .arg(format!("--ioengine={}", ioengine))
+        .arg(format!("--iodepth={}", run.queue_depth))
+        .arg(format!("--rw={}", run.rw))
+        .arg(format!("--bs={}", run.block_size))
+        .arg(format!("--numjobs={}", run.num_jobs))
         .arg("--time_based")
-        .arg("--runtime=60")
+        .arg(format!("--runtime={}", run.runtime_secs))
         .arg("--group_reporting")
+        .arg("--unified_rw_reporting=1")
+        .arg("--percentile_list=50:90:99:99.9")
         .arg("--output-format=json")
-        .arg(format!("--output={}", output_file))
+        .arg(format!("--output={}", output_file.display()))
         .status()?;
 
     if !status.success() {
@@ -197,457 +362,1467 @@ fn run_fio (
     let json_str = fs::read_to_string(output_file)?;
     let json: Value = serde_json::from_str(&json_str)?;
 
-    // Extract latency and IPOS based on rw_type
-    let (latency, iops) = if rw_type == "read" {
-        let lat_ns = json["jobs"][0]["read"]["lat_ns"]["mean"]
-            .as_f64()
-            .unwrap_or(0.0);
-        let iops_val = json["jobs"][0]["read"]["lat_ns"]["mean"]
-            .as_f64()
-            .unwrap_or(0.0);
-        (lat_ns / 1000.0, iops_val)
-    } else {
-        let lat_ns = json["jobs"][0]["write"]["lat_ns"]["mean"]
-            .as_f64()
-            .unwrap_or(0.0);
-        let iops_val = json["jobs"][0]["write"]["lat_ns"]["mean"]
-            .as_f64()
-            .unwrap_or(0.0);
-        (lat_ns / 1000.0, iops_val)
+    // Reads and writes are reported together, whatever the rw type
+    let stats = &json["jobs"][0]["mixed"];
+    let usec = |value: &Value| value.as_f64().unwrap_or(0.0) / 1000.0;
+    let percentiles = &stats["clat_ns"]["percentile"];
+
+    let result = Measurement {
+        latency: LatencyStats {
+            mean: usec(&stats["lat_ns"]["mean"]),
+            p50: usec(&percentiles["50.000000"]),
+            p90: usec(&percentiles["90.000000"]),
+            p99: usec(&percentiles["99.000000"]),
+            p999: usec(&percentiles["99.900000"]),
+            max: usec(&stats["lat_ns"]["max"]),
+        },
+        iops: stats["iops"].as_f64().unwrap_or(0.0),
+        bw_bytes: stats["bw_bytes"].as_f64().unwrap_or(0.0),
     };
 
-    println!("  Latency: {:.2} usec, IOPS: {:.2}", latency, iops);
+    print_result(&result);
 
-    Ok((latency, iops))
-    
+    Ok(result)
 }
 
-/// Run read benchmark with different and read-ahead values
-fn benchmark_read(device: &str, device_name: &str) -> Result<(), Box<dyn Error>> {
-    println!("\n=== Starting Read Benchmarks ===\n");
+/// Run benchmark with the built-in load generator and extract results
+fn run_native(run: &RunParams, output_file: &Path) -> Result<Measurement, Box<dyn Error>> {
+    println!(
+        "Running native benchmark: {} ({}, {}, bs={}, iodepth={}) with {}...",
+        run.rw, run.engine.name(), run.io_mode.name(), run.block_size, run.queue_depth, run.num_jobs
+    );
+
+    let access = &run.access;
+    let (rw, pattern) = match &access.trace {
+        Some(_) => (RwMode::Mixed, Pattern::Sequential),
+        None => loadgen::parse_rw(&run.rw).ok_or_else(|| format!("Unsupported I/O type: {}", run.rw))?,
+    };
+    let mut spec = JobSpec::new(&run.device, rw, pattern);
+    spec.engine = run.engine;
+    spec.io_mode = run.io_mode;
+    spec.block_size = run.block_size;
+    spec.num_jobs = run.num_jobs as usize;
+    spec.queue_depth = run.queue_depth as usize;
+    spec.runtime = Duration::from_secs(run.runtime_secs);
+    spec.read_pct = access.read_pct.unwrap_or(spec.read_pct);
+    spec.distribution = access.distribution()?;
+    spec.fsync_every = access.fsync;
+    if let Some(path) = &access.trace {
+        spec.trace = Some(Arc::new(Trace::load(path)?));
+        spec.trace_timing = !access.trace_fast;
+    }
 
-    let schedules = ["mq-deadline", "none"];
-    let read_ahead_values = [0, 256];
+    let job = loadgen::run(&spec)?;
+    if rw == RwMode::Mixed {
+        println!(
+            "  Reads: {}, writes: {}",
+            job.read_histogram.count(), job.write_histogram.count()
+        );
+    }
+    if job.fsync_histogram.count() > 0 {
+        let fsync = job.fsync_histogram.summary();
+        println!(
+            "  fsync: {} calls, p50={:.2} p99={:.2} usec",
+            job.fsync_histogram.count(), fsync.p50, fsync.p99
+        );
+    }
 
-    for &scheduler in &schedules {
-        set_scheduler(device, &scheduler)?;
+    // Keep the complete result, including the latency histogram, like fio's JSON output
+    fs::write(output_file, serde_json::to_string_pretty(&job.to_json(&spec))?)?;
 
-        for &ra in &read_ahead_values {
-            set_read_ahead(device, ra)?;
+    let result = Measurement {
+        latency: job.latency,
+        iops: job.iops,
+        bw_bytes: job.bw_bytes,
+    };
 
-            let output_json = format!("read-{}-{}-{}.json", device_name, ra, scheduler);
-            let output_txt = format!("read-{}-{}-{}.txt", device_name, ra, scheduler);
+    print_result(&result);
 
-            // Run benchmark
-            let (latency, iops) = run_fio(device, "read", 1, &output_json)?;
+    Ok(result)
+}
 
-            // Save extracted data to text file
-            let mut file = File::create(&output_txt)?;
-            writeln!(file, "{} {}", latency, iops)?;
+/// Run one benchmark with the configured load generator
+fn run_benchmark(config: &Config, run: &RunParams, output_file: &Path) -> Result<Measurement, Box<dyn Error>> {
+    if config.use_fio {
+        run_fio(run, output_file)
+    } else {
+        run_native(run, output_file)
+    }
+}
+
+/// Entry point of the processes started by `run_groups`: run one benchmark
+/// and leave its measurement in `result_file`
+fn run_worker(use_fio: bool, run_json: &str, output_file: &Path, result_file: &Path) -> Result<(), Box<dyn Error>> {
+    let run: RunParams = serde_json::from_str(run_json)?;
+    let result = if use_fio { run_fio(&run, output_file)? } else { run_native(&run, output_file)? };
+    fs::write(result_file, serde_json::to_string(&result)?)?;
+    Ok(())
+}
+
+/// IOPS the io.max limits allow a group, if they cap every direction it uses
+fn group_iops_limit(run: &RunParams) -> Option<f64> {
+    let (rw, _) = loadgen::parse_rw(&run.rw)?;
+    run.cgroup.as_ref()?.limits.iops_limit(run.block_size, rw != RwMode::Write, rw != RwMode::Read)
+}
+
+/// Print the measurements and the kernel's accounting of the groups of a batch
+fn print_groups(batch: &[RunParams], results: &[Measurement], usage: &[(IoStat, Pressure)]) {
+    let mib = |bytes: u64| bytes as f64 / 1048576.0;
+    for ((run, result), (stat, pressure)) in batch.iter().zip(results).zip(usage) {
+        let Some(group) = &run.cgroup else {
+            continue;
+        };
+        let limit = group_iops_limit(run).map_or(String::new(), |limit| format!(" ({:.0}% of the {:.0} IOPS limit)", result.iops / limit * 100.0, limit));
+        println!("  Group {}, {}:", group.label(), run.rw);
+        println!(
+            "    IOPS: {:.2}{}, BW: {:.2} MiB/s, latency p50={:.2} p99={:.2} usec",
+            result.iops, limit, result.bw_bytes / 1048576.0, result.latency.p50, result.latency.p99
+        );
+        println!(
+            "    io.stat: read {:.1} MiB in {} I/Os, written {:.1} MiB in {} I/Os; I/O pressure some={:.2}s full={:.2}s",
+            mib(stat.rbytes), stat.rios, mib(stat.wbytes), stat.wios,
+            pressure.some_usec as f64 / 1e6, pressure.full_usec as f64 / 1e6
+        );
+    }
+    let shares: Vec<f64> = batch.iter()
+        .zip(results)
+        .filter_map(|(run, result)| Some(result.iops / run.cgroup.as_ref()?.weight() as f64))
+        .collect();
+    println!("  Fairness (Jain's index of IOPS per weight): {:.3}", stats::jain_index(&shares));
+}
 
-            println!("Saved results to: {}", output_txt);
+/// Run the groups of a batch at the same time, each in a process of its own
+/// in its cgroup, and return their measurements in batch order
+fn run_groups(
+    config: &Config,
+    session: &mut Session,
+    (major, minor): (u32, u32),
+    batch: &[RunParams],
+    suffix: &str,
+) -> Result<Vec<Measurement>, Box<dyn Error>> {
+    let mut workers = Vec::new();
+    for run in batch {
+        let group = run.cgroup.as_ref().ok_or("Run without a group among grouped runs")?;
+        let cgroup = session.add_group(group, major, minor)?;
+
+        let name = format!("{}{}", run.id(), suffix);
+        let result_file = config.run_dir.join(format!("{}-result.json", name));
+        let log_file = config.run_dir.join(format!("{}.log", name));
+        let mut worker = Command::new(env::current_exe()?);
+        worker.arg("worker");
+        if config.use_fio {
+            worker.arg("--fio");
+        }
+        worker
+            .arg(serde_json::to_string(run)?)
+            .arg(config.run_dir.join(format!("{}.json", name)))
+            .arg(&result_file)
+            .stdout(File::create(&log_file)?);
+        cgroup.join_on_exec(&mut worker);
+
+        println!("Starting group {} ({}), output in {}", group.label(), run.rw, log_file.display());
+        match worker.spawn() {
+            Ok(child) => workers.push((child, result_file, log_file)),
+            Err(e) => {
+                for (child, _, _) in &mut workers {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+                session.clear()?;
+                return Err(format!("Failed to start the benchmark of group {}: {}", group.name, e).into());
+            }
         }
     }
 
-    Ok(())
+    // Wait for every worker before reporting a failure or reading a result, so none outlives the groups
+    let statuses: Vec<io::Result<ExitStatus>> = workers.iter_mut().map(|(child, _, _)| child.wait()).collect();
+    let mut failure = None;
+    for (status, (_, _, log_file)) in statuses.into_iter().zip(&workers) {
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => failure = failure.or(Some(format!("Benchmark of a group failed, see {}", log_file.display()))),
+            Err(e) => failure = failure.or(Some(format!("Failed to wait for the benchmark of a group: {}", e))),
+        }
+    }
+    if let Some(failure) = failure {
+        session.clear()?;
+        return Err(failure.into());
+    }
+    let mut results = Vec::new();
+    for (_, result_file, _) in &workers {
+        results.push(serde_json::from_str(&fs::read_to_string(result_file)?)?);
+        fs::remove_file(result_file)?;
+    }
+
+    let usage = session.groups()
+        .iter()
+        .map(|cgroup| Ok((cgroup.io_stat(major, minor)?, cgroup.io_pressure()?)))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    session.clear()?;
+
+    print_groups(batch, &results, &usage);
+    let groups: Vec<Value> = batch.iter()
+        .zip(&results)
+        .zip(&usage)
+        .map(|((run, result), (stat, pressure))| json!({ "group": run.cgroup, "rw": run.rw, "result": result, "io_stat": stat, "io_pressure": pressure }))
+        .collect();
+    let groups_json = config.run_dir.join(format!("{}{}-cgroups.json", batch[0].batch().id(), suffix));
+    fs::write(groups_json, serde_json::to_string_pretty(&groups)?)?;
+
+    Ok(results)
 }
 
-/// Run random write benchmarks with different schedulers and job counts
-fn benchmark_randwrite(device: &str, device_name: &str) -> Result<(), Box<dyn Error>> {
-    println!("\n=== Starting Random Write Benchmarks ===\n");
+/// Run the whole scenario matrix on one device
+fn benchmark_device(
+    config: &Config,
+    output: &mut Output,
+    device: &DeviceEntry,
+    bdev: Option<&BlockDevice>,
+    journal: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let runs = config.scenario.expand(device)?;
+    println!("\n=== Starting {} Benchmarks on {} ===\n", runs.len(), device.path);
+
+    let scenario = &config.scenario;
+
+    // Tenants of grouped workloads get cgroups with limits on the disk; io.cost
+    // stays enabled until its guard is dropped with the rest of this device's state
+    let grouped = runs.iter().any(|run| run.cgroup.is_some());
+    let (mut session, disk, _iocost) = if grouped {
+        let bdev = bdev.ok_or_else(|| format!("Groups need a block device, {} has none", device.path))?;
+        let disk = bdev.disk_devnum()?;
+        let session = Session::create()?;
+        let iocost = if scenario.iocost {
+            let iocost = IoCost::enable(disk.0, disk.1, journal)?;
+            println!("Enabled io.cost on {} ({}:{})", bdev.name, disk.0, disk.1);
+            Some(iocost)
+        } else {
+            None
+        };
+        (Some(session), disk, iocost)
+    } else {
+        (None, (0, 0), None)
+    };
 
-    let schedulers = ["mq-deadline", "none"];
-    let num_jobs_list = vec![1, 2, 4, 8, 16, 32, 64];
+    // The matrix is ordered by scheduler and read-ahead, so only touch them on change
+    let mut current_scheduler: Option<&str> = None;
+    let mut current_ra: Option<u32> = None;
 
-    for &scheduler in &schedulers {
-        set_scheduler(device, scheduler)?;
+    // Repetitions of the configuration being measured, one list per group, and
+    // the last configuration that stopped early
+    let mut samples: Vec<Vec<Measurement>> = Vec::new();
+    let mut converged: Option<RunParams> = None;
 
-        for &num_jobs in &num_jobs_list {
-            let output_json = format!("randwrite-{}-{}-{}.json", device_name, num_jobs, scheduler);
-            let output_txt = format!("randwrite-{}-{}-{}.txt", device_name, num_jobs, scheduler);
+    // The groups of a workload run together as one batch; other runs are batches of one
+    for batch in runs.chunk_by(|a, b| a.batch() == b.batch()) {
+        let run = &batch[0];
+        if converged.as_ref() == Some(&run.batch().configuration()) {
+            continue;
+        }
+        if run.repetition == 0 {
+            samples = vec![Vec::new(); batch.len()];
+        }
 
-            // Run benchmark
-            let (latency, iops) = run_fio(device, "randwrite", num_jobs, &output_json)?;
+        if let Some(scheduler) = run.scheduler.as_deref() {
+            if current_scheduler != Some(scheduler) {
+                match bdev {
+                    Some(bdev) => set_scheduler(bdev, scheduler)?,
+                    None => println!("Skipping scheduler setting without a block device"),
+                }
+                current_scheduler = Some(scheduler);
+            }
+        }
+        if let Some(ra) = run.read_ahead {
+            if current_ra != Some(ra) {
+                match bdev {
+                    Some(bdev) => set_read_ahead(bdev, ra)?,
+                    None => println!("Skipping read-ahead setting without a block device"),
+                }
+                current_ra = Some(ra);
+            }
+        }
 
-            // Save extracted data to text file
-            let mut file = File::create(&output_txt)?;
-            writeln!(file, "{} {}", latency, iops)?;
+        let mut run_batch = |suffix: &str| -> Result<Vec<Measurement>, Box<dyn Error>> {
+            match session.as_mut() {
+                Some(session) if run.cgroup.is_some() => run_groups(config, session, disk, batch, suffix),
+                _ => Ok(vec![run_benchmark(config, run, &config.run_dir.join(format!("{}{}.json", run.id(), suffix)))?]),
+            }
+        };
 
-            println!("Saved results to: {}\n", output_txt);
+        // Warm up caches, the device and the queue before the first repetition
+        if run.repetition == 0 {
+            for i in 0..scenario.warmup {
+                println!("Warm-up run {}/{} (discarded)", i + 1, scenario.warmup);
+                run_batch(&format!("-warmup{}", i))?;
+            }
         }
-    }
 
-    Ok(())
-}
+        // Bring the page cache into the requested state, then account for what the run adds
+        let target = Path::new(&run.device);
+        pagecache::prepare(target, run.access.cache)
+            .map_err(|e| format!("Failed to {} the page cache: {}", run.access.cache.name(), e))?;
+        let cache_before = CacheSnapshot::take(target)?;
+
+        // Record what the kernel observed on the device while the benchmark runs
+        let sampler = match bdev {
+            Some(bdev) if scenario.iostat_interval_ms > 0 => {
+                Some(Sampler::start(bdev.stat_path(), Duration::from_millis(scenario.iostat_interval_ms))?)
+            }
+            _ => None,
+        };
+
+        // Run benchmark
+        let results = run_batch("");
+        let iostat = sampler.map(Sampler::stop).transpose()?;
+        let results = results?;
+
+        let page_cache = CacheUsage::between(&cache_before, &CacheSnapshot::take(target)?);
+        print_page_cache(&page_cache);
+
+        // A batch shares the device, so its statistics are kept once under the batch's id
+        let id = if batch.len() > 1 { run.batch().id() } else { run.id() };
+        if let Some(iostat) = &iostat {
+            print_iostat(&iostat.total);
+            let iostat_json = config.run_dir.join(format!("{}-iostat.json", id));
+            fs::write(&iostat_json, serde_json::to_string_pretty(iostat)?)?;
+            render!(output, (device.name, run.workload), format!("{}-iostat", id), (800, 600), |root| {
+                plot_iostat(&root, &id, iostat)?;
+            });
+        }
 
-/// Process and plot read benchmark data
-fn process_read_data(device_name: &str) -> Result<(), Box<dyn Error>> {
-    // Data structure: (read_ahead, scheduler, latency, iops)
-    let mut data: Vec<(u32, String, f64, f64)> = Vec::new();
-
-    // Read data from the four result files
-    for ra in &[0, 256] {
-        for sched in &["mq-deadline", "none"] {
-            let filename = format!("read-{}-{}-{}.txt", device_name, ra, sched);
-            if let Ok(file) = File::open(&filename) {
-                let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        let parts: Vec<&str> = line.split_whitespace().collect();
-                        if parts.len() >= 2 {
-                            if let (Ok(latency), Ok(iops)) = (
-                                parts[0].parse::<f64>(),
-                                parts[1].parse::<f64>()
-                            ) {
-                                data.push((*ra, sched.to_string(), latency, iops));
-                            }
-                        }
+        // Append the extracted data to the result store
+        for (run, &result) in batch.iter().zip(&results) {
+            config.store.append(&Record {
+                run: config.run,
+                timestamp: results::now(),
+                system: config.system.clone(),
+                device_model: bdev.and_then(|bdev| bdev.model()),
+                params: run.clone(),
+                result,
+                page_cache: Some(page_cache),
+            })?;
+        }
+
+        println!("Saved results to: {}", config.store.path().display());
+
+        // A batch is done once the IOPS of every group are narrow enough
+        let mut narrow = scenario.ci_target.is_some();
+        let mut widest: f64 = 0.0;
+        for (samples, &result) in samples.iter_mut().zip(&results) {
+            samples.push(result);
+            let iops: Vec<f64> = samples.iter().map(|r| r.iops).collect();
+            let ci = stats::confidence_interval(&iops, results::CONFIDENCE);
+            narrow &= scenario.ci_target.is_some_and(|target| {
+                samples.len() >= scenario.min_repetitions as usize && ci.relative() <= target
+            });
+            widest = widest.max(ci.relative());
+        }
+        let last = run.repetition + 1 == scenario.repetitions;
+
+        if narrow || last {
+            if scenario.repetitions > 1 {
+                for (run, samples) in batch.iter().zip(&samples) {
+                    if let Some(group) = &run.cgroup {
+                        println!("  Group {}:", group.name);
                     }
+                    print_summary(&Summary::of(samples));
                 }
             }
+            if narrow && !last {
+                println!("  IOPS CI is within ±{:.1}%, skipping the remaining repetitions", widest * 100.0);
+                converged = Some(run.batch().configuration());
+            }
         }
+        println!();
     }
 
-    // Plot 1: Latency comparison
-    let output_file = format!("read-{}-latency.png", device_name);
-    let root = BitMapBackend::new(&output_file, (800, 600)).into_drawing_area();
+    Ok(())
+}
+
+/// Load the saved results of a workload, summarizing the repetitions of each run
+fn load_workload_results(
+    records: &[Record],
+    device: &DeviceEntry,
+    workload: &Workload,
+) -> Vec<(RunParams, Summary)> {
+    let mut grouped: Vec<(RunParams, Vec<Measurement>)> = Vec::new();
+
+    let records = records.iter()
+        .filter(|record| record.params.device_name == device.name && record.params.workload == workload.name);
+    for record in records {
+        let key = record.params.configuration();
+        match grouped.iter_mut().find(|(params, _)| *params == key) {
+            Some((_, results)) => results.push(record.result),
+            None => grouped.push((key, vec![record.result])),
+        }
+    }
+
+    grouped
+        .into_iter()
+        .map(|(params, results)| (params, Summary::of(&results)))
+        .collect()
+}
+
+/// Values of `items` in order of first appearance, without duplicates
+fn distinct<T: PartialEq>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let mut values = Vec::new();
+    for item in items {
+        if !values.contains(&item) {
+            values.push(item);
+        }
+    }
+    values
+}
+
+/// Accessors of the parameters that may differ between runs of a workload:
+/// column name, label prefix and value
+type ParamField = (&'static str, &'static str, fn(&RunParams) -> String);
+
+const PARAM_FIELDS: [ParamField; 8] = [
+    ("scheduler", "", |r| r.scheduler_label().to_string()),
+    ("read-ahead", "ra=", |r| r.read_ahead.map_or("keep".to_string(), |ra| ra.to_string())),
+    ("engine", "", |r| r.engine.name().to_string()),
+    ("bs", "bs=", |r| r.block_size.to_string()),
+    ("qd", "qd=", |r| r.queue_depth.to_string()),
+    ("jobs", "jobs=", |r| r.num_jobs.to_string()),
+    ("runtime", "t=", |r| format!("{}s", r.runtime_secs)),
+    ("io mode", "", |r| r.io_mode.name().to_string()),
+];
+
+/// Describe `run` by the `fields` whose values differ between the runs in `runs`
+fn describe<'a>(run: &RunParams, runs: impl Iterator<Item = &'a RunParams> + Clone, fields: &[ParamField]) -> String {
+    fields.iter()
+        .filter(|(_, _, field)| runs.clone().any(|other| field(other) != field(run)))
+        .map(|(_, prefix, field)| format!("{}{}", prefix, field(run)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Zero for the infinite interval of a single repetition
+fn finite_or_zero(value: f64) -> f64 {
+    if value.is_finite() { value } else { 0.0 }
+}
+
+/// Vertical error bar of ±`half_width` around `y`; none without a finite interval
+fn ci_bar(x: f64, y: f64, half_width: f64, color: RGBColor) -> Option<ErrorBar<f64, f64, ErrorBarOrientV<f64, f64>>> {
+    if !half_width.is_finite() || half_width <= 0.0 {
+        return None;
+    }
+    Some(ErrorBar::new_vertical(x, y - half_width, y, y + half_width, color.stroke_width(1), 8))
+}
+
+/// Plot a workload with one category per parameter combination
+fn process_tunables_data(output: &mut Output, workload: &str, device_name: &str, data: &[(RunParams, Summary)]) -> Result<(), Box<dyn Error>> {
+    // The x-axis labels only mention the parameters that actually vary
+    let labels: Vec<String> = data.iter()
+        .map(|(run, _)| {
+            let label = describe(run, data.iter().map(|(r, _)| r), &PARAM_FIELDS);
+            if label.is_empty() { run.scheduler_label().to_string() } else { label }
+        })
+        .collect();
+
+    let x_label = |x: &f64| {
+        // Only label the integer positions where the configurations are drawn
+        if (x - x.round()).abs() > 1e-6 || *x < 0.0 {
+            return "".to_string();
+        }
+        labels.get(x.round() as usize).cloned().unwrap_or_default()
+    };
+    let points: Vec<TunablesPoint> = data.iter()
+        .enumerate()
+        .map(|(i, (_, summary))| (i as f64, &summary.mean, &summary.ci))
+        .collect();
+
+    // Plot 1: Latency percentile bands
+    render!(output, (device_name, workload), format!("{}-{}-latency", workload, device_name), (800, 600), |root| {
+        plot_tunables_latency(&root, &format!("{} Latency Comparison ({})", workload, device_name), data.len(), &x_label, &points)?;
+    });
+
+    // Plot 2: IOPS comparison
+    render!(output, (device_name, workload), format!("{}-{}-iops", workload, device_name), (800, 600), |root| {
+        plot_tunables_iops(&root, &format!("{} IOPS Comparison ({})", workload, device_name), data.len(), &x_label, &points)?;
+    });
+
+    Ok(())
+}
+
+/// Points of the tunables graphs: x position, mean and confidence interval half widths
+type TunablesPoint<'a> = (f64, &'a Measurement, &'a Measurement);
+
+/// Draw latency percentile bands, one category per configuration
+fn plot_tunables_latency<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    categories: usize,
+    x_label: &dyn Fn(&f64) -> String,
+    points: &[TunablesPoint],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     root.fill(&WHITE)?;
+    let x_range = -0.5f64..categories as f64 - 0.5;
 
-    let max_latency = data.iter()
-        .map(|(_, _, lat, _)| lat)
-        .fold(0.0f64, |a, &b| a.max(b));
+    let max_latency = points.iter()
+        .map(|(_, r, _)| r.latency.p999)
+        .fold(0.0f64, f64::max);
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("Read Latency Comparison ({})", device_name),
-            ("sans-serif", 30).into_font(),
-        )
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
         .margin(10)
         .x_label_area_size(40)
         .y_label_area_size(60)
-        .build_cartesian_2d(0f64..4f64, 0f64..max_latency * 1.1)?;
+        .build_cartesian_2d(x_range, 0f64..max_latency * 1.1)?;
 
     chart
         .configure_mesh()
-        .x_labels(4)
-        .x_label_formatter(&|x| {
-            match  *x as i32 {
-                0 => "mq-deadline\nra=0".to_string(),
-                1 => "mq-deadline\nra=256".to_string(),
-                2 => "none\nra=0".to_string(),
-                3 => "none\nra=256".to_string(),
-                _ => "".to_string(),
-            }
-        })
+        .x_labels(2 * categories + 1)
+        .x_label_formatter(x_label)
         .y_desc("Latency (usec)")
         .draw()?;
 
-    // Group data by configuration
-    let mut deadline_ra0: Vec<f64> = Vec::new();
-    let mut deadline_ra256: Vec<f64> = Vec::new();
-    let mut none_ra0: Vec<f64> = Vec::new();
-    let mut none_ra256: Vec<f64> = Vec::new();
-
-    for (ra, sched, lat, _) in &data {
-        match (sched.as_str(), ra) {
-            ("mq-deadline", 0) => deadline_ra0.push(*lat),
-            ("mq-deadline", 256) => deadline_ra256.push(*lat),
-            ("none", 0) => none_ra0.push(*lat),
-            ("none", 256) => none_ra256.push(*lat),
-            _ => {}
-        }
-    }
-
-    // Plot scatter points for each configuration
+    // Box from p50 to p99 with a tick at p90, whisker up to p99.9
     chart.draw_series(
-        deadline_ra0.iter().map(|lat| {
-            Circle::new((0.0, *lat), 3, RED.filled())
+        points.iter().map(|(x, r, _)| {
+            Rectangle::new([(x - 0.2, r.latency.p50), (x + 0.2, r.latency.p99)], RED.mix(0.3).filled())
         })
-    )?;
+    )?
+    .label("p50 - p99")
+    .legend(|(x, y)| Rectangle::new([(x - 5, y - 5), (x + 5, y + 5)], RED.mix(0.3).filled()));
+
     chart.draw_series(
-        deadline_ra256.iter().map(|lat| {
-            Circle::new((1.0, *lat), 3, RED.filled())
+        points.iter().map(|(x, r, _)| {
+            PathElement::new(vec![(x - 0.2, r.latency.p90), (x + 0.2, r.latency.p90)], RED.stroke_width(2))
         })
-    )?;
+    )?
+    .label("p90")
+    .legend(|(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], RED.stroke_width(2)));
+
     chart.draw_series(
-        none_ra0.iter().map(|lat| {
-            Circle::new((2.0, *lat), 3, RED.filled())
+        points.iter().map(|(x, r, _)| {
+            PathElement::new(vec![(*x, r.latency.p99), (*x, r.latency.p999)], BLACK)
         })
-    )?;
+    )?
+    .label("p99 - p99.9")
+    .legend(|(x, y)| PathElement::new(vec![(x, y - 5), (x, y + 5)], BLACK));
+
+    // Confidence intervals of p50 and p99 over the repetitions
     chart.draw_series(
-        none_ra256.iter().map(|lat| {
-            Circle::new((3.0, *lat), 3, RED.filled())
+        points.iter().flat_map(|(x, r, ci)| {
+            [
+                ci_bar(*x, r.latency.p50, ci.latency.p50, BLUE),
+                ci_bar(*x, r.latency.p99, ci.latency.p99, BLUE),
+            ]
         })
-    )?;
+        .flatten()
+    )?
+    .label(format!("{:.0}% CI", results::CONFIDENCE * 100.0))
+    .legend(|(x, y)| PathElement::new(vec![(x, y - 5), (x, y + 5)], BLUE));
 
-    root.present()?;
-    println!("Generated: {}", output_file);
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
 
-    // Plot 2: IOPS comparison
-    let output_file = format!("read-{}-iops.png", device_name);
-    let root = BitMapBackend::new(&output_file, (800, 600)).into_drawing_area();
+    Ok(())
+}
+
+/// Draw IOPS with confidence intervals, one category per configuration
+fn plot_tunables_iops<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    categories: usize,
+    x_label: &dyn Fn(&f64) -> String,
+    points: &[TunablesPoint],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     root.fill(&WHITE)?;
+    let x_range = -0.5f64..categories as f64 - 0.5;
 
-    let max_iops = data.iter()
-        .map(|(_, _, _, iops)| iops)
-        .fold(0.0f64, |a, &b| a.max(b));
+    let max_iops = points.iter()
+        .map(|(_, r, ci)| r.iops + finite_or_zero(ci.iops))
+        .fold(0.0f64, f64::max);
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("Read IOPS Comparison ({})", device_name),
-            ("sans-serif", 30).into_font(),
-        )
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
         .margin(10)
         .x_label_area_size(40)
         .y_label_area_size(60)
-        .build_cartesian_2d(0f64..4f64,  0f64..max_iops * 1.1)?;
+        .build_cartesian_2d(x_range, 0f64..max_iops * 1.1)?;
 
     chart
         .configure_mesh()
-        .x_labels(4)
-        .x_label_formatter(&|x| {
-            match *x as i32 {
-                0 => "mq-deadline\nra=0".to_string(),
-                1 => "mq-deadline\nra=256".to_string(),
-                2 => "none\nra=0".to_string(),
-                3 => "none\nra=256".to_string(),
-                _ => "".to_string(),
-            }
-        })
+        .x_labels(2 * categories + 1)
+        .x_label_formatter(x_label)
         .y_desc("IOPS")
         .draw()?;
 
-    // Grpup IOPS data
-    let mut deadline_ra0_iops: Vec<f64> = Vec::new();
-    let mut deadline_ra256_iops: Vec<f64> = Vec::new();
-    let mut none_ra0_iops: Vec<f64> = Vec::new();
-    let mut none_ra256_iops: Vec<f64> = Vec::new();
-
-    for (ra, sched, _, iops) in &data {
-        match (sched.as_str(), ra) {
-            ("mq-deadline", 0) => deadline_ra0_iops.push(*iops),
-            ("mq-deadline", 256) => deadline_ra256_iops.push(*iops),
-            ("none", 0) => none_ra0_iops.push(*iops),
-            ("none", 256) => none_ra256_iops.push(*iops),
-            _ => {}
-        }
-    }
-
-    // Plot scatter points
-    chart.draw_series(
-        deadline_ra0_iops.iter().map(|iops| {
-            Circle::new((0.0, *iops), 3, BLUE.filled())
-        })
-    )?;
-    chart.draw_series(
-        deadline_ra256_iops.iter().map(|iops| {
-            Circle::new((1.0, *iops), 3, BLUE.filled())
-        })
-    )?;
+    // Plot scatter points with their confidence intervals
     chart.draw_series(
-        none_ra0_iops.iter().map(|iops| {
-            Circle::new((2.0, *iops), 3, BLUE.filled())
-        })
-    )?;
-    chart.draw_series(
-        none_ra256_iops.iter().map(|iops| {
-            Circle::new((3.0, *iops), 3, BLUE.filled())
+        points.iter().map(|(x, r, _)| {
+            Circle::new((*x, r.iops), 3, BLUE.filled())
         })
     )?;
-
-    root.present()?;
-    println!("Generated: {}", output_file);
+    chart.draw_series(points.iter().filter_map(|(x, r, ci)| ci_bar(*x, r.iops, ci.iops, BLUE)))?;
 
     Ok(())
 }
 
-/// Process and plot random write benchmark data
-fn process_randwrite_data(device_name: &str) -> Result<(), Box<dyn Error>> {
-    // Data structure: (num_jobs, scheduler, latency, iops)
-    let mut data: Vec<(u32, String, f64, f64)> = Vec::new();
-    
-    let num_jobs_list = vec![1, 2, 4, 8, 16, 32, 64];
-    
-    // Read data from result files
-    for &nj in &num_jobs_list {
-        for sched in &["mq-deadline", "none"] {
-            let filename = format!("randwrite-{}-{}-{}.txt", device_name, nj, sched);
-            if let Ok(file) = File::open(&filename) {
-                let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        let parts: Vec<&str> = line.split_whitespace().collect();
-                        if parts.len() >= 2 {
-                            if let (Ok(latency), Ok(iops)) = (
-                                parts[0].parse::<f64>(),
-                                parts[1].parse::<f64>()
-                            ) {
-                                data.push((nj, sched.to_string(), latency, iops));
-                            }
-                        }
-                    }
-                }
+/// Colors of the engines in the job count graphs
+const ENGINE_COLORS: [RGBColor; 3] = [BLUE, RED, GREEN];
+
+/// Draw latency against the number of jobs, one band per engine and scheduler
+///
+/// Each point carries the confidence interval half width of its p50.
+fn plot_jobs_latency<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    points: &[(Engine, &str, f64, LatencyStats, f64)],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let max_x = points.iter()
+        .map(|(_, _, nj, _, _)| *nj)
+        .fold(1.0f64, f64::max);
+    let max_y = points.iter()
+        .map(|(_, _, _, lat, _)| lat.p999)
+        .fold(0.0f64, f64::max);
+    let schedulers = distinct(points.iter().map(|(_, sched, _, _, _)| *sched));
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..max_x * 1.1, 0f64..max_y * 1.1)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Number of Jobs")
+        .y_desc("Latency (usec)")
+        .draw()?;
+
+    // Shaded band from p50 to p99 around a p50 line, triangles mark p99.9.
+    // The first scheduler uses solid lines and the others dashed ones.
+    for (i, engine) in Engine::ALL.iter().enumerate() {
+        let color = ENGINE_COLORS[i % ENGINE_COLORS.len()];
+
+        for (j, &sched) in schedulers.iter().enumerate() {
+            let mut series: Vec<(f64, LatencyStats, f64)> = points.iter()
+                .filter(|(e, s, _, _, _)| e == engine && *s == sched)
+                .map(|(_, _, nj, lat, ci)| (*nj, *lat, *ci))
+                .collect();
+            if series.is_empty() {
+                continue;
+            }
+            series.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let band: Vec<(f64, f64)> = series.iter()
+                .map(|(nj, lat, _)| (*nj, lat.p50))
+                .chain(series.iter().rev().map(|(nj, lat, _)| (*nj, lat.p99)))
+                .collect();
+            chart.draw_series(std::iter::once(Polygon::new(band, color.mix(0.15).filled())))?;
+
+            let p50 = series.iter().map(|(nj, lat, _)| (*nj, lat.p50));
+            let label = format!("{} / {} (p50, p99, p99.9)", engine.name(), sched);
+            if j == 0 {
+                chart.draw_series(LineSeries::new(p50, color.stroke_width(2)))?
+                    .label(label)
+                    .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], color.stroke_width(2)));
+            } else {
+                chart.draw_series(DashedLineSeries::new(p50, 5, 5, color.stroke_width(2)))?
+                    .label(label)
+                    .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x - 1, y)], color.stroke_width(2)));
             }
+
+            chart.draw_series(
+                series.iter().map(|(nj, lat, _)| TriangleMarker::new((*nj, lat.p999), 4, color.filled()))
+            )?;
+            chart.draw_series(series.iter().filter_map(|(nj, lat, ci)| ci_bar(*nj, lat.p50, *ci, color)))?;
         }
     }
-    
-    // Plot 1: Latency vs Number of Jobs
-    let output_file = format!("randwrite-{}-latency.png", device_name);
-    let root = BitMapBackend::new(&output_file, (800, 600)).into_drawing_area();
+
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    Ok(())
+}
+
+/// Draw IOPS against the number of jobs, one series per engine and scheduler
+///
+/// Each point carries the confidence interval half width of its IOPS.
+fn plot_jobs_iops<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    points: &[(Engine, &str, f64, f64, f64)],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     root.fill(&WHITE)?;
 
-    let max_latency = data.iter()
-        .map(|(_, _, lat, _)| lat)
-        .fold(0.0f64, |a, &b| a.max(b));
+    let max_x = points.iter()
+        .map(|(_, _, nj, _, _)| *nj)
+        .fold(1.0f64, f64::max);
+    let max_y = points.iter()
+        .map(|(_, _, _, y, ci)| y + finite_or_zero(*ci))
+        .fold(0.0f64, f64::max);
+    let schedulers = distinct(points.iter().map(|(_, sched, _, _, _)| *sched));
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("Rondom Write Latency ({})", device_name),
-            ("sans-serif", 30).into_font(),
-        )
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
         .margin(10)
         .x_label_area_size(40)
         .y_label_area_size(60)
-        .build_cartesian_2d(0f64..70f64, 0f64..max_latency * 1.1)?;
+        .build_cartesian_2d(0f64..max_x * 1.1, 0f64..max_y * 1.1)?;
 
     chart
         .configure_mesh()
         .x_desc("Number of Jobs")
-        .y_desc("Latency (usec)")
+        .y_desc("IOPS")
         .draw()?;
 
-    // Separate data by scheduler
-    let deadline_data: Vec<(f64, f64)> = data.iter()
-        .filter(|(_, sched, _, _)| sched == "mq-deadline")
-        .map(|(nj, _, lat, _)| (*nj as f64, *lat))
-        .collect();
+    // Color tells the engine apart; the first scheduler is filled, the others hollow
+    for (i, engine) in Engine::ALL.iter().enumerate() {
+        let color = ENGINE_COLORS[i % ENGINE_COLORS.len()];
+
+        for (j, &sched) in schedulers.iter().enumerate() {
+            let style = if j == 0 { color.filled() } else { color.stroke_width(2) };
+            let series: Vec<(f64, f64, f64)> = points.iter()
+                .filter(|(e, s, _, _, _)| e == engine && *s == sched)
+                .map(|(_, _, nj, y, ci)| (*nj, *y, *ci))
+                .collect();
+            if series.is_empty() {
+                continue;
+            }
 
-    let none_data: Vec<(f64, f64)> = data.iter()
-        .filter(|(_, sched, _, _)| sched == "none")
-        .map(|(nj, _, lat, _)| (*nj as f64, *lat))
-        .collect();
+            chart.draw_series(
+                series.iter().map(|(nj, y, _)| Circle::new((*nj, *y), 4, style))
+            )?
+            .label(format!("{} / {}", engine.name(), sched))
+            .legend(move |(x, y)| Circle::new((x, y), 4, style));
+            chart.draw_series(series.iter().filter_map(|(nj, y, ci)| ci_bar(*nj, *y, *ci, color)))?;
+        }
+    }
 
-    // Plot scatter points for mq-deadline
-    chart.draw_series(
-        deadline_data.iter().map(|(nj, lat)| {
-            Circle::new((*nj, *lat), 3, BLUE.filled())
-        })
-    )?
-    .label("mq-deadline")
-    .legend(|(x, y)| Circle::new((x, y), 3, BLUE.filled()));
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
 
-    // Plot scatter points gor none
-    chart.draw_series(
-        none_data.iter().map(|(nj, lat)| {
-            Circle::new((*nj, *lat), 3, RED.filled())
-        })
-    )?
-    .label("none")
-    .legend(|(x, y)| Circle::new((x, y), 3, RED.filled()));
+    Ok(())
+}
+
+/// Plot a workload against the number of jobs, one pair of graphs per remaining parameter combination
+fn process_jobs_data(output: &mut Output, workload: &str, device_name: &str, data: &[(RunParams, Summary)]) -> Result<(), Box<dyn Error>> {
+    // Engine, scheduler and job count are drawn inside a graph; the rest selects the graph
+    let graph_fields = [PARAM_FIELDS[1], PARAM_FIELDS[3], PARAM_FIELDS[4], PARAM_FIELDS[6], PARAM_FIELDS[7]];
+    let graph_key = |run: &RunParams| graph_fields.iter().map(|(_, _, field)| field(run)).collect::<Vec<_>>();
+
+    for key in distinct(data.iter().map(|(run, _)| graph_key(run))) {
+        let graph: Vec<&(RunParams, Summary)> = data.iter()
+            .filter(|(run, _)| graph_key(run) == key)
+            .collect();
+        let suffix = describe(&graph[0].0, data.iter().map(|(r, _)| r), &graph_fields).replace([' ', '='], "");
+        let name = if suffix.is_empty() {
+            format!("{}-{}", workload, device_name)
+        } else {
+            format!("{}-{}-{}", workload, device_name, suffix)
+        };
+        let title = if suffix.is_empty() {
+            device_name.to_string()
+        } else {
+            format!("{}, {}", device_name, suffix)
+        };
+
+        let latency: Vec<(Engine, &str, f64, LatencyStats, f64)> = graph.iter()
+            .map(|(run, r)| (run.engine, run.scheduler_label(), run.num_jobs as f64, r.mean.latency, r.ci.latency.p50))
+            .collect();
+        let iops: Vec<(Engine, &str, f64, f64, f64)> = graph.iter()
+            .map(|(run, r)| (run.engine, run.scheduler_label(), run.num_jobs as f64, r.mean.iops, r.ci.iops))
+            .collect();
+
+        // Plot 1: Latency vs Number of Jobs
+        render!(output, (device_name, workload), format!("{}-latency", name), (800, 600), |root| {
+            plot_jobs_latency(&root, &format!("{} Latency ({})", workload, title), &latency)?;
+        });
+
+        // Plot 2: IOPS vs Number of Jobs
+        render!(output, (device_name, workload), format!("{}-iops", name), (800, 600), |root| {
+            plot_jobs_iops(&root, &format!("{} IOPS ({})", workload, title), &iops)?;
+        });
+    }
+
+    Ok(())
+}
+
+/// One group in one configuration of the group graphs
+struct GroupBar<'a> {
+    category: usize,
+    group: usize,
+    summary: &'a Summary,
+    /// IOPS allowed by the group's io.max
+    limit: Option<f64>,
+}
+
+/// Horizontal extent of a group's bar within its category
+fn group_span(bar: &GroupBar, groups: usize) -> (f64, f64) {
+    let width = 0.8 / groups as f64;
+    let left = bar.category as f64 - 0.4 + bar.group as f64 * width;
+    (left + width * 0.1, left + width * 0.9)
+}
+
+/// Draw the IOPS of every group side by side, with their io.max limits
+fn plot_groups_iops<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    categories: usize,
+    x_label: &dyn Fn(&f64) -> String,
+    groups: &[String],
+    bars: &[GroupBar],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let max_iops = bars.iter()
+        .map(|bar| (bar.summary.mean.iops + finite_or_zero(bar.summary.ci.iops)).max(bar.limit.unwrap_or(0.0)))
+        .fold(0.0f64, f64::max);
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(-0.5f64..categories as f64 - 0.5, 0f64..max_iops * 1.1)?;
+
+    chart
+        .configure_mesh()
+        .x_labels(2 * categories + 1)
+        .x_label_formatter(x_label)
+        .x_desc("J: Jain's fairness index of IOPS per weight")
+        .y_desc("IOPS")
+        .draw()?;
+
+    for (g, label) in groups.iter().enumerate() {
+        let color = Palette99::pick(g).to_rgba();
+        let group_bars = bars.iter().filter(|bar| bar.group == g);
+        chart.draw_series(group_bars.clone().map(|bar| {
+            let (left, right) = group_span(bar, groups.len());
+            Rectangle::new([(left, 0.0), (right, bar.summary.mean.iops)], color.mix(0.6).filled())
+        }))?
+        .label(label)
+        .legend(move |(x, y)| Rectangle::new([(x - 5, y - 5), (x + 5, y + 5)], color.mix(0.6).filled()));
+        chart.draw_series(group_bars.filter_map(|bar| {
+            let (left, right) = group_span(bar, groups.len());
+            ci_bar((left + right) / 2.0, bar.summary.mean.iops, bar.summary.ci.iops, BLACK)
+        }))?;
+    }
+
+    chart.draw_series(bars.iter().filter_map(|bar| {
+        let (left, right) = group_span(bar, groups.len());
+        Some(PathElement::new(vec![(left, bar.limit?), (right, bar.limit?)], RED.stroke_width(3)))
+    }))?
+    .label("io.max limit")
+    .legend(|(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], RED.stroke_width(3)));
 
     chart.configure_series_labels()
-        .background_style(&WHITE.mix(0.8))
-        .border_style(&BLACK)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
         .draw()?;
 
-    root.present()?;
-    println!("Generated: {}", output_file);
+    Ok(())
+}
 
-    // Plot 2: IOPS vs Number of Jobs
-    let output_file = format!("randwrite-{}-iops.png", device_name);
-    let root = BitMapBackend::new(&output_file, (800, 600)).into_drawing_area();
+/// Draw the latency percentiles of every group side by side
+fn plot_groups_latency<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    caption: &str,
+    categories: usize,
+    x_label: &dyn Fn(&f64) -> String,
+    groups: &[String],
+    bars: &[GroupBar],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     root.fill(&WHITE)?;
 
-    let max_iops = data.iter()
-        .map(|(_, _, _, iops)| iops)
-        .fold(0.0f64, |a, &b| a.max(b));
+    let max_latency = bars.iter()
+        .map(|bar| bar.summary.mean.latency.p999)
+        .fold(0.0f64, f64::max);
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("Random Write IOPS ({})", device_name),
-            ("sans-serif", 30).into_font(),
-        )
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 30).into_font())
         .margin(10)
         .x_label_area_size(40)
         .y_label_area_size(60)
-        .build_cartesian_2d(0f64..70f64, 0f64..max_iops * 1.1)?;
+        .build_cartesian_2d(-0.5f64..categories as f64 - 0.5, 0f64..max_latency * 1.1)?;
 
     chart
         .configure_mesh()
-        .x_desc("Number of Jobs")
-        .y_desc("IOPS")
+        .x_labels(2 * categories + 1)
+        .x_label_formatter(x_label)
+        .x_desc("J: Jain's fairness index of IOPS per weight")
+        .y_desc("Latency (usec)")
         .draw()?;
 
-    // Separate IOPS data by scheduler
-    let deadline_iops: Vec<(f64, f64)> = data.iter()
-        .filter(|(_, sched, _, _)| sched == "mq-deadline")
-        .map(|(nj, _, _, iops)| (*nj as f64, *iops))
-        .collect();
+    // Box from p50 to p99 with a tick at p90, whisker up to p99.9, as in the tunables graphs
+    for (g, label) in groups.iter().enumerate() {
+        let color = Palette99::pick(g).to_rgba();
+        let group_bars = bars.iter().filter(|bar| bar.group == g);
+        chart.draw_series(group_bars.clone().map(|bar| {
+            let (left, right) = group_span(bar, groups.len());
+            let lat = &bar.summary.mean.latency;
+            Rectangle::new([(left, lat.p50), (right, lat.p99)], color.mix(0.4).filled())
+        }))?
+        .label(format!("{} (p50 - p99, p90, p99.9)", label))
+        .legend(move |(x, y)| Rectangle::new([(x - 5, y - 5), (x + 5, y + 5)], color.mix(0.4).filled()));
+        chart.draw_series(group_bars.clone().map(|bar| {
+            let (left, right) = group_span(bar, groups.len());
+            let p90 = bar.summary.mean.latency.p90;
+            PathElement::new(vec![(left, p90), (right, p90)], color.stroke_width(2))
+        }))?;
+        chart.draw_series(group_bars.map(|bar| {
+            let (left, right) = group_span(bar, groups.len());
+            let lat = &bar.summary.mean.latency;
+            PathElement::new(vec![((left + right) / 2.0, lat.p99), ((left + right) / 2.0, lat.p999)], BLACK)
+        }))?;
+    }
+
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
 
-    let none_iops: Vec<(f64, f64)> = data.iter()
-        .filter(|(_, sched, _, _)| sched == "none")
-        .map(|(nj, _, _, iops)| (*nj as f64, *iops))
+    Ok(())
+}
+
+/// Plot a workload run by several cgroups, one category per configuration
+/// and one bar per group in it
+fn process_groups_data(output: &mut Output, workload: &str, device_name: &str, data: &[(RunParams, Summary)]) -> Result<(), Box<dyn Error>> {
+    let batches = distinct(data.iter().map(|(run, _)| run.batch()));
+    let groups = distinct(data.iter().filter_map(|(run, _)| run.cgroup.as_ref().map(|group| group.name.as_str())));
+
+    // Groups with more weight should get proportionally more
+    let fairness: Vec<f64> = batches.iter()
+        .map(|batch| {
+            let shares: Vec<f64> = data.iter()
+                .filter(|(run, _)| run.batch() == *batch)
+                .filter_map(|(run, summary)| Some(summary.mean.iops / run.cgroup.as_ref()?.weight() as f64))
+                .collect();
+            stats::jain_index(&shares)
+        })
+        .collect();
+    let labels: Vec<String> = batches.iter()
+        .zip(&fairness)
+        .map(|(batch, fairness)| {
+            let label = describe(batch, batches.iter(), &PARAM_FIELDS);
+            let label = if label.is_empty() { batch.scheduler_label().to_string() } else { label };
+            format!("{} J={:.2}", label, fairness)
+        })
         .collect();
+    let x_label = |x: &f64| {
+        if (x - x.round()).abs() > 1e-6 || *x < 0.0 {
+            return "".to_string();
+        }
+        labels.get(x.round() as usize).cloned().unwrap_or_default()
+    };
 
-    // Plot scatter points for mq-deadline
-    chart.draw_series(
-        deadline_iops.iter().map(|(nj, iops)| {
-            Circle::new((*nj, *iops), 3, BLUE.filled())
+    let legend: Vec<String> = groups.iter()
+        .filter_map(|&name| {
+            let (run, _) = data.iter().find(|(run, _)| run.cgroup.as_ref().is_some_and(|group| group.name == name))?;
+            Some(format!("{} {}", run.cgroup.as_ref()?.label(), run.rw))
         })
-    )?
-    .label("mq-deadline")
-    .legend(|(x, y)| Circle::new((x, y), 3, BLUE.filled()));
+        .collect();
+    let bars: Vec<GroupBar> = data.iter()
+        .filter_map(|(run, summary)| {
+            let name = &run.cgroup.as_ref()?.name;
+            Some(GroupBar {
+                category: batches.iter().position(|batch| *batch == run.batch())?,
+                group: groups.iter().position(|group| group == name)?,
+                summary,
+                limit: group_iops_limit(run),
+            })
+        })
+        .collect();
 
-    // Plot scatter points for none
-    chart.draw_series(
-        none_iops.iter().map(|(nj, iops)| {
-            Circle::new((*nj, *iops), 3, RED.filled())
+    render!(output, (device_name, workload), format!("{}-{}-groups-iops", workload, device_name), (800, 600), |root| {
+        plot_groups_iops(&root, &format!("{} IOPS per Group ({})", workload, device_name), batches.len(), &x_label, &legend, &bars)?;
+    });
+    render!(output, (device_name, workload), format!("{}-{}-groups-latency", workload, device_name), (800, 600), |root| {
+        plot_groups_latency(&root, &format!("{} Latency per Group ({})", workload, device_name), batches.len(), &x_label, &legend, &bars)?;
+    });
+
+    Ok(())
+}
+
+/// Panels of the iostat plot: title, unit and the read/write (or single) series
+type IostatPanel = (&'static str, &'static str, &'static [(&'static str, fn(&IostatSample) -> f64)]);
+
+const IOSTAT_PANELS: [IostatPanel; 4] = [
+    ("Utilization", "%", &[("util", |s| s.util)]),
+    ("Average queue size", "requests", &[("aqu-sz", |s| s.aqu_sz)]),
+    ("Await", "ms", &[("r_await", |s| s.r_await), ("w_await", |s| s.w_await)]),
+    ("Merges", "per second", &[("rrqm/s", |s| s.rrqm_s), ("wrqm/s", |s| s.wrqm_s)]),
+];
+
+/// Draw the device statistics sampled during a run over time, one panel per metric
+fn plot_iostat<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, caption: &str, series: &IostatSeries) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let root = root.titled(caption, ("sans-serif", 16).into_font())?;
+
+    let max_x = series.samples.iter()
+        .map(|sample| sample.time)
+        .fold(0.0f64, f64::max)
+        .max(f64::EPSILON);
+
+    for (area, (title, unit, lines)) in root.split_evenly((2, 2)).iter().zip(IOSTAT_PANELS) {
+        let max_y = series.samples.iter()
+            .flat_map(|sample| lines.iter().map(move |(_, value)| value(sample)))
+            .fold(0.0f64, f64::max);
+        // Keep flat zero lines visible
+        let max_y = if max_y > 0.0 { max_y * 1.1 } else { 1.0 };
+
+        let mut chart = ChartBuilder::on(area)
+            .caption(title, ("sans-serif", 18).into_font())
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(0f64..max_x, 0f64..max_y)?;
+
+        chart
+            .configure_mesh()
+            .x_desc("Time (s)")
+            .y_desc(unit)
+            .draw()?;
+
+        for (i, (label, value)) in lines.iter().enumerate() {
+            let color = ENGINE_COLORS[i % ENGINE_COLORS.len()];
+            let points = series.samples.iter().map(|sample| (sample.time, value(sample)));
+            chart.draw_series(LineSeries::new(points, color.stroke_width(2)))?
+                .label(*label)
+                .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], color.stroke_width(2)));
+        }
+
+        if lines.len() > 1 {
+            chart.configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Columns of the device table of the report
+const DEVICE_COLUMNS: [&str; 8] = [
+    "name", "path", "block device", "kind", "model", "size (MiB)", "logical block size", "original tunables",
+];
+
+/// One row of the device table of the report
+fn device_row(device: &DeviceEntry, bdev: Option<&BlockDevice>, guard: Option<&TunableGuard>) -> Vec<String> {
+    let unknown = || "-".to_string();
+    let tunables = guard.iter()
+        .flat_map(|guard| guard.snapshots())
+        .flat_map(|snapshot| {
+            snapshot.values.iter().map(move |(attr, value)| format!("{} {}={}", snapshot.device, attr, value))
         })
-    )?
-    .label("none")
-    .legend(|(x, y)| Circle::new((x, y), 3, RED.filled()));
+        .collect::<Vec<_>>();
+
+    vec![
+        device.name.clone(),
+        device.path.clone(),
+        bdev.map_or_else(unknown, |bdev| bdev.name.clone()),
+        bdev.map_or_else(unknown, |bdev| format!("{:?}", bdev.kind).to_lowercase()),
+        bdev.and_then(|bdev| bdev.model()).unwrap_or_else(unknown),
+        bdev.and_then(|bdev| bdev.size().ok()).map_or_else(unknown, |size| (size >> 20).to_string()),
+        bdev.and_then(|bdev| bdev.logical_block_size().ok()).map_or_else(unknown, |size| size.to_string()),
+        if tunables.is_empty() { unknown() } else { tunables.join(", ") },
+    ]
+}
 
-    chart.configure_series_labels()
-        .background_style(&WHITE.mix(0.8))
-        .border_style(&BLACK)
-        .draw()?;
+/// Latency columns of the report tables, in usec
+type LatencyColumn = (&'static str, fn(&LatencyStats) -> f64);
+
+const LATENCY_COLUMNS: [LatencyColumn; 6] = [
+    ("mean", |l| l.mean),
+    ("p50", |l| l.p50),
+    ("p90", |l| l.p90),
+    ("p99", |l| l.p99),
+    ("p99.9", |l| l.p999),
+    ("max", |l| l.max),
+];
+
+/// Parameter matrix of a workload with the summarized results; `#` is the
+/// position of the configuration in the tunables graphs
+fn matrix_table(data: &[(RunParams, Summary)]) -> Table {
+    let headers = ["#", "rw", "access"].into_iter()
+        .chain(PARAM_FIELDS.iter().map(|(name, _, _)| *name))
+        .chain(["n", "IOPS", "IOPS CI (±)", "MiB/s"])
+        .map(str::to_string)
+        .chain(LATENCY_COLUMNS.iter().map(|(name, _)| format!("lat {} (usec)", name)))
+        .collect::<Vec<_>>();
+    let mut table = Table::new(&headers);
+
+    for (i, (run, summary)) in data.iter().enumerate() {
+        let access = run.access.label();
+        let row = [i.to_string(), run.rw.clone(), if access.is_empty() { "-".to_string() } else { access }]
+            .into_iter()
+            .chain(PARAM_FIELDS.iter().map(|(_, _, field)| field(run)))
+            .chain([
+                summary.n.to_string(),
+                format!("{:.1}", summary.mean.iops),
+                if summary.ci.iops.is_finite() { format!("{:.1}", summary.ci.iops) } else { "-".to_string() },
+                format!("{:.2}", summary.mean.bw_bytes / 1048576.0),
+            ])
+            .chain(LATENCY_COLUMNS.iter().map(|(_, field)| format!("{:.2}", field(&summary.mean.latency))))
+            .collect();
+        table.push(row);
+    }
+    table
+}
 
-    root.present()?;
-    println!("Generated: {}", output_file);
+/// Every measured repetition of a workload
+fn raw_table(records: &[&Record]) -> Table {
+    let headers = ["run", "finished", "IOPS", "MiB/s"].into_iter()
+        .map(str::to_string)
+        .chain(LATENCY_COLUMNS.iter().map(|(name, _)| format!("lat {} (usec)", name)))
+        .chain(["cached before (MiB)", "cached after (MiB)", "system cache delta (MiB)"].map(str::to_string))
+        .collect::<Vec<_>>();
+    let mut table = Table::new(&headers);
+
+    let mib = |bytes: f64| format!("{:.1}", bytes / 1048576.0);
+    for record in records {
+        let result = &record.result;
+        let cache = record.page_cache.map_or(["-".to_string(), "-".to_string(), "-".to_string()], |cache| {
+            [mib(cache.target_before as f64), mib(cache.target_after as f64), mib(cache.system_delta as f64)]
+        });
+        let row = [record.params.id(), results::format_time(record.timestamp), format!("{:.1}", result.iops), mib(result.bw_bytes)]
+            .into_iter()
+            .chain(LATENCY_COLUMNS.iter().map(|(_, field)| format!("{:.2}", field(&result.latency))))
+            .chain(cache)
+            .collect();
+        table.push(row);
+    }
+    table
+}
+
+/// Write the HTML report of this run: host and devices, then per workload the
+/// parameter matrix, the plots and the raw numbers
+fn write_report(config: &Config, output: &Output, scenario_path: &str, devices: &Table, records: &[Record]) -> Result<(), Box<dyn Error>> {
+    let scenario = &config.scenario;
+    let mut report = Report::new(format!("Block device benchmark run {} ({})", config.run, config.system.host));
+
+    report.heading(2, "Run");
+    let mut run = Table::new(&["property", "value"]);
+    let properties = [
+        ("run", config.run.to_string()),
+        ("started", results::format_time(results::run_started(config.run))),
+        ("host", config.system.host.clone()),
+        ("kernel", config.system.kernel.clone()),
+        ("CPUs", num_cpus::get().to_string()),
+        ("scenario", scenario_path.to_string()),
+        ("load generator", if config.use_fio { "fio" } else { "native" }.to_string()),
+        ("repetitions", format!("up to {} after {} warm-up runs", scenario.repetitions, scenario.warmup)),
+        ("CI target", scenario.ci_target.map_or("-".to_string(), |target| format!("±{:.1}% IOPS", target * 100.0))),
+        ("confidence level", format!("{:.0}%", results::CONFIDENCE * 100.0)),
+        ("iostat interval", format!("{} ms", scenario.iostat_interval_ms)),
+    ];
+    for (property, value) in properties {
+        run.push(vec![property.to_string(), value]);
+    }
+    report.table(&run);
+
+    report.heading(2, "Devices");
+    report.table(devices);
+
+    for device in &scenario.devices {
+        for workload in &scenario.workloads {
+            let data = load_workload_results(records, device, workload);
+            if data.is_empty() {
+                continue;
+            }
+
+            report.heading(2, &format!("{} on {}", workload.name, device.name));
+            report.table(&matrix_table(&data));
+
+            let (iostat, plots): (Vec<&Chart>, Vec<&Chart>) = output.charts.iter()
+                .filter(|chart| chart.device_name == device.name && chart.workload == workload.name)
+                .partition(|chart| chart.name.ends_with("-iostat"));
+            for chart in plots {
+                report.chart(&chart.name, &chart.svg);
+            }
+
+            let raw: Vec<&Record> = records.iter()
+                .filter(|record| record.params.device_name == device.name && record.params.workload == workload.name)
+                .collect();
+            report.collapsed_table(&format!("Raw results of {} runs", raw.len()), &raw_table(&raw));
+
+            if !iostat.is_empty() {
+                report.heading(3, &format!("Device statistics of {} on {}", workload.name, device.name));
+                for chart in iostat {
+                    report.chart(&chart.name, &chart.svg);
+                }
+            }
+        }
+    }
+
+    let path = config.run_dir.join("report.html");
+    report.save(&path)?;
+    println!("Generated: {}", path.display());
+
+    Ok(())
+}
+
+/// Compare two runs of the result store, metric by metric
+fn compare_runs(store: &ResultStore, before: Option<u64>, after: Option<u64>, alpha: f64) -> Result<(), Box<dyn Error>> {
+    let runs = store.runs()?;
+    println!("=== Runs in {} ===\n", store.path().display());
+    for info in &runs {
+        println!(
+            "  {}  {}  {}  {}  {} ({} results)",
+            info.run,
+            results::format_time(results::run_started(info.run)),
+            info.system.host,
+            info.system.kernel,
+            info.devices.join(","),
+            info.records
+        );
+    }
+
+    let (before, after) = match (before, after) {
+        (Some(before), Some(after)) => (before, after),
+        _ if runs.len() >= 2 => (runs[runs.len() - 2].run, runs[runs.len() - 1].run),
+        _ => return Err("Need two runs in the result store to compare".into()),
+    };
+    let find = |run: u64| {
+        runs.iter()
+            .find(|info| info.run == run)
+            .ok_or_else(|| format!("No run {} in {}", run, store.path().display()))
+    };
+    let (info_before, info_after) = (find(before)?, find(after)?);
+
+    println!(
+        "\n=== Comparing {} ({}) with {} ({}), alpha={} ===",
+        before, info_before.system.kernel, after, info_after.system.kernel, alpha
+    );
+
+    let comparisons = results::compare(&store.run_records(before)?, &store.run_records(after)?, alpha);
+    if comparisons.is_empty() {
+        println!("\nNo configuration was measured in both runs");
+        return Ok(());
+    }
+
+    let mut current: Option<&RunParams> = None;
+    for c in &comparisons {
+        if current != Some(&c.params) {
+            let label = PARAM_FIELDS.iter()
+                .map(|(_, prefix, field)| format!("{}{}", prefix, field(&c.params)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("\n{} on {}: {}", c.params.workload, c.params.device_name, label);
+            current = Some(&c.params);
+        }
+        let p = c.test.map_or("n/a".to_string(), |test| format!("{:.4}", test.p));
+        println!(
+            "  {:<16} {:>12.2} -> {:>12.2}  {:>+7.1}%  p={:<7} n={}/{}  {}",
+            c.metric,
+            stats::mean(&c.before),
+            stats::mean(&c.after),
+            c.change * 100.0,
+            p,
+            c.before.len(),
+            c.after.len(),
+            c.verdict.name()
+        );
+    }
+
+    let count = |verdict: Verdict| comparisons.iter().filter(|c| c.verdict == verdict).count();
+    println!(
+        "\n{} improvements, {} regressions, {} unchanged, {} inconclusive (need at least 2 repetitions)",
+        count(Verdict::Improvement),
+        count(Verdict::Regression),
+        count(Verdict::Unchanged),
+        count(Verdict::Inconclusive)
+    );
 
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    // Benchmark of one group, started by run_groups inside the group's cgroup
+    if args.get(1).is_some_and(|arg| arg == "worker") {
+        let use_fio = args.get(2).is_some_and(|arg| arg == "--fio");
+        let [run, output_file, result_file] = &args[if use_fio { 3 } else { 2 }..] else {
+            usage(prog_name);
+        };
+        return run_worker(use_fio, run, Path::new(output_file), Path::new(result_file));
+    }
+
+    let compare = args.get(1).is_some_and(|arg| arg == "compare");
+    let mut rest = args[if compare { 2 } else { 1 }..].iter();
+
+    // Pass --fio to cross-check the built-in load generator against fio
+    let mut use_fio = false;
+    let mut store_path = results::DEFAULT_STORE.to_string();
+    let mut alpha = DEFAULT_ALPHA;
+    let mut formats = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--fio" if !compare => use_fio = true,
+            "--store" => store_path = rest.next().cloned().unwrap_or_else(|| usage(prog_name)),
+            "--format" if !compare => {
+                formats = rest.next()
+                    .and_then(|list| list.split(',').map(OutputFormat::parse).collect::<Option<Vec<_>>>())
+                    .map(Some)
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            "--alpha" if compare => {
+                alpha = rest.next()
+                    .and_then(|a| a.parse().ok())
+                    .unwrap_or_else(|| usage(prog_name))
+            }
+            _ if arg.starts_with('-') => usage(prog_name),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let store = ResultStore::new(store_path);
+
+    if compare {
+        let runs = positional.iter()
+            .map(|run| run.parse::<u64>().map_err(|_| format!("Invalid run id: {}", run)))
+            .collect::<Result<Vec<_>, _>>()?;
+        return match runs[..] {
+            [] => compare_runs(&store, None, None, alpha),
+            [before, after] => compare_runs(&store, Some(before), Some(after), alpha),
+            _ => usage(prog_name),
+        };
+    }
+
+    let scenario_path = match &positional[..] {
+        [] => DEFAULT_SCENARIO.to_string(),
+        [path] => path.clone(),
+        _ => usage(prog_name),
+    };
+
     println!("=== Block Device I/O Benchmark ===\n");
 
+    // Restore devices left misconfigured by an earlier run that was killed
+    let journal = Path::new(tunables::DEFAULT_JOURNAL);
+    for device in tunables::repair(journal)? {
+        println!("Repaired tunables of {} left by an interrupted run", device);
+    }
+
     // Load configuration
-    let config = load_config()?;
-    println!("Device: {}", config.device);
-    println!("Device Name: {}", config.device_name);
-
-    // Validate device
-    validate_device(&config.device)?;
-    println!("Device validated successfully\n");
-
-    // Save original setting
-    let original_scheduler = get_scheduler(&config.device)?;
-    let original_ra = get_read_ahead(&config.device)?;
-    println!("Original scheduler: {}", original_scheduler);
-    println!("Original read-ahead: {}", original_ra);
-
-    // Run benchmark
-    benchmark_read(&config.device, &config.device_name)?;
-    benchmark_randwrite(&config.device, &config.device_name)?;
-
-    // restore original setting
-    println!("\n=== Reatoring Original Settings ===\n");
-    set_scheduler(&config.device, &original_scheduler)?;
-    set_read_ahead(&config.device, original_ra)?;
-
-    // Generate graphs
-    process_read_data(&config.device_name)?;
-    process_randwrite_data(&config.device_name)?;
+    let mut config = load_config(&scenario_path, use_fio, store)?;
+    if let Some(formats) = formats {
+        config.scenario.formats = formats;
+    }
+    let mut output = Output {
+        dir: config.run_dir.clone(),
+        formats: config.scenario.formats.clone(),
+        charts: Vec::new(),
+    };
+    let mut devices = Table::new(&DEVICE_COLUMNS);
+
+    // Sandbox filesystems are mounted in a namespace of our own, so they vanish with the process
+    let mounts = config.scenario.devices.iter().any(|d| d.sandbox.as_ref().is_some_and(|s| s.fs.is_some()));
+    if mounts {
+        loopdev::private_mounts().map_err(|e| format!("Failed to create a private mount namespace: {}", e))?;
+    }
+
+    // Restore the tunables on Ctrl+C too; must happen before any thread is spawned
+    tunables::install_signal_handler()?;
+    println!("Scenario: {}", scenario_path);
+    println!("Load generator: {}", if config.use_fio { "fio" } else { "native" });
+    println!("Run: {} on {} ({})", config.run, config.system.host, config.system.kernel);
+
+    for device in &config.scenario.devices {
+        // A sandbox is created now and removed once its benchmarks are done
+        let (sandbox, device) = match &device.sandbox {
+            Some(spec) => {
+                let (sandbox, device) = setup_sandbox(device, spec)?;
+                (Some(sandbox), device)
+            }
+            None => (None, device.clone()),
+        };
+        let device = &device;
+
+        println!("\nDevice: {}", device.path);
+        println!("Device Name: {}", device.name);
+
+        // Directories are benchmarked through a test file in them
+        let device = &prepare_target(device)?;
+
+        // Validate device
+        let bdev = validate_device(&device.path)?;
+        println!("Device validated successfully\n");
+
+        // Save original setting; they are restored even if a benchmark fails.
+        // A sandbox takes its tunables with it.
+        let guard = match sandbox {
+            Some(_) => None,
+            None => Some(TunableGuard::new(&device.path, journal)?),
+        };
+        for snapshot in guard.iter().flat_map(|guard| guard.snapshots()) {
+            for (attr, value) in &snapshot.values {
+                println!("Original {} {}: {}", snapshot.device, attr, value);
+            }
+        }
+
+        devices.push(device_row(device, bdev.as_ref(), guard.as_ref()));
+
+        // Run benchmark
+        benchmark_device(&config, &mut output, device, bdev.as_ref(), journal)?;
+
+        // restore original setting
+        if let Some(guard) = guard {
+            println!("\n=== Restoring Original Settings ===\n");
+            guard.restore()?;
+        }
+        if let Some(sandbox) = sandbox {
+            println!("\n=== Removing Sandbox {} ===\n", sandbox.device.path.display());
+            sandbox.teardown()?;
+        }
+    }
+
+    // Generate graphs from the results of this run
+    let records = config.store.run_records(config.run)?;
+    for device in &config.scenario.devices {
+        for workload in &config.scenario.workloads {
+            let data = load_workload_results(&records, device, workload);
+            if data.is_empty() {
+                continue;
+            }
+
+            match workload.plot {
+                PlotKind::Tunables => process_tunables_data(&mut output, &workload.name, &device.name, &data)?,
+                PlotKind::Jobs => process_jobs_data(&mut output, &workload.name, &device.name, &data)?,
+                PlotKind::Groups => process_groups_data(&mut output, &workload.name, &device.name, &data)?,
+            }
+        }
+    }
+
+    if output.formats.contains(&OutputFormat::Html) {
+        write_report(&config, &output, &scenario_path, &devices, &records)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,434 @@
+use chap09::blockdev::{BlockDevError, Sysfs};
+use chap09::pagecache;
+use chap09::report::{OutputFormat, Report, Table};
+use chap09::results;
+use chap09::tunables::{self, TunableGuard};
+use chap09::writeback::{self, DirtySample, DirtySettings, WritebackResult, WritebackSpec};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Directory holding one subdirectory of outputs and plots per run
+const RUNS_DIR: &str = "chap09-runs";
+
+/// File written when the target is a directory
+const WRITEBACK_FILE: &str = "chap09-writeback.dat";
+
+const SERIES_COLORS: [RGBColor; 6] = [BLUE, RED, GREEN, MAGENTA, CYAN, BLACK];
+
+fn usage(prog_name: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--size MB] [--runtime SEC] [--interval MS] [--bs BYTES] \
+         [--ratio LIST] [--background-ratio LIST] [--expire LIST] [--format LIST] PATH",
+        prog_name
+    );
+    eprintln!();
+    eprintln!("  Write through the page cache to a new file PATH (a directory gets a {} in it)", WRITEBACK_FILE);
+    eprintln!("  under every combination of the listed vm.dirty_* settings, and plot throughput,");
+    eprintln!("  write(2) stalls and the Dirty/Writeback counters over time.");
+    eprintln!("  The settings are restored afterwards, also on Ctrl+C.");
+    eprintln!("  Per-device counters need debugfs mounted on /sys/kernel/debug.");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --size MB: File size; the writer wraps around at its end (default: 4096)");
+    eprintln!("  --runtime SEC: Duration of each run (default: 30)");
+    eprintln!("  --interval MS: Sampling interval (default: 250)");
+    eprintln!("  --bs BYTES: Size of each write (default: 1048576)");
+    eprintln!("  --ratio LIST: vm.dirty_ratio values, e.g. 5,20,40 (default: current)");
+    eprintln!("  --background-ratio LIST: vm.dirty_background_ratio values (default: current)");
+    eprintln!("  --expire LIST: vm.dirty_expire_centisecs values (default: current)");
+    eprintln!("  --format LIST: Plot formats out of png, svg and html, e.g. png,html (default: png)");
+    std::process::exit(1);
+}
+
+/// Parse "5,20,40"
+fn parse_list(text: &str) -> Option<Vec<u32>> {
+    text.split(',').map(|value| value.trim().parse().ok()).collect()
+}
+
+/// Device number of the disk whose bdi backs `path`, if any
+fn bdi_of(path: &Path) -> Result<Option<(u32, u32)>, Box<dyn Error>> {
+    let sysfs = Sysfs::new();
+    let bdev = match sysfs.resolve(path) {
+        Ok(bdev) => bdev,
+        Err(e @ BlockDevError::NoBackingDevice { .. }) => {
+            println!("{}; there is no writeback to observe", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Partitions share the bdi of their disk
+    let disk = sysfs.resolve_name(&bdev.name)?;
+    println!("Backing device: {} ({}:{})", disk.name, disk.major, disk.minor);
+    Ok(Some((disk.major, disk.minor)))
+}
+
+/// Test file created by this run and removed when it ends
+struct TestFile(PathBuf);
+
+impl TestFile {
+    /// Create `path`, refusing anything that exists so no data file or device node is overwritten
+    fn create(path: &Path) -> Result<TestFile, Box<dyn Error>> {
+        OpenOptions::new().write(true).create_new(true).open(path).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => format!(
+                "{} already exists; pass a directory or the path of a new file (remove it if an interrupted run left it)",
+                path.display()
+            ),
+            _ => format!("Failed to create {}: {}", path.display(), e),
+        })?;
+        Ok(TestFile(path.to_path_buf()))
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            eprintln!("Failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Where the plots of this run go
+struct Output {
+    dir: PathBuf,
+    formats: Vec<OutputFormat>,
+    /// Plots collected for the HTML report, as (name, SVG document)
+    charts: Vec<(String, String)>,
+}
+
+/// Draw a plot in every requested format, like 01_measure does
+///
+/// `$draw` is expanded once per backend with `$root` bound to its drawing
+/// area, so it may call any function generic over `DrawingBackend`.
+macro_rules! render {
+    ($output:expr, $name:expr, $size:expr, |$root:ident| $draw:block) => {{
+        let output: &mut Output = $output;
+        let name: String = $name;
+        for format in output.formats.clone() {
+            match format {
+                OutputFormat::Png => {
+                    let file = output.dir.join(format!("{}.png", name));
+                    let $root = BitMapBackend::new(&file, $size).into_drawing_area();
+                    $draw
+                    $root.present()?;
+                    println!("Generated: {}", file.display());
+                }
+                OutputFormat::Svg => {
+                    let file = output.dir.join(format!("{}.svg", name));
+                    let $root = SVGBackend::new(&file, $size).into_drawing_area();
+                    $draw
+                    $root.present()?;
+                    println!("Generated: {}", file.display());
+                }
+                OutputFormat::Html => {
+                    let mut svg = String::new();
+                    {
+                        let $root = SVGBackend::with_string(&mut svg, $size).into_drawing_area();
+                        $draw
+                        $root.present()?;
+                    }
+                    output.charts.push((name.clone(), svg));
+                }
+            }
+        }
+    }};
+}
+
+fn mib(bytes: f64) -> f64 {
+    bytes / 1048576.0
+}
+
+fn print_result(result: &WritebackResult) {
+    let lat = result.write_latency.summary();
+    println!(
+        "  Throughput: {:.2} MiB/s, write() p50={:.2} p99={:.2} max={:.2} usec",
+        mib(result.bw_bytes()), lat.p50, lat.p99, lat.max
+    );
+    println!(
+        "  Throttled: {} writes of {} ms or more, {:.2} s in total ({:.1}% of the run)",
+        result.stalls,
+        writeback::STALL.as_millis(),
+        result.stalled.as_secs_f64(),
+        result.stalled.as_secs_f64() / result.elapsed.as_secs_f64() * 100.0
+    );
+    let peak = result.samples.iter().max_by_key(|s| s.dirty).copied().unwrap_or_default();
+    println!(
+        "  Peak dirty: {:.1} MiB (threshold {:.1} MiB, background {:.1} MiB), final fsync: {:.2} s",
+        mib(peak.dirty as f64),
+        mib(peak.dirty_thresh as f64),
+        mib(peak.background_thresh as f64),
+        result.fsync.as_secs_f64()
+    );
+}
+
+/// Throughput, write(2) stalls and dirty memory of one run, one panel each
+fn plot_run<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, caption: &str, samples: &[DirtySample]) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let root = root.titled(caption, ("sans-serif", 20).into_font())?;
+    let panels = root.split_evenly((3, 1));
+
+    let max_x = samples.iter().map(|s| s.time).fold(f64::EPSILON, f64::max);
+    let max_of = |value: &dyn Fn(&DirtySample) -> f64| {
+        let max = samples.iter().map(value).fold(0.0f64, f64::max);
+        if max > 0.0 { max * 1.1 } else { 1.0 }
+    };
+
+    // Throughput
+    let mut chart = ChartBuilder::on(&panels[0])
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..max_x, 0f64..max_of(&|s| mib(s.write_bps)))?;
+    chart.configure_mesh().x_desc("Time (s)").y_desc("Write (MiB/s)").draw()?;
+    chart.draw_series(LineSeries::new(samples.iter().map(|s| (s.time, mib(s.write_bps))), BLUE.stroke_width(2)))?;
+
+    // Longest write(2) per interval; throttled intervals are marked
+    let mut chart = ChartBuilder::on(&panels[1])
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..max_x, 0f64..max_of(&|s| s.max_write_usec / 1000.0))?;
+    chart.configure_mesh().x_desc("Time (s)").y_desc("Longest write (ms)").draw()?;
+    chart.draw_series(LineSeries::new(samples.iter().map(|s| (s.time, s.max_write_usec / 1000.0)), RED.stroke_width(2)))?;
+    let stall_ms = writeback::STALL.as_secs_f64() * 1000.0;
+    chart.draw_series(
+        samples.iter()
+            .filter(|s| s.max_write_usec / 1000.0 >= stall_ms)
+            .map(|s| TriangleMarker::new((s.time, s.max_write_usec / 1000.0), 4, RED.filled())),
+    )?;
+
+    // Dirty memory against the thresholds
+    let mut chart = ChartBuilder::on(&panels[2])
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..max_x, 0f64..max_of(&|s| mib(s.dirty.max(s.dirty_thresh) as f64)))?;
+    chart.configure_mesh().x_desc("Time (s)").y_desc("MiB").draw()?;
+
+    type Line = (&'static str, RGBColor, bool, fn(&DirtySample) -> Option<u64>);
+    let lines: [Line; 5] = [
+        ("Dirty", BLUE, false, |s| Some(s.dirty)),
+        ("Writeback", GREEN, false, |s| Some(s.writeback)),
+        ("dirty threshold", RED, true, |s| Some(s.dirty_thresh)),
+        ("background threshold", MAGENTA, true, |s| Some(s.background_thresh)),
+        ("device dirty threshold", BLACK, true, |s| s.bdi.map(|bdi| bdi.dirty_thresh)),
+    ];
+    for (label, color, dashed, value) in lines {
+        let points: Vec<(f64, f64)> = samples.iter()
+            .filter_map(|s| Some((s.time, mib(value(s)? as f64))))
+            .collect();
+        if points.is_empty() {
+            continue;
+        }
+        if dashed {
+            chart.draw_series(DashedLineSeries::new(points, 5, 5, color.stroke_width(1)))?
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], color.stroke_width(1)));
+        } else {
+            chart.draw_series(LineSeries::new(points, color.stroke_width(2)))?
+                .label(label)
+                .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], color.stroke_width(2)));
+        }
+    }
+    chart.configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    Ok(())
+}
+
+/// Throughput over time of every setting in one graph
+fn plot_throughput<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    runs: &[(DirtySettings, Vec<DirtySample>)],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let samples = runs.iter().flat_map(|(_, samples)| samples);
+    let max_x = samples.clone().map(|s| s.time).fold(f64::EPSILON, f64::max);
+    let max_y = samples.map(|s| mib(s.write_bps)).fold(1.0f64, f64::max);
+
+    let mut chart = ChartBuilder::on(root)
+        .caption("Buffered write throughput", ("sans-serif", 30).into_font())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..max_x, 0f64..max_y * 1.1)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Time (s)")
+        .y_desc("Write (MiB/s)")
+        .draw()?;
+
+    for (i, (settings, samples)) in runs.iter().enumerate() {
+        let color = SERIES_COLORS[i % SERIES_COLORS.len()];
+        chart.draw_series(LineSeries::new(samples.iter().map(|s| (s.time, mib(s.write_bps))), color.stroke_width(2)))?
+            .label(settings.label())
+            .legend(move |(x, y)| PathElement::new(vec![(x - 5, y), (x + 5, y)], color.stroke_width(2)));
+    }
+
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+
+    let mut size_mb: u64 = 4096;
+    let mut runtime: u64 = 30;
+    let mut interval_ms: u64 = 250;
+    let mut block_size: usize = 1 << 20;
+    let mut ratios = None;
+    let mut background_ratios = None;
+    let mut expires = None;
+    let mut formats = vec![OutputFormat::Png];
+    let mut path = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().cloned().unwrap_or_else(|| usage(prog_name));
+        match arg.as_str() {
+            "--size" => size_mb = value().parse().unwrap_or_else(|_| usage(prog_name)),
+            "--runtime" => runtime = value().parse().unwrap_or_else(|_| usage(prog_name)),
+            "--interval" => interval_ms = value().parse().unwrap_or_else(|_| usage(prog_name)),
+            "--bs" => block_size = value().parse().unwrap_or_else(|_| usage(prog_name)),
+            "--ratio" => ratios = Some(parse_list(&value()).unwrap_or_else(|| usage(prog_name))),
+            "--background-ratio" => background_ratios = Some(parse_list(&value()).unwrap_or_else(|| usage(prog_name))),
+            "--expire" => expires = Some(parse_list(&value()).unwrap_or_else(|| usage(prog_name))),
+            "--format" => {
+                formats = value().split(',').map(OutputFormat::parse).collect::<Option<_>>().unwrap_or_else(|| usage(prog_name))
+            }
+            _ if arg.starts_with('-') || path.is_some() => usage(prog_name),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let Some(mut path) = path else {
+        usage(prog_name);
+    };
+    if path.is_dir() {
+        path = path.join(WRITEBACK_FILE);
+    }
+
+    println!("=== Writeback Experiment ===\n");
+
+    // Restore settings left behind by an earlier run that was killed
+    let journal = Path::new(tunables::DEFAULT_JOURNAL);
+    for device in tunables::repair(journal)? {
+        println!("Repaired tunables of {} left by an interrupted run", device);
+    }
+
+    // Restore the sysctls on Ctrl+C too; must happen before any thread is spawned
+    tunables::install_signal_handler()?;
+
+    // The file has to exist to find the device behind it
+    let test_file = TestFile::create(&path)?;
+    println!("Target: {}", path.display());
+    let bdi = bdi_of(&path)?;
+
+    let original = DirtySettings::current()?;
+    println!(
+        "Original vm.dirty_ratio={} dirty_background_ratio={} dirty_expire_centisecs={}",
+        original.ratio, original.background_ratio, original.expire_centisecs
+    );
+    let guard = TunableGuard::vm(journal)?;
+
+    let mut sweep = Vec::new();
+    for &ratio in ratios.as_deref().unwrap_or(&[original.ratio]) {
+        for &background_ratio in background_ratios.as_deref().unwrap_or(&[original.background_ratio]) {
+            for &expire_centisecs in expires.as_deref().unwrap_or(&[original.expire_centisecs]) {
+                sweep.push(DirtySettings { ratio, background_ratio, expire_centisecs });
+            }
+        }
+    }
+
+    let run_id = results::new_run_id();
+    let run_dir = Path::new(RUNS_DIR).join(run_id.to_string());
+    fs::create_dir_all(&run_dir)?;
+    let mut output = Output { dir: run_dir.clone(), formats, charts: Vec::new() };
+    let mut summary = Table::new(&["Settings", "MiB/s", "Throttled writes", "Throttled (s)", "Final fsync (s)"]);
+
+    let spec = WritebackSpec {
+        path: path.clone(),
+        size: size_mb << 20,
+        block_size,
+        runtime: Duration::from_secs(runtime),
+        interval: Duration::from_millis(interval_ms),
+        bdi,
+    };
+
+    let mut runs = Vec::new();
+    for settings in &sweep {
+        println!("\n--- {} ---", settings.label());
+        settings.apply()?;
+
+        // Start every run without dirty or cached pages of earlier ones
+        pagecache::drop_caches()?;
+
+        println!("Writing {} for {} s...", path.display(), runtime);
+        let result = writeback::run(&spec)?;
+        print_result(&result);
+
+        let name = format!("writeback-{}", settings.label());
+        fs::write(
+            run_dir.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&result.to_json(&spec, settings))?,
+        )?;
+        render!(&mut output, name, (800, 900), |root| {
+            plot_run(&root, &settings.label(), &result.samples)?;
+        });
+        summary.push(vec![
+            settings.label(),
+            format!("{:.2}", mib(result.bw_bytes())),
+            result.stalls.to_string(),
+            format!("{:.2}", result.stalled.as_secs_f64()),
+            format!("{:.2}", result.fsync.as_secs_f64()),
+        ]);
+        runs.push((*settings, result.samples));
+    }
+
+    println!("\n=== Restoring Original Settings ===\n");
+    guard.restore()?;
+    drop(test_file);
+
+    render!(&mut output, "writeback-throughput".to_string(), (800, 600), |root| {
+        plot_throughput(&root, &runs)?;
+    });
+
+    if output.formats.contains(&OutputFormat::Html) {
+        let mut report = Report::new(format!("Writeback experiment run {}", run_id));
+        report.heading(2, "Runs");
+        report.paragraph(&format!(
+            "Target: {}, a {} MiB file written in blocks of {} bytes for {} s per run",
+            path.display(), size_mb, block_size, runtime
+        ));
+        report.table(&summary);
+        report.heading(2, "Plots");
+        for (name, svg) in &output.charts {
+            report.chart(name, svg);
+        }
+        let report_path = run_dir.join("report.html");
+        report.save(&report_path)?;
+        println!("Generated: {}", report_path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Minimal Linux native AIO (io_setup/io_submit/io_getevents) through raw syscalls.
+
+use std::io;
+use std::ptr;
+
+const IOCB_CMD_PREAD: u16 = 0;
+const IOCB_CMD_PWRITE: u16 = 1;
+
+/// `struct iocb` from <linux/aio_abi.h> (little endian layout)
+#[repr(C)]
+#[derive(Default)]
+struct Iocb {
+    aio_data: u64,
+    aio_key: u32,
+    aio_rw_flags: i32,
+    aio_lio_opcode: u16,
+    aio_reqprio: i16,
+    aio_fildes: u32,
+    aio_buf: u64,
+    aio_nbytes: u64,
+    aio_offset: i64,
+    aio_reserved2: u64,
+    aio_flags: u32,
+    aio_resfd: u32,
+}
+
+/// `struct io_event` from <linux/aio_abi.h>
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct IoEvent {
+    data: u64,
+    obj: u64,
+    res: i64,
+    res2: i64,
+}
+
+/// AIO context with room for `depth` requests in flight
+pub struct AioContext {
+    ctx: libc::c_ulong,
+    iocbs: Vec<Iocb>,
+    pending: Vec<*mut Iocb>,
+    events: Vec<IoEvent>,
+    /// Requests accepted by the kernel and not reaped yet
+    in_flight: usize,
+}
+
+// The raw pointers only refer to memory owned by the context itself
+unsafe impl Send for AioContext {}
+
+impl AioContext {
+    pub fn new(depth: usize) -> io::Result<Self> {
+        let mut ctx: libc::c_ulong = 0;
+        let ret = unsafe { libc::syscall(libc::SYS_io_setup, depth as libc::c_long, &mut ctx) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(AioContext {
+            ctx,
+            iocbs: (0..depth).map(|_| Iocb::default()).collect(),
+            pending: Vec::with_capacity(depth),
+            events: vec![IoEvent::default(); depth],
+            in_flight: 0,
+        })
+    }
+
+    /// Queue a read or write for `slot`; it is sent to the kernel by `submit`
+    ///
+    /// The buffer must stay valid until the completion for `slot` is reaped.
+    pub fn prepare(&mut self, slot: usize, fd: i32, write: bool, buf: *mut u8, len: usize, offset: u64) {
+        let iocb = &mut self.iocbs[slot];
+        *iocb = Iocb {
+            aio_data: slot as u64,
+            aio_lio_opcode: if write { IOCB_CMD_PWRITE } else { IOCB_CMD_PREAD },
+            aio_fildes: fd as u32,
+            aio_buf: buf as u64,
+            aio_nbytes: len as u64,
+            aio_offset: offset as i64,
+            ..Iocb::default()
+        };
+        self.pending.push(iocb);
+    }
+
+    /// Submit all prepared requests; on error the requests accepted before
+    /// it stay in flight and the others are dropped
+    pub fn submit(&mut self) -> io::Result<()> {
+        let mut done = 0;
+        while done < self.pending.len() {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_submit,
+                    self.ctx,
+                    (self.pending.len() - done) as libc::c_long,
+                    self.pending[done..].as_mut_ptr(),
+                )
+            };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.pending.clear();
+                return Err(err);
+            }
+            if ret == 0 {
+                self.pending.clear();
+                return Err(io::Error::other("io_submit accepted no requests"));
+            }
+            done += ret as usize;
+            self.in_flight += ret as usize;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Wait for at least `min` completions and append (slot, result) pairs to `out`
+    pub fn wait(&mut self, min: usize, out: &mut Vec<(usize, i64)>) -> io::Result<()> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_getevents,
+                self.ctx,
+                min as libc::c_long,
+                self.events.len() as libc::c_long,
+                self.events.as_mut_ptr(),
+                ptr::null_mut::<libc::timespec>(),
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err);
+        }
+
+        out.extend(self.events[..ret as usize].iter().map(|ev| (ev.data as usize, ev.res)));
+        self.in_flight -= ret as usize;
+        Ok(())
+    }
+
+    /// Number of submitted requests whose completion has not been reaped
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+}
+
+impl Drop for AioContext {
+    fn drop(&mut self) {
+        unsafe {
+            libc::syscall(libc::SYS_io_destroy, self.ctx);
+        }
+    }
+}
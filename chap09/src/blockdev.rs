@@ -0,0 +1,577 @@
+//! Block device discovery and queue tunables through sysfs and ioctls.
+//!
+//! Any path can be resolved to the block device behind it: a whole disk, a
+//! partition (which shares the queue of its disk), a device-mapper or md
+//! device, or a regular file on a filesystem (resolved through the device
+//! number of the filesystem). All sysfs paths are built from a root directory,
+//! so a fake tree mirroring `/sys/dev/block`, `/sys/class/block` and the
+//! `devices` directories they link to can stand in for real disks.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+// Block layer ioctls from <linux/fs.h>
+const BLKRASET: libc::c_ulong = 0x1262;
+const BLKRAGET: libc::c_ulong = 0x1263;
+const BLKSSZGET: libc::c_ulong = 0x1268;
+const BLKGETSIZE64: libc::c_ulong = 0x8008_1272;
+
+/// Errors of block device discovery and tunable access
+#[derive(Debug)]
+pub enum BlockDevError {
+    /// The path does not exist
+    NotFound(PathBuf),
+    /// The path is neither a block device nor a regular file
+    Unsupported(PathBuf),
+    /// The file lives on a filesystem without a block device (tmpfs, overlayfs, NFS, ...)
+    NoBackingDevice { path: PathBuf, major: u32, minor: u32 },
+    /// Sysfs has no entry for the device or the entry lacks a queue
+    NoQueue { device: String, dir: PathBuf },
+    /// The kernel does not provide this queue attribute for the device
+    NoAttribute { device: String, attr: String },
+    /// The value is not one the device accepts
+    InvalidValue { device: String, attr: String, value: String, accepted: Vec<String> },
+    /// A sysfs attribute held something unexpected
+    Parse { path: PathBuf, content: String },
+    /// Any other failure while accessing `path`
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for BlockDevError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockDevError::NotFound(path) => write!(f, "{} does not exist", path.display()),
+            BlockDevError::Unsupported(path) => {
+                write!(f, "{} is neither a block device nor a regular file", path.display())
+            }
+            BlockDevError::NoBackingDevice { path, major, minor } => write!(
+                f,
+                "{} is on a filesystem without a block device (device {}:{})",
+                path.display(),
+                major,
+                minor
+            ),
+            BlockDevError::NoQueue { device, dir } => {
+                write!(f, "No block queue for {} under {}", device, dir.display())
+            }
+            BlockDevError::NoAttribute { device, attr } => {
+                write!(f, "{} has no queue attribute {}", device, attr)
+            }
+            BlockDevError::InvalidValue { device, attr, value, accepted } => {
+                write!(f, "{} does not accept {}={}", device, attr, value)?;
+                if !accepted.is_empty() {
+                    write!(f, " (accepted: {})", accepted.join(", "))?;
+                }
+                Ok(())
+            }
+            BlockDevError::Parse { path, content } => {
+                write!(f, "Unexpected content {:?} in {}", content.trim(), path.display())
+            }
+            BlockDevError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for BlockDevError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlockDevError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, BlockDevError>;
+
+fn io_error(path: &Path, source: io::Error) -> BlockDevError {
+    if source.kind() == io::ErrorKind::NotFound {
+        BlockDevError::NotFound(path.to_path_buf())
+    } else {
+        BlockDevError::Io { path: path.to_path_buf(), source }
+    }
+}
+
+/// What kind of path a `BlockDevice` was resolved from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    /// Device node of a whole disk (including dm and md devices)
+    Disk,
+    /// Device node of a partition
+    Partition,
+    /// Regular file on a filesystem backed by the device
+    File,
+}
+
+/// Sysfs tree used for discovery, normally mounted at `/sys`
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    root: PathBuf,
+}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sysfs {
+    /// The real sysfs
+    pub fn new() -> Self {
+        Sysfs { root: PathBuf::from("/sys") }
+    }
+
+    /// A sysfs tree rooted at `root` instead of `/sys`, e.g. a fake one for testing
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Sysfs { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve a device node or a file on a filesystem to its block device
+    pub fn resolve(&self, path: impl AsRef<Path>) -> Result<BlockDevice> {
+        let path = path.as_ref();
+        let metadata = fs::metadata(path).map_err(|e| io_error(path, e))?;
+
+        let (dev, file) = if metadata.file_type().is_block_device() {
+            (metadata.rdev(), false)
+        } else if metadata.is_file() {
+            (metadata.dev(), true)
+        } else {
+            return Err(BlockDevError::Unsupported(path.to_path_buf()));
+        };
+        let (major, minor) = (libc::major(dev), libc::minor(dev));
+
+        let mut device = self.resolve_devnum(major, minor).map_err(|e| match e {
+            BlockDevError::NotFound(_) if file => BlockDevError::NoBackingDevice {
+                path: path.to_path_buf(),
+                major,
+                minor,
+            },
+            e => e,
+        })?;
+        device.path = path.to_path_buf();
+        if file {
+            device.kind = TargetKind::File;
+        }
+        Ok(device)
+    }
+
+    /// Resolve a device number through `dev/block/<major>:<minor>`
+    pub fn resolve_devnum(&self, major: u32, minor: u32) -> Result<BlockDevice> {
+        let link = self.root.join("dev/block").join(format!("{}:{}", major, minor));
+        self.device_at(&link, major, minor)
+    }
+
+    /// Resolve a kernel device name (sda, sda1, dm-0, md127) through `class/block`
+    pub fn resolve_name(&self, name: &str) -> Result<BlockDevice> {
+        let dir = self.root.join("class/block").join(name);
+        let (major, minor) = read_devnum(&dir)?;
+        self.device_at(&dir, major, minor)
+    }
+
+    fn device_at(&self, link: &Path, major: u32, minor: u32) -> Result<BlockDevice> {
+        let dev_dir = fs::canonicalize(link).map_err(|e| io_error(link, e))?;
+
+        // Partitions have no queue of their own and use the one of their disk
+        let (kind, disk_dir) = if dev_dir.join("partition").exists() {
+            let parent = dev_dir.parent().unwrap_or(&dev_dir).to_path_buf();
+            (TargetKind::Partition, parent)
+        } else {
+            (TargetKind::Disk, dev_dir.clone())
+        };
+
+        let name = file_name(&disk_dir)?;
+        if !disk_dir.join("queue").is_dir() {
+            return Err(BlockDevError::NoQueue { device: name, dir: disk_dir });
+        }
+
+        Ok(BlockDevice {
+            sysfs: self.clone(),
+            path: PathBuf::from("/dev").join(file_name(&dev_dir)?),
+            kind,
+            major,
+            minor,
+            name,
+            dev_dir,
+            disk_dir,
+        })
+    }
+}
+
+fn file_name(dir: &Path) -> Result<String> {
+    dir.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| BlockDevError::NotFound(dir.to_path_buf()))
+}
+
+/// Read a sysfs attribute, mapping a missing file to `NotFound`
+fn read_attr(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| io_error(path, e))
+}
+
+fn parse_attr<T: std::str::FromStr>(path: &Path) -> Result<T> {
+    let content = read_attr(path)?;
+    content.trim().parse().map_err(|_| BlockDevError::Parse { path: path.to_path_buf(), content })
+}
+
+/// The `dev` attribute ("major:minor") of a sysfs device directory
+fn read_devnum(dir: &Path) -> Result<(u32, u32)> {
+    let path = dir.join("dev");
+    let content = read_attr(&path)?;
+    let parsed = content
+        .trim()
+        .split_once(':')
+        .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)));
+    parsed.ok_or(BlockDevError::Parse { path, content })
+}
+
+/// Split "mq-deadline kyber [none]" into the active and the available schedulers
+pub fn parse_scheduler(content: &str) -> (Option<String>, Vec<String>) {
+    let mut current = None;
+    let mut available = Vec::new();
+    for part in content.split_whitespace() {
+        let name = part.trim_matches(|c| c == '[' || c == ']');
+        if part.starts_with('[') {
+            current = Some(name.to_string());
+        }
+        available.push(name.to_string());
+    }
+    (current, available)
+}
+
+/// A block device together with the disk whose queue serves it
+#[derive(Debug, Clone)]
+pub struct BlockDevice {
+    sysfs: Sysfs,
+    /// Path the device was resolved from (the device node for names and numbers)
+    pub path: PathBuf,
+    pub kind: TargetKind,
+    /// Device number of the partition or disk holding `path`
+    pub major: u32,
+    pub minor: u32,
+    /// Kernel name of the disk that owns the queue
+    pub name: String,
+    /// Sysfs directory of the partition or disk
+    dev_dir: PathBuf,
+    /// Sysfs directory of the disk, which contains `queue`
+    disk_dir: PathBuf,
+}
+
+impl BlockDevice {
+    /// Sysfs queue directory of the disk
+    pub fn queue_dir(&self) -> PathBuf {
+        self.disk_dir.join("queue")
+    }
+
+    /// Whether ioctls can be issued on `path`
+    fn is_node(&self) -> bool {
+        self.kind != TargetKind::File
+            && fs::metadata(&self.path).is_ok_and(|m| m.file_type().is_block_device())
+    }
+
+    /// Read a queue attribute, trimmed
+    pub fn queue_attr(&self, attr: &str) -> Result<String> {
+        match read_attr(&self.queue_dir().join(attr)) {
+            Ok(content) => Ok(content.trim().to_string()),
+            Err(BlockDevError::NotFound(_)) => Err(self.no_attribute(attr)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a queue attribute; EINVAL from the kernel becomes `InvalidValue`
+    pub fn set_queue_attr(&self, attr: &str, value: &str) -> Result<()> {
+        let path = self.queue_dir().join(attr);
+        fs::write(&path, value).map_err(|e| match e.raw_os_error() {
+            Some(libc::ENOENT) => self.no_attribute(attr),
+            Some(libc::EINVAL) => BlockDevError::InvalidValue {
+                device: self.name.clone(),
+                attr: attr.to_string(),
+                value: value.to_string(),
+                accepted: Vec::new(),
+            },
+            _ => BlockDevError::Io { path, source: e },
+        })
+    }
+
+    fn no_attribute(&self, attr: &str) -> BlockDevError {
+        BlockDevError::NoAttribute { device: self.name.clone(), attr: attr.to_string() }
+    }
+
+    /// Active scheduler and the ones the queue can switch to
+    pub fn schedulers(&self) -> Result<(String, Vec<String>)> {
+        let path = self.queue_dir().join("scheduler");
+        let content = self.queue_attr("scheduler")?;
+        match parse_scheduler(&content) {
+            (Some(current), available) => Ok((current, available)),
+            (None, _) => Err(BlockDevError::Parse { path, content }),
+        }
+    }
+
+    pub fn scheduler(&self) -> Result<String> {
+        Ok(self.schedulers()?.0)
+    }
+
+    /// Switch the I/O scheduler, refusing names the queue does not offer
+    pub fn set_scheduler(&self, scheduler: &str) -> Result<()> {
+        let (_, available) = self.schedulers()?;
+        if !available.iter().any(|name| name == scheduler) {
+            return Err(BlockDevError::InvalidValue {
+                device: self.name.clone(),
+                attr: "scheduler".to_string(),
+                value: scheduler.to_string(),
+                accepted: available,
+            });
+        }
+        self.set_queue_attr("scheduler", scheduler)
+    }
+
+    /// Read-ahead in 512-byte sectors, through BLKRAGET when `path` is a device node
+    pub fn read_ahead(&self) -> Result<u32> {
+        if self.is_node() {
+            let mut sectors: libc::c_long = 0;
+            self.ioctl(BLKRAGET, &mut sectors as *mut libc::c_long as libc::c_ulong)?;
+            return Ok(sectors as u32);
+        }
+        let kb: u32 = self.parse_queue_attr("read_ahead_kb")?;
+        Ok(kb * 2)
+    }
+
+    /// Set the read-ahead in 512-byte sectors, through BLKRASET when `path` is a device node
+    pub fn set_read_ahead(&self, sectors: u32) -> Result<()> {
+        if self.is_node() {
+            return self.ioctl(BLKRASET, sectors as libc::c_ulong);
+        }
+        self.set_queue_attr("read_ahead_kb", &(sectors / 2).to_string())
+    }
+
+    /// Size in bytes of the partition or disk, through BLKGETSIZE64 when possible
+    pub fn size(&self) -> Result<u64> {
+        if self.is_node() {
+            let mut bytes: u64 = 0;
+            self.ioctl(BLKGETSIZE64, &mut bytes as *mut u64 as libc::c_ulong)?;
+            return Ok(bytes);
+        }
+        // The size attribute is always in 512-byte sectors
+        let sectors: u64 = parse_attr(&self.dev_dir.join("size"))?;
+        Ok(sectors * 512)
+    }
+
+    /// Logical sector size in bytes, through BLKSSZGET when possible
+    pub fn logical_block_size(&self) -> Result<u32> {
+        if self.is_node() {
+            let mut bytes: libc::c_int = 0;
+            self.ioctl(BLKSSZGET, &mut bytes as *mut libc::c_int as libc::c_ulong)?;
+            return Ok(bytes as u32);
+        }
+        self.parse_queue_attr("logical_block_size")
+    }
+
+    /// I/O statistics file of the partition or disk (see `iostat`)
+    pub fn stat_path(&self) -> PathBuf {
+        self.dev_dir.join("stat")
+    }
+
+    /// Device number of the disk, which cgroup I/O limits are set on
+    pub fn disk_devnum(&self) -> Result<(u32, u32)> {
+        read_devnum(&self.disk_dir)
+    }
+
+    /// Whether the queue reports a rotational device
+    pub fn rotational(&self) -> Result<bool> {
+        Ok(self.parse_queue_attr::<u32>("rotational")? != 0)
+    }
+
+    /// Model string of the disk, if the driver reports one
+    pub fn model(&self) -> Option<String> {
+        read_attr(&self.disk_dir.join("device/model"))
+            .ok()
+            .map(|model| model.trim().to_string())
+            .filter(|model| !model.is_empty())
+    }
+
+    fn parse_queue_attr<T: std::str::FromStr>(&self, attr: &str) -> Result<T> {
+        match parse_attr(&self.queue_dir().join(attr)) {
+            Err(BlockDevError::NotFound(_)) => Err(self.no_attribute(attr)),
+            result => result,
+        }
+    }
+
+    fn ioctl(&self, request: libc::c_ulong, arg: libc::c_ulong) -> Result<()> {
+        let file = File::open(&self.path).map_err(|e| io_error(&self.path, e))?;
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+        if ret < 0 {
+            return Err(BlockDevError::Io { path: self.path.clone(), source: io::Error::last_os_error() });
+        }
+        Ok(())
+    }
+
+    /// Names of the devices this one is stacked on (dm and md members)
+    pub fn slaves(&self) -> Result<Vec<String>> {
+        let dir = self.disk_dir.join("slaves");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(&dir, e)),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| io_error(&dir, e))?;
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Disks at the bottom of a dm/md stack; just this disk when it is not stacked
+    pub fn backing_disks(&self) -> Result<Vec<BlockDevice>> {
+        let slaves = self.slaves()?;
+        if slaves.is_empty() {
+            return Ok(vec![self.clone()]);
+        }
+
+        let mut disks: Vec<BlockDevice> = Vec::new();
+        for slave in slaves {
+            for disk in self.sysfs.resolve_name(&slave)?.backing_disks()? {
+                if !disks.iter().any(|d| d.name == disk.name) {
+                    disks.push(disk);
+                }
+            }
+        }
+        Ok(disks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// Fake sysfs with a partitioned disk, a dm device on its partition, an
+    /// md array on two more disks and a device without a queue
+    fn fake_sysfs() -> TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let devices = root.path().join("devices");
+        let scsi = devices.join("pci0000:00/0000:00:10.0/host0/block");
+        let virt = devices.join("virtual/block");
+
+        let add = |dir: &Path, name: &str, devnum: &str, queue: bool| {
+            let dev_dir = dir.join(name);
+            fs::create_dir_all(&dev_dir).unwrap();
+            fs::write(dev_dir.join("dev"), format!("{}\n", devnum)).unwrap();
+            fs::write(dev_dir.join("size"), "2048\n").unwrap();
+            if queue {
+                fs::create_dir(dev_dir.join("queue")).unwrap();
+                fs::write(dev_dir.join("queue/scheduler"), "mq-deadline kyber [none]\n").unwrap();
+            }
+            for link in [format!("dev/block/{}", devnum), format!("class/block/{}", name)] {
+                let link = root.path().join(link);
+                fs::create_dir_all(link.parent().unwrap()).unwrap();
+                symlink(&dev_dir, link).unwrap();
+            }
+            dev_dir
+        };
+        let slaves = |dev_dir: &Path, names: &[&str]| {
+            fs::create_dir(dev_dir.join("slaves")).unwrap();
+            for name in names {
+                symlink(root.path().join("class/block").join(name), dev_dir.join("slaves").join(name)).unwrap();
+            }
+        };
+
+        let sda = add(&scsi, "sda", "8:0", true);
+        let sda1 = add(&sda, "sda1", "8:1", false);
+        fs::write(sda1.join("partition"), "1\n").unwrap();
+        add(&scsi, "sdb", "8:16", true);
+        add(&scsi, "sdc", "8:32", true);
+        let dm = add(&virt, "dm-0", "253:0", true);
+        slaves(&dm, &["sda1"]);
+        let md = add(&virt, "md127", "9:127", true);
+        slaves(&md, &["sdc", "sdb"]);
+        add(&virt, "noqueue0", "7:0", false);
+        root
+    }
+
+    #[test]
+    fn partition_uses_the_queue_of_its_disk() {
+        let root = fake_sysfs();
+        let sysfs = Sysfs::with_root(root.path());
+
+        let part = sysfs.resolve_devnum(8, 1).unwrap();
+        assert_eq!(part.kind, TargetKind::Partition);
+        assert_eq!(part.name, "sda");
+        assert_eq!(part.path, Path::new("/dev/sda1"));
+        assert_eq!((part.major, part.minor), (8, 1));
+        assert!(part.queue_dir().ends_with("block/sda/queue"));
+        assert!(part.stat_path().ends_with("sda/sda1/stat"));
+        assert_eq!(part.disk_devnum().unwrap(), (8, 0));
+
+        let disk = sysfs.resolve_name("sda").unwrap();
+        assert_eq!(disk.kind, TargetKind::Disk);
+        assert_eq!(disk.queue_dir(), part.queue_dir());
+    }
+
+    #[test]
+    fn stacked_devices_resolve_to_their_disks() {
+        let root = fake_sysfs();
+        let sysfs = Sysfs::with_root(root.path());
+
+        let dm = sysfs.resolve_name("dm-0").unwrap();
+        assert_eq!(dm.kind, TargetKind::Disk);
+        assert_eq!(dm.slaves().unwrap(), ["sda1"]);
+        let names: Vec<String> = dm.backing_disks().unwrap().into_iter().map(|disk| disk.name).collect();
+        assert_eq!(names, ["sda"]);
+
+        let md = sysfs.resolve_devnum(9, 127).unwrap();
+        assert_eq!(md.slaves().unwrap(), ["sdb", "sdc"]);
+        let names: Vec<String> = md.backing_disks().unwrap().into_iter().map(|disk| disk.name).collect();
+        assert_eq!(names, ["sdb", "sdc"]);
+
+        let sdb = sysfs.resolve_name("sdb").unwrap();
+        let names: Vec<String> = sdb.backing_disks().unwrap().into_iter().map(|disk| disk.name).collect();
+        assert_eq!(names, ["sdb"]);
+    }
+
+    #[test]
+    fn missing_queue_or_device_is_reported() {
+        let root = fake_sysfs();
+        let sysfs = Sysfs::with_root(root.path());
+
+        match sysfs.resolve_name("noqueue0") {
+            Err(BlockDevError::NoQueue { device, .. }) => assert_eq!(device, "noqueue0"),
+            other => panic!("expected NoQueue, got {:?}", other),
+        }
+        assert!(matches!(sysfs.resolve_devnum(8, 48), Err(BlockDevError::NotFound(_))));
+        assert!(matches!(sysfs.resolve_name("sdz"), Err(BlockDevError::NotFound(_))));
+    }
+
+    #[test]
+    fn scheduler_is_read_and_checked_from_the_queue() {
+        let root = fake_sysfs();
+        let disk = Sysfs::with_root(root.path()).resolve_name("sdb").unwrap();
+
+        assert_eq!(disk.scheduler().unwrap(), "none");
+        assert!(matches!(disk.queue_attr("nr_requests"), Err(BlockDevError::NoAttribute { .. })));
+        match disk.set_scheduler("bfq") {
+            Err(BlockDevError::InvalidValue { accepted, .. }) => assert_eq!(accepted, ["mq-deadline", "kyber", "none"]),
+            other => panic!("expected InvalidValue, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn scheduler_list_is_split() {
+        assert_eq!(
+            parse_scheduler("[mq-deadline] none\n"),
+            (Some("mq-deadline".to_string()), vec!["mq-deadline".to_string(), "none".to_string()])
+        );
+        assert_eq!(parse_scheduler("none").0, None);
+    }
+}
@@ -0,0 +1,413 @@
+//! Temporary cgroup v2 groups for block I/O control experiments.
+//!
+//! The io controller limits a group's bandwidth and IOPS on a device through
+//! `io.max`, and shares a device between groups in proportion to their
+//! weight. The weight only takes effect through a proportional controller:
+//! BFQ reads the group's `io.bfq.weight`, and with any scheduler the io.cost
+//! controller reads `io.weight` once it is enabled for the device through
+//! `io.cost.qos` in the root group. Under mq-deadline or none without io.cost,
+//! weights are ignored and only the limits apply.
+//!
+//! A session creates `chap09-<pid>` below the cgroup2 mount and one child per
+//! tenant in it. The io controller is not threaded, so each tenant's
+//! benchmark runs in a process of its own that joins its group when it is
+//! executed. `io.stat` and `io.pressure` of each group give the kernel's side
+//! of what the group did and how long it was stalled.
+//!
+//! On a host that still mounts the v1 blkio hierarchy, the io controller only
+//! becomes available to cgroup v2 once that hierarchy is gone.
+
+use crate::tunables::{Snapshot, TunableGuard};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// Default of io.weight and io.bfq.weight
+pub const DEFAULT_WEIGHT: u32 = 100;
+
+/// Prefix of the session groups below the cgroup2 mount
+const SESSION_PREFIX: &str = "chap09-";
+
+/// Limits of io.max; `None` means "max", i.e. unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IoLimits {
+    /// Read and write bytes per second
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    /// Read and write I/Os per second
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
+impl IoLimits {
+    fn fields(&self) -> [(&'static str, Option<u64>); 4] {
+        [("rbps", self.rbps), ("wbps", self.wbps), ("riops", self.riops), ("wiops", self.wiops)]
+    }
+
+    /// Line written to io.max, e.g. "8:16 rbps=max wbps=1048576 riops=max wiops=max"
+    pub fn io_max(&self, major: u32, minor: u32) -> String {
+        let mut line = format!("{}:{}", major, minor);
+        for (key, value) in self.fields() {
+            line.push_str(&format!(" {}={}", key, value.map_or("max".to_string(), |v| v.to_string())));
+        }
+        line
+    }
+
+    /// The limits that are set, e.g. "wbps=1048576 riops=500"; empty without any
+    pub fn label(&self) -> String {
+        self.fields()
+            .iter()
+            .filter_map(|(key, value)| value.map(|v| format!("{}={}", key, v)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// IOPS the limits allow a job of `block_size` blocks that reads and/or
+    /// writes; `None` if some direction it uses is unlimited
+    pub fn iops_limit(&self, block_size: usize, reads: bool, writes: bool) -> Option<f64> {
+        let direction = |bps: Option<u64>, iops: Option<u64>| {
+            let by_bytes = bps.map(|bps| bps as f64 / block_size as f64);
+            match (by_bytes, iops.map(|iops| iops as f64)) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        };
+        let read = if reads { direction(self.rbps, self.riops) } else { Some(0.0) };
+        let write = if writes { direction(self.wbps, self.wiops) } else { Some(0.0) };
+        Some(read? + write?)
+    }
+}
+
+/// One tenant of a contention experiment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSpec {
+    /// Name of the group's directory; letters, digits, '-' and '_'
+    pub name: String,
+    /// io.weight from 1 to 10000, also applied as io.bfq.weight (1 to 1000)
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// I/O type of the group's job; defaults to the workload's
+    #[serde(default)]
+    pub rw: Option<String>,
+    #[serde(flatten)]
+    pub limits: IoLimits,
+}
+
+impl GroupSpec {
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(DEFAULT_WEIGHT)
+    }
+
+    /// Name and settings for labels, e.g. "a (weight 200, wbps=1048576)"
+    pub fn label(&self) -> String {
+        let mut settings = vec![format!("weight {}", self.weight())];
+        let limits = self.limits.label();
+        if !limits.is_empty() {
+            settings.push(limits);
+        }
+        format!("{} ({})", self.name, settings.join(", "))
+    }
+}
+
+/// Where cgroup v2 is mounted, from /proc/self/mountinfo
+pub fn mount_point() -> io::Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    mountinfo
+        .lines()
+        .find_map(|line| {
+            // "<id> <parent> <dev> <root> <mount point> <options> ... - <fstype> <source> <options>"
+            let (fields, rest) = line.split_once(" - ")?;
+            (rest.split_whitespace().next()? == "cgroup2").then(|| fields.split_whitespace().nth(4).map(PathBuf::from))?
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cgroup v2 is not mounted"))
+}
+
+/// Counters of one device in io.stat
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoStat {
+    pub rbytes: u64,
+    pub wbytes: u64,
+    pub rios: u64,
+    pub wios: u64,
+}
+
+impl IoStat {
+    /// Find the line of `major:minor` in the contents of io.stat; zero if the
+    /// group has not done any I/O on the device
+    pub fn parse(content: &str, major: u32, minor: u32) -> IoStat {
+        let device = format!("{}:{}", major, minor);
+        let mut stat = IoStat::default();
+        let Some(line) = content.lines().find(|line| line.split_whitespace().next() == Some(device.as_str())) else {
+            return stat;
+        };
+        for (key, value) in line.split_whitespace().skip(1).filter_map(|field| field.split_once('=')) {
+            let value = value.parse().unwrap_or(0);
+            match key {
+                "rbytes" => stat.rbytes = value,
+                "wbytes" => stat.wbytes = value,
+                "rios" => stat.rios = value,
+                "wios" => stat.wios = value,
+                _ => {}
+            }
+        }
+        stat
+    }
+}
+
+/// Time the tasks of a group were stalled on I/O, from io.pressure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pressure {
+    /// Microseconds in which at least one task was stalled
+    pub some_usec: u64,
+    /// Microseconds in which all non-idle tasks were stalled
+    pub full_usec: u64,
+}
+
+impl Pressure {
+    pub fn parse(content: &str) -> Pressure {
+        let total = |kind: &str| {
+            content
+                .lines()
+                .find(|line| line.starts_with(kind))
+                .and_then(|line| line.split_whitespace().find_map(|field| field.strip_prefix("total=")))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+        Pressure { some_usec: total("some"), full_usec: total("full") }
+    }
+}
+
+/// A cgroup directory
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: PathBuf,
+}
+
+impl Cgroup {
+    fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    fn write(&self, name: &str, value: &str) -> io::Result<()> {
+        fs::write(self.file(name), value).map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to write {:?} to {}: {}", value, self.file(name).display(), e))
+        })
+    }
+
+    /// Controllers available to the children of the group
+    pub fn controllers(&self) -> io::Result<Vec<String>> {
+        let content = fs::read_to_string(self.file("cgroup.controllers"))?;
+        Ok(content.split_whitespace().map(str::to_string).collect())
+    }
+
+    /// Enable `controller` for the children; returns whether it was off before
+    pub fn enable_controller(&self, controller: &str) -> io::Result<bool> {
+        let enabled = fs::read_to_string(self.file("cgroup.subtree_control"))?;
+        if enabled.split_whitespace().any(|c| c == controller) {
+            return Ok(false);
+        }
+        self.write("cgroup.subtree_control", &format!("+{}", controller))?;
+        Ok(true)
+    }
+
+    pub fn set_io_max(&self, major: u32, minor: u32, limits: &IoLimits) -> io::Result<()> {
+        self.write("io.max", &limits.io_max(major, minor))
+    }
+
+    /// Set io.weight where the kernel has iocost, and io.bfq.weight where BFQ is available
+    pub fn set_weight(&self, weight: u32) -> io::Result<()> {
+        if self.file("io.weight").exists() {
+            self.write("io.weight", &format!("default {}", weight.clamp(1, 10000)))?;
+        }
+        if self.file("io.bfq.weight").exists() {
+            self.write("io.bfq.weight", &weight.clamp(1, 1000).to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn io_stat(&self, major: u32, minor: u32) -> io::Result<IoStat> {
+        Ok(IoStat::parse(&fs::read_to_string(self.file("io.stat"))?, major, minor))
+    }
+
+    pub fn io_pressure(&self) -> io::Result<Pressure> {
+        Ok(Pressure::parse(&fs::read_to_string(self.file("io.pressure"))?))
+    }
+
+    /// Make `command` move its process into the group before it executes
+    pub fn join_on_exec(&self, command: &mut Command) {
+        let procs = CString::new(self.file("cgroup.procs").as_os_str().as_bytes()).unwrap();
+        // Only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // "0" stands for the writing process
+                let ret = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                if ret < 0 {
+                    return Err(err);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Remove the group, waiting briefly for exiting processes to leave it
+    fn remove_dir(path: &Path) -> io::Result<()> {
+        let mut attempts = 0;
+        loop {
+            match fs::remove_dir(path) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) && attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Remove the session groups of processes that are gone, children first
+fn remove_stale_sessions(root: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|name| name.strip_prefix(SESSION_PREFIX)) else {
+            continue;
+        };
+        if pid.parse::<u32>().is_err() || Path::new("/proc").join(pid).exists() {
+            continue;
+        }
+        if let Ok(children) = fs::read_dir(entry.path()) {
+            for child in children.flatten().filter(|child| child.path().is_dir()) {
+                let _ = Cgroup::remove_dir(&child.path());
+            }
+        }
+        let _ = Cgroup::remove_dir(&entry.path());
+    }
+}
+
+/// The group of this process's experiments, with one child per tenant
+pub struct Session {
+    root: Cgroup,
+    group: Cgroup,
+    /// Whether the io controller was enabled in the root group by us
+    enabled_root_io: bool,
+    groups: Vec<Cgroup>,
+}
+
+impl Session {
+    /// Create `chap09-<pid>` below the cgroup2 mount with the io controller
+    /// enabled for its children
+    pub fn create() -> io::Result<Session> {
+        let root = Cgroup { path: mount_point()? };
+        remove_stale_sessions(&root.path);
+        if !root.controllers()?.iter().any(|c| c == "io") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "The io controller is not available in {}; is the v1 blkio hierarchy still mounted?",
+                    root.path.display()
+                ),
+            ));
+        }
+
+        let enabled_root_io = root.enable_controller("io")?;
+        let path = root.path.join(format!("{}{}", SESSION_PREFIX, std::process::id()));
+        fs::create_dir(&path).map_err(|e| io::Error::new(e.kind(), format!("Failed to create {}: {}", path.display(), e)))?;
+        let group = Cgroup { path };
+        let session = Session { root, group, enabled_root_io, groups: Vec::new() };
+        session.group.enable_controller("io")?;
+        Ok(session)
+    }
+
+    /// Create the group of a tenant with its limits and weight on the disk `major:minor`
+    pub fn add_group(&mut self, spec: &GroupSpec, major: u32, minor: u32) -> io::Result<&Cgroup> {
+        let path = self.group.path.join(&spec.name);
+        fs::create_dir(&path).map_err(|e| io::Error::new(e.kind(), format!("Failed to create {}: {}", path.display(), e)))?;
+        self.groups.push(Cgroup { path });
+        let group = &self.groups[self.groups.len() - 1];
+        group.set_io_max(major, minor, &spec.limits)?;
+        group.set_weight(spec.weight())?;
+        Ok(group)
+    }
+
+    /// The tenant groups, in the order they were added
+    pub fn groups(&self) -> &[Cgroup] {
+        &self.groups
+    }
+
+    /// Remove the tenant groups, e.g. between two configurations
+    pub fn clear(&mut self) -> io::Result<()> {
+        for group in self.groups.drain(..) {
+            Cgroup::remove_dir(&group.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Err(e) = self.clear().and_then(|_| Cgroup::remove_dir(&self.group.path)) {
+            eprintln!("Failed to remove {}: {}", self.group.path.display(), e);
+        }
+        if self.enabled_root_io {
+            // Fails harmlessly while other groups use the controller
+            let _ = self.root.write("cgroup.subtree_control", "-io");
+        }
+    }
+}
+
+/// io.cost enabled on a device for as long as the guard lives
+///
+/// The previous setting is journaled like the queue tunables, so it is also
+/// restored on SIGINT/SIGTERM and by `tunables::repair` after a crash.
+pub struct IoCost {
+    guard: Option<TunableGuard>,
+}
+
+impl IoCost {
+    pub fn enable(major: u32, minor: u32, journal: &Path) -> Result<IoCost, Box<dyn Error>> {
+        let root = Cgroup { path: mount_point()? };
+        let qos = fs::read_to_string(root.file("io.cost.qos")).map_err(|e| {
+            io::Error::new(e.kind(), format!("io.cost is not available (CONFIG_BLK_CGROUP_IOCOST): {}", e))
+        })?;
+        let device = format!("{}:{}", major, minor);
+        let previous = qos
+            .lines()
+            .filter(|line| line.split_whitespace().next() == Some(device.as_str()))
+            .find_map(|line| line.split_whitespace().find_map(|field| field.strip_prefix("enable=")))
+            .unwrap_or("0");
+
+        let snapshot = Snapshot {
+            pid: std::process::id(),
+            device: format!("io.cost {}", device),
+            queue_dir: root.path.clone(),
+            values: vec![("io.cost.qos".to_string(), format!("{} enable={}", device, previous))],
+        };
+        let guard = TunableGuard::from_snapshots(vec![snapshot], journal)?;
+        root.write("io.cost.qos", &format!("{} enable=1", device))?;
+        Ok(IoCost { guard: Some(guard) })
+    }
+}
+
+impl Drop for IoCost {
+    fn drop(&mut self) {
+        if let Some(Err(e)) = self.guard.take().map(TunableGuard::restore) {
+            eprintln!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Log-linear latency histogram with percentile queries.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Every power of two range is split into 2^SUB_BITS buckets (about 3% resolution)
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BITS;
+const NUM_BUCKETS: usize = ((64 - SUB_BITS as usize) + 1) * SUB_BUCKETS as usize;
+
+/// Latency percentiles in microseconds
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    /// Stored as "p99.9"; older result files used "p999"
+    #[serde(rename = "p99.9", alias = "p999")]
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl LatencyStats {
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).expect("Latency stats are plain numbers")
+    }
+}
+
+/// Histogram of latencies in nanoseconds
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bucket index of `value`
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - SUB_BITS;
+    let top = value >> shift;
+    ((shift as u64 + 1) * SUB_BUCKETS + (top - SUB_BUCKETS)) as usize
+}
+
+/// Smallest and largest value that fall into bucket `index`
+fn bucket_range(index: usize) -> (u64, u64) {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return (index, index);
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let top = SUB_BUCKETS + index % SUB_BUCKETS;
+    let lower = top << shift;
+    (lower, lower + ((1u64 << shift) - 1))
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            counts: vec![0; NUM_BUCKETS],
+            total: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Record one latency sample in nanoseconds
+    pub fn record(&mut self, value_ns: u64) {
+        self.counts[bucket_index(value_ns)] += 1;
+        self.total += 1;
+        self.sum = self.sum.saturating_add(value_ns);
+        self.min = self.min.min(value_ns);
+        self.max = self.max.max(value_ns);
+    }
+
+    /// Add all samples of `other` to this histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.total += other.total;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.total as f64
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.total == 0 { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Value below which `percentile` percent of the samples fall
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let (lower, upper) = bucket_range(index);
+                // Report the middle of the bucket, but never outside the observed range
+                return (lower + (upper - lower) / 2).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Mean, p50, p90, p99, p99.9 and max converted to microseconds
+    pub fn summary(&self) -> LatencyStats {
+        let usec = |ns: u64| ns as f64 / 1000.0;
+        LatencyStats {
+            mean: self.mean() / 1000.0,
+            p50: usec(self.percentile(50.0)),
+            p90: usec(self.percentile(90.0)),
+            p99: usec(self.percentile(99.0)),
+            p999: usec(self.percentile(99.9)),
+            max: usec(self.max()),
+        }
+    }
+
+    /// Non-empty buckets as `[[lower_ns, upper_ns, count], ...]`
+    pub fn to_json(&self) -> Value {
+        let buckets: Vec<Value> = self.counts.iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| {
+                let (lower, upper) = bucket_range(index);
+                json!([lower, upper, count])
+            })
+            .collect();
+        Value::Array(buckets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_tile_the_whole_range() {
+        assert_eq!(bucket_range(0), (0, 0));
+        for index in 1..NUM_BUCKETS {
+            assert_eq!(bucket_range(index - 1).1 + 1, bucket_range(index).0, "gap before bucket {}", index);
+        }
+        assert_eq!(bucket_range(NUM_BUCKETS - 1).1, u64::MAX);
+    }
+
+    #[test]
+    fn values_fall_into_their_bucket() {
+        // Exact below SUB_BUCKETS, then 2 values per bucket from 64 on
+        assert_eq!(bucket_range(bucket_index(31)), (31, 31));
+        assert_eq!(bucket_range(bucket_index(63)), (63, 63));
+        assert_eq!(bucket_range(bucket_index(64)), (64, 65));
+        assert_eq!(bucket_index(65), bucket_index(64));
+        assert_eq!(bucket_index(66), bucket_index(64) + 1);
+        assert_eq!(bucket_index(u64::MAX), NUM_BUCKETS - 1);
+
+        for shift in 0..64 {
+            for value in [(1u64 << shift) - 1, 1 << shift, (1 << shift) + 1, (1 << shift) | ((1 << shift) - 1)] {
+                let (lower, upper) = bucket_range(bucket_index(value));
+                assert!(lower <= value && value <= upper, "{} outside [{}, {}]", value, lower, upper);
+                // About 3% resolution
+                assert!((upper - lower) as f64 <= value as f64 / SUB_BUCKETS as f64, "bucket of {} too wide", value);
+            }
+        }
+    }
+
+    #[test]
+    fn percentiles_are_within_one_bucket() {
+        let mut hist = Histogram::new();
+        for usec in 1..=1000u64 {
+            hist.record(usec * 1000);
+        }
+        for (percentile, expected) in [(50.0, 500_000.0), (90.0, 900_000.0), (99.0, 990_000.0)] {
+            let value = hist.percentile(percentile) as f64;
+            assert!((value - expected).abs() <= expected / SUB_BUCKETS as f64, "p{} = {}", percentile, value);
+        }
+        let summary = hist.summary();
+        assert_eq!(summary.mean, 500.5);
+        assert_eq!(summary.max, 1000.0);
+        assert_eq!(hist.percentile(0.0), 1000);
+    }
+
+    #[test]
+    fn percentiles_stay_within_the_observed_range() {
+        let mut hist = Histogram::new();
+        hist.record(1_000_003);
+        assert_eq!(hist.percentile(50.0), 1_000_003);
+        assert_eq!(hist.percentile(100.0), 1_000_003);
+
+        let empty = Histogram::new();
+        assert_eq!((empty.percentile(50.0), empty.min(), empty.max(), empty.mean()), (0, 0, 0, 0.0));
+    }
+
+    #[test]
+    fn extreme_values_are_recorded() {
+        let mut hist = Histogram::new();
+        hist.record(0);
+        hist.record(u64::MAX);
+        hist.record(u64::MAX);
+        assert_eq!((hist.min(), hist.max(), hist.count()), (0, u64::MAX, 3));
+        assert_eq!(hist.percentile(1.0), 0);
+        let (lower, _) = bucket_range(NUM_BUCKETS - 1);
+        assert!(hist.percentile(100.0) >= lower);
+        // The sum saturates instead of overflowing
+        assert_eq!(hist.mean(), u64::MAX as f64 / 3.0);
+
+        let mut merged = Histogram::new();
+        merged.merge(&hist);
+        merged.merge(&Histogram::new());
+        assert_eq!((merged.min(), merged.max(), merged.count()), (0, u64::MAX, 3));
+        assert_eq!(merged.to_json(), json!([[0, 0, 1], [lower, u64::MAX, 2]]));
+    }
+}
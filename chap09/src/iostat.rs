@@ -0,0 +1,257 @@
+//! Device-side I/O statistics, sampled in the background like `iostat -x`.
+//!
+//! `/sys/block/<dev>/stat` (and `/sys/block/<dev>/<part>/stat` for
+//! partitions) holds the same cumulative counters as a line of
+//! `/proc/diskstats`: completed I/Os, merges, sectors and the milliseconds
+//! spent on them per direction, followed by the requests in flight, the time
+//! the device was busy and the time requests spent queued. The rates iostat
+//! reports are differences of two readings divided by the time between them.
+//! See Documentation/admin-guide/iostats.rst in the kernel tree.
+
+use crate::blockdev::{BlockDevError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Size of the sectors the counters are kept in, whatever the device's sector size
+const SECTOR_SIZE: f64 = 512.0;
+
+/// Cumulative counters of one device since it appeared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskStats {
+    pub read_ios: u64,
+    pub read_merges: u64,
+    pub read_sectors: u64,
+    /// Milliseconds spent on reads, summed over all reads
+    pub read_ticks: u64,
+    pub write_ios: u64,
+    pub write_merges: u64,
+    pub write_sectors: u64,
+    pub write_ticks: u64,
+    /// Requests issued to the driver but not completed yet; not cumulative
+    pub in_flight: u64,
+    /// Milliseconds during which at least one request was in flight
+    pub io_ticks: u64,
+    /// Milliseconds of requests in flight, weighted by their number
+    pub time_in_queue: u64,
+}
+
+impl DiskStats {
+    /// Parse the contents of a `stat` file; kernels since 4.18 and 5.5 append
+    /// discard and flush counters, which are ignored
+    pub fn parse(content: &str) -> Option<DiskStats> {
+        let fields: Vec<u64> = content
+            .split_whitespace()
+            .take(11)
+            .map(|field| field.parse().ok())
+            .collect::<Option<_>>()?;
+        let [read_ios, read_merges, read_sectors, read_ticks, write_ios, write_merges, write_sectors, write_ticks, in_flight, io_ticks, time_in_queue] =
+            fields[..]
+        else {
+            return None;
+        };
+
+        Some(DiskStats {
+            read_ios,
+            read_merges,
+            read_sectors,
+            read_ticks,
+            write_ios,
+            write_merges,
+            write_sectors,
+            write_ticks,
+            in_flight,
+            io_ticks,
+            time_in_queue,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<DiskStats> {
+        let content = fs::read_to_string(path).map_err(|source| match source.kind() {
+            io::ErrorKind::NotFound => BlockDevError::NotFound(path.to_path_buf()),
+            _ => BlockDevError::Io { path: path.to_path_buf(), source },
+        })?;
+        DiskStats::parse(&content).ok_or(BlockDevError::Parse { path: path.to_path_buf(), content })
+    }
+}
+
+/// What the device did during one interval, in iostat's units
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct IostatSample {
+    /// Seconds from the start of sampling to the end of the interval
+    pub time: f64,
+    /// Completed reads and writes per second
+    pub r_s: f64,
+    pub w_s: f64,
+    /// Requests merged into others per second
+    pub rrqm_s: f64,
+    pub wrqm_s: f64,
+    /// KiB per second
+    pub rkb_s: f64,
+    pub wkb_s: f64,
+    /// Average time from queueing to completion in milliseconds
+    pub r_await: f64,
+    pub w_await: f64,
+    /// Average number of requests in flight
+    pub aqu_sz: f64,
+    /// Percentage of the interval the device was busy
+    pub util: f64,
+}
+
+impl IostatSample {
+    /// Rates between two readings taken `elapsed` apart
+    pub fn between(before: &DiskStats, after: &DiskStats, elapsed: Duration, time: f64) -> IostatSample {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let delta = |f: fn(&DiskStats) -> u64| f(after).saturating_sub(f(before)) as f64;
+        let average = |total: f64, count: f64| if count == 0.0 { 0.0 } else { total / count };
+
+        let (reads, writes) = (delta(|s| s.read_ios), delta(|s| s.write_ios));
+        IostatSample {
+            time,
+            r_s: reads / secs,
+            w_s: writes / secs,
+            rrqm_s: delta(|s| s.read_merges) / secs,
+            wrqm_s: delta(|s| s.write_merges) / secs,
+            rkb_s: delta(|s| s.read_sectors) * SECTOR_SIZE / 1024.0 / secs,
+            wkb_s: delta(|s| s.write_sectors) * SECTOR_SIZE / 1024.0 / secs,
+            r_await: average(delta(|s| s.read_ticks), reads),
+            w_await: average(delta(|s| s.write_ticks), writes),
+            aqu_sz: delta(|s| s.time_in_queue) / (secs * 1000.0),
+            util: (delta(|s| s.io_ticks) / (secs * 10.0)).min(100.0),
+        }
+    }
+}
+
+/// Samples of one sampling session
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IostatSeries {
+    /// Statistics file that was sampled
+    pub path: PathBuf,
+    /// Sampling interval in milliseconds
+    pub interval_ms: u64,
+    pub samples: Vec<IostatSample>,
+    /// The whole session as one interval
+    pub total: IostatSample,
+}
+
+/// Background thread reading a `stat` file at a fixed interval
+pub struct Sampler {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<Result<IostatSeries>>,
+}
+
+impl Sampler {
+    /// Take the first reading and start sampling every `interval`
+    pub fn start(path: impl Into<PathBuf>, interval: Duration) -> Result<Sampler> {
+        let path = path.into();
+        let interval = interval.max(Duration::from_millis(1));
+        let first = DiskStats::read(&path)?;
+        let start = Instant::now();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopped = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut series = IostatSeries {
+                path,
+                interval_ms: interval.as_millis() as u64,
+                ..Default::default()
+            };
+            let (mut last, mut last_at) = (first, start);
+            let mut next = start + interval;
+
+            loop {
+                // stop() unparks the thread, so the last interval ends when the benchmark does
+                let now = Instant::now();
+                if !stopped.load(Ordering::Acquire) && now < next {
+                    thread::park_timeout(next - now);
+                    continue;
+                }
+
+                let stats = DiskStats::read(&series.path)?;
+                let now = Instant::now();
+                let done = stopped.load(Ordering::Acquire);
+                // Skip the sliver of an interval left when stopping right after a sample
+                if !done || now - last_at >= interval / 10 {
+                    let time = (now - start).as_secs_f64();
+                    series.samples.push(IostatSample::between(&last, &stats, now - last_at, time));
+                }
+                if done {
+                    series.total = IostatSample::between(&first, &stats, now - start, (now - start).as_secs_f64());
+                    return Ok(series);
+                }
+                (last, last_at) = (stats, now);
+                // Keep to the schedule even if a reading was late
+                while next <= now {
+                    next += interval;
+                }
+            }
+        });
+
+        Ok(Sampler { stop, handle })
+    }
+
+    /// Take a final reading and return every sample
+    pub fn stop(self) -> Result<IostatSeries> {
+        self.stop.store(true, Ordering::Release);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap_or_else(|_| {
+            Err(BlockDevError::Io {
+                path: PathBuf::new(),
+                source: io::Error::other("iostat sampler panicked"),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counters of a /proc/diskstats line, which has the major, minor and name in front
+    fn diskstats(line: &str) -> DiskStats {
+        let fields: Vec<&str> = line.split_whitespace().skip(3).collect();
+        DiskStats::parse(&fields.join(" ")).unwrap()
+    }
+
+    // Two seconds apart, with the discard and flush counters of 5.5+ kernels
+    const BEFORE: &str = "   8       0 sda 1000 10 80000 500 2000 40 160000 3000 0 1500 3500 0 0 0 0 100 20";
+    const AFTER: &str = "   8       0 sda 1400 30 112000 1300 2200 50 176000 4000 3 2500 5300 0 0 0 0 120 25";
+
+    #[test]
+    fn counters_are_parsed() {
+        let after = diskstats(AFTER);
+        assert_eq!((after.read_ios, after.read_sectors, after.write_ticks), (1400, 112000, 4000));
+        assert_eq!((after.in_flight, after.io_ticks, after.time_in_queue), (3, 2500, 5300));
+        // Kernels before 4.18 only have the first 11 fields
+        assert_eq!(DiskStats::parse("1 2 3 4 5 6 7 8 9 10 11").map(|s| s.time_in_queue), Some(11));
+        assert_eq!(DiskStats::parse("1 2 3 4 5 6 7 8 9 10"), None);
+        assert_eq!(DiskStats::parse("1 2 3 4 5 6 7 8 9 10 x"), None);
+    }
+
+    #[test]
+    fn rates_match_iostat() {
+        let sample = IostatSample::between(&diskstats(BEFORE), &diskstats(AFTER), Duration::from_secs(2), 2.0);
+        assert_eq!((sample.r_s, sample.w_s), (200.0, 100.0));
+        assert_eq!((sample.rrqm_s, sample.wrqm_s), (10.0, 5.0));
+        assert_eq!((sample.rkb_s, sample.wkb_s), (8000.0, 4000.0));
+        assert_eq!((sample.r_await, sample.w_await), (2.0, 5.0));
+        assert_eq!(sample.aqu_sz, 0.9);
+        assert_eq!(sample.util, 50.0);
+    }
+
+    #[test]
+    fn idle_and_saturated_intervals() {
+        let before = diskstats(BEFORE);
+        let idle = IostatSample::between(&before, &before, Duration::from_secs(1), 1.0);
+        assert_eq!((idle.r_s, idle.r_await, idle.w_await, idle.aqu_sz, idle.util), (0.0, 0.0, 0.0, 0.0, 0.0));
+
+        // io_ticks may run slightly ahead of the wall clock between two readings
+        let busy = DiskStats { io_ticks: before.io_ticks + 1010, ..before };
+        assert_eq!(IostatSample::between(&before, &busy, Duration::from_secs(1), 1.0).util, 100.0);
+    }
+}
@@ -0,0 +1,18 @@
+//! Building blocks for the chapter 9 block device experiments.
+
+pub mod aio;
+pub mod blockdev;
+pub mod cgroup;
+pub mod histogram;
+pub mod iostat;
+pub mod loadgen;
+pub mod loopdev;
+pub mod pagecache;
+pub mod report;
+pub mod results;
+pub mod scenario;
+pub mod stats;
+pub mod trace;
+pub mod tunables;
+pub mod uring;
+pub mod writeback;
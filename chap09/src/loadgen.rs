@@ -0,0 +1,1039 @@
+//! Native load generator used instead of the external fio binary.
+//!
+//! I/O bypasses the page cache with O_DIRECT unless the job asks for
+//! buffered, O_DSYNC or mmap access (`IoMode`).
+
+use crate::aio::AioContext;
+use crate::blockdev::Sysfs;
+use crate::histogram::{Histogram, LatencyStats};
+use crate::trace::{Trace, SECTOR_SIZE};
+use crate::uring::IoUring;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::alloc::{self, Layout};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Alignment used for I/O buffers and offsets (O_DIRECT requirement)
+pub const DIRECT_ALIGN: usize = 4096;
+
+/// Direction of the generated I/O
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RwMode {
+    Read,
+    Write,
+    /// Reads and writes mixed according to `JobSpec::read_pct`
+    Mixed,
+}
+
+/// Offset pattern of the generated I/O
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Sequential,
+    /// Random offsets following `JobSpec::distribution`
+    Random,
+}
+
+/// Distribution of random offsets over the accessed region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Uniform,
+    /// Zipf distribution with the given exponent; the hottest blocks are at the start
+    Zipf(f64),
+    /// `io_pct` percent of the I/O goes to the first `space_pct` percent of the region
+    Hotspot { io_pct: u8, space_pct: u8 },
+}
+
+impl Distribution {
+    /// Parse "uniform", "zipf:<exponent>" or "hotspot:<io_pct>/<space_pct>"
+    pub fn parse(text: &str) -> Option<Distribution> {
+        let (kind, arg) = text.split_once(':').unwrap_or((text, ""));
+        match kind {
+            "uniform" | "random" if arg.is_empty() => Some(Distribution::Uniform),
+            "zipf" => {
+                let theta: f64 = arg.parse().ok()?;
+                (theta > 0.0).then_some(Distribution::Zipf(theta))
+            }
+            "hotspot" => {
+                let (io, space) = arg.split_once('/')?;
+                let (io_pct, space_pct): (u8, u8) = (io.parse().ok()?, space.parse().ok()?);
+                (io_pct <= 100 && space_pct > 0 && space_pct < 100)
+                    .then_some(Distribution::Hotspot { io_pct, space_pct })
+            }
+            _ => None,
+        }
+    }
+
+    /// Name in the format accepted by `parse`
+    pub fn name(&self) -> String {
+        match self {
+            Distribution::Uniform => "uniform".to_string(),
+            Distribution::Zipf(theta) => format!("zipf:{}", theta),
+            Distribution::Hotspot { io_pct, space_pct } => format!("hotspot:{}/{}", io_pct, space_pct),
+        }
+    }
+
+    /// Equivalent fio `--random_distribution` value
+    pub fn fio_name(&self) -> String {
+        match self {
+            Distribution::Uniform => "random".to_string(),
+            Distribution::Zipf(theta) => format!("zipf:{}", theta),
+            Distribution::Hotspot { io_pct, space_pct } => {
+                format!("zoned:{}/{}:{}/{}", io_pct, space_pct, 100 - io_pct, 100 - space_pct)
+            }
+        }
+    }
+}
+
+/// How I/O requests are submitted to the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Engine {
+    /// Blocking pread/pwrite; queue depth is emulated with one thread per slot
+    #[serde(rename = "sync")]
+    Sync,
+    /// Linux native AIO (io_submit/io_getevents)
+    #[serde(rename = "libaio")]
+    Aio,
+    /// io_uring
+    #[serde(rename = "io_uring")]
+    IoUring,
+}
+
+impl Engine {
+    pub const ALL: [Engine; 3] = [Engine::Sync, Engine::Aio, Engine::IoUring];
+
+    /// Parse an engine name; fio's names are accepted too
+    pub fn parse(name: &str) -> Option<Engine> {
+        match name {
+            "sync" | "psync" => Some(Engine::Sync),
+            "aio" | "libaio" => Some(Engine::Aio),
+            "io_uring" | "uring" => Some(Engine::IoUring),
+            _ => None,
+        }
+    }
+
+    /// Short name used in file names and plot labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            Engine::Sync => "sync",
+            Engine::Aio => "libaio",
+            Engine::IoUring => "io_uring",
+        }
+    }
+
+    /// Equivalent fio `--ioengine` value
+    pub fn fio_name(&self) -> &'static str {
+        match self {
+            Engine::Sync => "psync",
+            Engine::Aio => "libaio",
+            Engine::IoUring => "io_uring",
+        }
+    }
+}
+
+/// How the target file is accessed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoMode {
+    /// O_DIRECT, bypassing the page cache
+    #[default]
+    Direct,
+    /// Through the page cache, with read-ahead and writeback
+    Buffered,
+    /// Through the page cache, every write waits for the device (O_DSYNC)
+    Dsync,
+    /// Loads and stores on a shared mapping of the target (sync engine only)
+    Mmap,
+}
+
+impl IoMode {
+    pub const ALL: [IoMode; 4] = [IoMode::Direct, IoMode::Buffered, IoMode::Dsync, IoMode::Mmap];
+
+    pub fn parse(name: &str) -> Option<IoMode> {
+        IoMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// Short name used in file names and plot labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            IoMode::Direct => "direct",
+            IoMode::Buffered => "buffered",
+            IoMode::Dsync => "dsync",
+            IoMode::Mmap => "mmap",
+        }
+    }
+
+    /// Extra open(2) flags
+    fn open_flags(&self) -> i32 {
+        match self {
+            IoMode::Direct => libc::O_DIRECT,
+            IoMode::Dsync => libc::O_DSYNC,
+            IoMode::Buffered | IoMode::Mmap => 0,
+        }
+    }
+}
+
+/// Parse a fio style `--rw` value (read, write, rw, randread, randwrite, randrw)
+pub fn parse_rw(rw_type: &str) -> Option<(RwMode, Pattern)> {
+    match rw_type {
+        "read" => Some((RwMode::Read, Pattern::Sequential)),
+        "write" => Some((RwMode::Write, Pattern::Sequential)),
+        "rw" | "readwrite" => Some((RwMode::Mixed, Pattern::Sequential)),
+        "randread" => Some((RwMode::Read, Pattern::Random)),
+        "randwrite" => Some((RwMode::Write, Pattern::Random)),
+        "randrw" => Some((RwMode::Mixed, Pattern::Random)),
+        _ => None,
+    }
+}
+
+/// Parameters of one benchmark run
+#[derive(Debug, Clone)]
+pub struct JobSpec {
+    pub path: String,
+    pub rw: RwMode,
+    pub pattern: Pattern,
+    pub engine: Engine,
+    pub io_mode: IoMode,
+    pub block_size: usize,
+    pub queue_depth: usize,
+    pub num_jobs: usize,
+    pub runtime: Duration,
+    /// Size of the region to access; defaults to the size of the target
+    pub size: Option<u64>,
+    /// Percentage of reads in `RwMode::Mixed` (fio's rwmixread)
+    pub read_pct: u8,
+    pub distribution: Distribution,
+    /// Call fsync after every N writes of a worker
+    pub fsync_every: Option<u32>,
+    /// Replay this trace instead of generating requests; `rw` and `pattern` are ignored
+    pub trace: Option<Arc<Trace>>,
+    /// Issue trace requests no earlier than their recorded time, else as fast as possible
+    pub trace_timing: bool,
+}
+
+impl JobSpec {
+    /// Create a job with fio's defaults (sync engine, 4 KiB blocks, iodepth=1, 1 job, 60 sec)
+    pub fn new(path: &str, rw: RwMode, pattern: Pattern) -> Self {
+        JobSpec {
+            path: path.to_string(),
+            rw,
+            pattern,
+            engine: Engine::Sync,
+            io_mode: IoMode::Direct,
+            block_size: 4096,
+            queue_depth: 1,
+            num_jobs: 1,
+            runtime: Duration::from_secs(60),
+            size: None,
+            read_pct: 50,
+            distribution: Distribution::Uniform,
+            fsync_every: None,
+            trace: None,
+            trace_timing: true,
+        }
+    }
+
+    /// Whether the job issues any writes
+    fn writes(&self) -> bool {
+        match &self.trace {
+            Some(trace) => trace.has_writes(),
+            None => self.rw != RwMode::Read,
+        }
+    }
+}
+
+/// Summary of one benchmark run
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    /// Completion latency percentiles
+    pub latency: LatencyStats,
+    pub iops: f64,
+    /// Bandwidth in bytes per second
+    pub bw_bytes: f64,
+    pub total_ios: u64,
+    pub elapsed: Duration,
+    /// Completion latency of every I/O
+    pub histogram: Histogram,
+    pub read_histogram: Histogram,
+    pub write_histogram: Histogram,
+    /// Latency of the fsync calls
+    pub fsync_histogram: Histogram,
+}
+
+impl JobResult {
+    /// Complete record of the run, including its parameters and the latency histogram
+    pub fn to_json(&self, spec: &JobSpec) -> Value {
+        let direction = |hist: &Histogram| {
+            json!({
+                "total_ios": hist.count(),
+                "lat_usec": hist.summary().to_json(),
+                "lat_histogram_ns": hist.to_json(),
+            })
+        };
+
+        json!({
+            "path": spec.path,
+            "rw": match (&spec.trace, spec.rw) {
+                (Some(_), _) => "replay",
+                (None, RwMode::Read) => "read",
+                (None, RwMode::Write) => "write",
+                (None, RwMode::Mixed) => "rw",
+            },
+            "pattern": match spec.pattern { Pattern::Sequential => "seq", Pattern::Random => "rand" },
+            "rwmixread": spec.read_pct,
+            "distribution": spec.distribution.name(),
+            "fsync_every": spec.fsync_every,
+            "ioengine": spec.engine.name(),
+            "io_mode": spec.io_mode.name(),
+            "bs": spec.block_size,
+            "iodepth": spec.queue_depth,
+            "numjobs": spec.num_jobs,
+            "runtime_ms": self.elapsed.as_millis() as u64,
+            "total_ios": self.total_ios,
+            "iops": self.iops,
+            "bw_bytes": self.bw_bytes,
+            "lat_usec": self.latency.to_json(),
+            "lat_histogram_ns": self.histogram.to_json(),
+            "read": direction(&self.read_histogram),
+            "write": direction(&self.write_histogram),
+            "fsync_latency": direction(&self.fsync_histogram),
+        })
+    }
+}
+
+/// Heap buffer aligned for O_DIRECT
+struct AlignedBuf {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuf {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, DIRECT_ALIGN).expect("invalid buffer layout");
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuf { ptr, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// Small xorshift generator, good enough to pick random offsets
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// Uniform value in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Zipf distributed ranks in 1..=n by rejection-inversion sampling
+/// (W. Hörmann and G. Derflinger, 1996), which needs no table of size n
+struct ZipfSampler {
+    n: f64,
+    exponent: f64,
+    h_integral_x1: f64,
+    h_integral_n: f64,
+    s: f64,
+}
+
+/// log(1 + x) / x, continuous at 0
+fn log1p_over_x(x: f64) -> f64 {
+    if x.abs() > 1e-8 { x.ln_1p() / x } else { 1.0 - x / 2.0 }
+}
+
+/// (exp(x) - 1) / x, continuous at 0
+fn expm1_over_x(x: f64) -> f64 {
+    if x.abs() > 1e-8 { x.exp_m1() / x } else { 1.0 + x / 2.0 }
+}
+
+impl ZipfSampler {
+    fn new(n: u64, exponent: f64) -> Self {
+        let mut zipf = ZipfSampler {
+            n: n as f64,
+            exponent,
+            h_integral_x1: 0.0,
+            h_integral_n: 0.0,
+            s: 0.0,
+        };
+        zipf.h_integral_x1 = zipf.h_integral(1.5) - 1.0;
+        zipf.h_integral_n = zipf.h_integral(zipf.n + 0.5);
+        zipf.s = 2.0 - zipf.h_integral_inverse(zipf.h_integral(2.5) - zipf.h(2.0));
+        zipf
+    }
+
+    fn h(&self, x: f64) -> f64 {
+        (-self.exponent * x.ln()).exp()
+    }
+
+    fn h_integral(&self, x: f64) -> f64 {
+        let log_x = x.ln();
+        expm1_over_x((1.0 - self.exponent) * log_x) * log_x
+    }
+
+    fn h_integral_inverse(&self, x: f64) -> f64 {
+        let t = (x * (1.0 - self.exponent)).max(-1.0);
+        (log1p_over_x(t) * x).exp()
+    }
+
+    fn sample(&self, rng: &mut XorShift) -> u64 {
+        loop {
+            let u = self.h_integral_n + rng.next_f64() * (self.h_integral_x1 - self.h_integral_n);
+            let x = self.h_integral_inverse(u);
+            let k = (x + 0.5).clamp(1.0, self.n).floor();
+            if k - x <= self.s || u >= self.h_integral(k + 0.5) - self.h(k) {
+                return k as u64;
+            }
+        }
+    }
+}
+
+/// Open the target for the job's I/O mode and return it with the size of the accessed region
+fn open_target(spec: &JobSpec) -> Result<(File, u64), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(spec.writes())
+        .custom_flags(spec.io_mode.open_flags())
+        .open(&spec.path)
+        .map_err(|e| format!("Failed to open {} for {} I/O: {}", spec.path, spec.io_mode.name(), e))?;
+
+    // Block devices report a zero length in metadata, so seek to the end instead
+    let target_size = file.seek(SeekFrom::End(0))?;
+    let size = match spec.size {
+        Some(size) if size > target_size && file.metadata()?.is_file() => {
+            if spec.writes() {
+                file.set_len(size)?;
+                size
+            } else {
+                target_size
+            }
+        }
+        Some(size) => size.min(target_size),
+        None => target_size,
+    };
+
+    Ok((file, size))
+}
+
+/// Shared mapping of the accessed region, for `IoMode::Mmap`
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// Workers may access the same bytes at once, but only through `words`
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: u64, write: bool) -> io::Result<Mapping> {
+        let prot = if write { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len as usize, prot, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr: ptr as *mut u8, len: len as usize })
+    }
+
+    /// The `len` bytes at `offset` as 64-bit words, which several workers
+    /// may load and store at once with relaxed atomics
+    fn words(&self, offset: u64, len: usize) -> &[AtomicU64] {
+        let offset = offset as usize;
+        assert!(offset + len <= self.len);
+        // Requests are sector aligned
+        assert!(offset.is_multiple_of(size_of::<u64>()) && len.is_multiple_of(size_of::<u64>()));
+        // The mapping is page aligned, and AtomicU64 has the size and alignment of u64
+        unsafe { std::slice::from_raw_parts(self.ptr.add(offset) as *const AtomicU64, len / size_of::<u64>()) }
+    }
+
+    /// Copy `buf.len()` bytes at `offset` into `buf`, faulting the pages in
+    fn read(&self, offset: u64, buf: &mut [u8]) {
+        for (word, bytes) in self.words(offset, buf.len()).iter().zip(buf.chunks_exact_mut(size_of::<u64>())) {
+            bytes.copy_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes());
+        }
+        // The copy has no other observer; keep it from being optimized away
+        std::hint::black_box(buf);
+    }
+
+    /// Copy `buf` to `offset`, dirtying the pages
+    fn write(&self, offset: u64, buf: &[u8]) {
+        for (word, bytes) in self.words(offset, buf.len()).iter().zip(buf.chunks_exact(size_of::<u64>())) {
+            word.store(u64::from_ne_bytes(bytes.try_into().expect("8-byte chunk")), Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// Next thing a worker has to do
+enum Request {
+    Io {
+        write: bool,
+        offset: u64,
+        len: usize,
+        /// Not to be issued before this point in time
+        due: Option<Instant>,
+    },
+    /// Wait for the in-flight I/O, then fsync
+    Fsync,
+}
+
+/// Produces the requests of one worker, generated or replayed from a trace
+struct Generator<'a> {
+    spec: &'a JobSpec,
+    /// Next block of the job for sequential I/O
+    cursor: &'a AtomicU64,
+    /// Next trace entry, shared by every worker
+    trace_cursor: &'a AtomicUsize,
+    rng: XorShift,
+    zipf: Option<ZipfSampler>,
+    region: u64,
+    nblocks: u64,
+    /// Logical block size of the target, which replayed requests are aligned to
+    align: u64,
+    start: Instant,
+    writes: u32,
+}
+
+/// Fit a replayed request into a region of `region` bytes: offset and length are aligned to `align`,
+/// requests longer than the region are cut to it and requests beyond its end wrap around
+fn fit_request(offset: u64, len: usize, region: u64, align: u64) -> (u64, usize) {
+    let region = region / align * align;
+    let len = (len as u64).next_multiple_of(align).min(region);
+    let limit = region - len;
+    let offset = if offset <= limit { offset } else { offset % (limit + 1) };
+    (offset / align * align, len as usize)
+}
+
+impl Generator<'_> {
+    fn random_block(&mut self) -> u64 {
+        match self.spec.distribution {
+            Distribution::Uniform => self.rng.next() % self.nblocks,
+            Distribution::Zipf(_) => self.zipf.as_ref().map_or(0, |zipf| zipf.sample(&mut self.rng) - 1),
+            Distribution::Hotspot { io_pct, space_pct } => {
+                let hot = (self.nblocks * space_pct as u64 / 100).max(1);
+                if self.rng.next() % 100 < io_pct as u64 || hot == self.nblocks {
+                    self.rng.next() % hot
+                } else {
+                    hot + self.rng.next() % (self.nblocks - hot)
+                }
+            }
+        }
+    }
+
+    /// Next request, or `None` once a replayed trace is exhausted
+    fn next(&mut self) -> Option<Request> {
+        if let Some(every) = self.spec.fsync_every {
+            if self.writes >= every {
+                self.writes = 0;
+                return Some(Request::Fsync);
+            }
+        }
+
+        let request = match &self.spec.trace {
+            Some(trace) => {
+                let entry = trace.entries.get(self.trace_cursor.fetch_add(1, Ordering::Relaxed))?;
+                let (offset, len) = fit_request(entry.offset, entry.len, self.region, self.align);
+                Request::Io {
+                    write: entry.write,
+                    offset,
+                    len,
+                    due: self.spec.trace_timing.then_some(self.start + entry.at),
+                }
+            }
+            None => {
+                let block = match self.spec.pattern {
+                    Pattern::Sequential => self.cursor.fetch_add(1, Ordering::Relaxed) % self.nblocks,
+                    Pattern::Random => self.random_block(),
+                };
+                let write = match self.spec.rw {
+                    RwMode::Read => false,
+                    RwMode::Write => true,
+                    RwMode::Mixed => self.rng.next() % 100 >= self.spec.read_pct as u64,
+                };
+                Request::Io {
+                    write,
+                    offset: block * self.spec.block_size as u64,
+                    len: self.spec.block_size,
+                    due: None,
+                }
+            }
+        };
+
+        if let Request::Io { write: true, .. } = request {
+            self.writes += 1;
+        }
+        Some(request)
+    }
+}
+
+/// Latencies collected by one worker
+#[derive(Default)]
+struct WorkerStats {
+    read: Histogram,
+    write: Histogram,
+    fsync: Histogram,
+    bytes: u64,
+}
+
+impl WorkerStats {
+    fn record(&mut self, write: bool, len: usize, latency: Duration) {
+        let hist = if write { &mut self.write } else { &mut self.read };
+        hist.record(latency.as_nanos() as u64);
+        self.bytes += len as u64;
+    }
+}
+
+/// Sleep until `due`, but never past the deadline; false if the deadline comes first
+fn sleep_until(due: Instant, deadline: Instant) -> bool {
+    let now = Instant::now();
+    if due >= deadline {
+        thread::sleep(deadline.saturating_duration_since(now));
+        return false;
+    }
+    thread::sleep(due.saturating_duration_since(now));
+    true
+}
+
+fn timed_fsync(file: &File, stats: &mut WorkerStats) -> io::Result<()> {
+    let start = Instant::now();
+    file.sync_all()?;
+    stats.fsync.record(start.elapsed().as_nanos() as u64);
+    Ok(())
+}
+
+/// Bytes moved by a request of `len` bytes that returned `res`; errors and
+/// 0-byte transfers, i.e. the end of the target, fail
+fn transferred(write: bool, offset: u64, len: usize, res: i64) -> io::Result<usize> {
+    if res < 0 {
+        return Err(io::Error::from_raw_os_error(-res as i32));
+    }
+    if res == 0 && len > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} of {} bytes at offset {} transferred nothing", if write { "Write" } else { "Read" }, len, offset),
+        ));
+    }
+    Ok((res as usize).min(len))
+}
+
+/// Issue blocking I/O one request at a time until the deadline, through `mapping` if given
+fn sync_worker(
+    file: &File,
+    mapping: Option<&Mapping>,
+    mut requests: Generator,
+    buf_size: usize,
+    deadline: Instant,
+) -> io::Result<WorkerStats> {
+    let mut buf = AlignedBuf::new(buf_size);
+    let mut stats = WorkerStats::default();
+
+    while Instant::now() < deadline {
+        let Some(request) = requests.next() else {
+            break;
+        };
+        let Request::Io { write, offset, len, due } = request else {
+            timed_fsync(file, &mut stats)?;
+            continue;
+        };
+        if let Some(due) = due {
+            if !sleep_until(due, deadline) {
+                break;
+            }
+        }
+
+        let io_start = Instant::now();
+        let done = match (mapping, write) {
+            (Some(mapping), true) => {
+                mapping.write(offset, &buf.as_slice()[..len]);
+                len
+            }
+            (Some(mapping), false) => {
+                mapping.read(offset, &mut buf.as_mut_slice()[..len]);
+                len
+            }
+            (None, true) => file.write_at(&buf.as_slice()[..len], offset)?,
+            (None, false) => file.read_at(&mut buf.as_mut_slice()[..len], offset)?,
+        };
+        stats.record(write, transferred(write, offset, len, done as i64)?, io_start.elapsed());
+    }
+
+    Ok(stats)
+}
+
+/// Common interface of the asynchronous submission engines
+trait AsyncQueue {
+    fn prepare(&mut self, slot: usize, fd: i32, write: bool, buf: *mut u8, len: usize, offset: u64);
+    fn submit(&mut self) -> io::Result<()>;
+    fn wait(&mut self, min: usize, out: &mut Vec<(usize, i64)>) -> io::Result<()>;
+    fn in_flight(&self) -> usize;
+}
+
+impl AsyncQueue for AioContext {
+    fn prepare(&mut self, slot: usize, fd: i32, write: bool, buf: *mut u8, len: usize, offset: u64) {
+        AioContext::prepare(self, slot, fd, write, buf, len, offset)
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        AioContext::submit(self)
+    }
+
+    fn wait(&mut self, min: usize, out: &mut Vec<(usize, i64)>) -> io::Result<()> {
+        AioContext::wait(self, min, out)
+    }
+
+    fn in_flight(&self) -> usize {
+        AioContext::in_flight(self)
+    }
+}
+
+impl AsyncQueue for IoUring {
+    fn prepare(&mut self, slot: usize, fd: i32, write: bool, buf: *mut u8, len: usize, offset: u64) {
+        IoUring::prepare(self, slot, fd, write, buf, len, offset)
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        IoUring::submit(self)
+    }
+
+    fn wait(&mut self, min: usize, out: &mut Vec<(usize, i64)>) -> io::Result<()> {
+        IoUring::wait(self, min, out)
+    }
+
+    fn in_flight(&self) -> usize {
+        IoUring::in_flight(self)
+    }
+}
+
+/// A queue with the buffers of its requests; dropping it waits for every
+/// request in flight, so the kernel never transfers into freed buffers
+struct InFlight<Q: AsyncQueue> {
+    queue: Q,
+    bufs: Vec<AlignedBuf>,
+}
+
+impl<Q: AsyncQueue> Drop for InFlight<Q> {
+    fn drop(&mut self) {
+        let mut completions = Vec::new();
+        while self.queue.in_flight() > 0 {
+            completions.clear();
+            if let Err(e) = self.queue.wait(1, &mut completions) {
+                // The kernel may still use the buffers, so leak them rather than free them
+                eprintln!("Failed to wait for {} requests in flight: {}", self.queue.in_flight(), e);
+                std::mem::forget(std::mem::take(&mut self.bufs));
+                return;
+            }
+        }
+    }
+}
+
+/// Keep `queue_depth` requests in flight on `queue` until the deadline
+fn async_worker<Q: AsyncQueue>(
+    queue: Q,
+    spec: &JobSpec,
+    file: &File,
+    mut requests: Generator,
+    buf_size: usize,
+    deadline: Instant,
+) -> io::Result<WorkerStats> {
+    let fd = file.as_raw_fd();
+    let depth = spec.queue_depth;
+    let mut queue = InFlight { queue, bufs: (0..depth).map(|_| AlignedBuf::new(buf_size)).collect() };
+    // (submission time, write, offset, length) of every slot
+    let mut in_flight = vec![(Instant::now(), false, 0u64, 0usize); depth];
+    let mut free: Vec<usize> = (0..depth).collect();
+    let mut completions = Vec::with_capacity(depth);
+    let mut stats = WorkerStats::default();
+    // Request that could not be issued yet: not due, or an fsync waiting for the queue to drain
+    let mut pending: Option<Request> = None;
+    let mut exhausted = false;
+
+    loop {
+        // Refill every free slot while there is time left
+        let now = Instant::now();
+        let mut prepared = 0;
+        while now < deadline && !exhausted && !free.is_empty() {
+            let Some(request) = pending.take().or_else(|| requests.next()) else {
+                exhausted = true;
+                break;
+            };
+            match request {
+                Request::Fsync if free.len() == depth => timed_fsync(file, &mut stats)?,
+                Request::Io { due: Some(due), .. } if due > now => {
+                    pending = Some(request);
+                    break;
+                }
+                Request::Io { write, offset, len, .. } => {
+                    let slot = free.pop().unwrap();
+                    let buf = queue.bufs[slot].as_mut_ptr();
+                    queue.queue.prepare(slot, fd, write, buf, len, offset);
+                    in_flight[slot] = (now, write, offset, len);
+                    prepared += 1;
+                }
+                Request::Fsync => {
+                    pending = Some(request);
+                    break;
+                }
+            }
+        }
+        if prepared > 0 {
+            queue.queue.submit()?;
+        }
+
+        if free.len() == depth {
+            if exhausted || Instant::now() >= deadline {
+                break;
+            }
+            // Nothing in flight: wait for the next replayed request to become due
+            if let Some(Request::Io { due: Some(due), .. }) = pending {
+                if !sleep_until(due, deadline) {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        completions.clear();
+        queue.queue.wait(1, &mut completions)?;
+        let now = Instant::now();
+        for &(slot, res) in &completions {
+            let (submitted_at, write, offset, len) = in_flight[slot];
+            stats.record(write, transferred(write, offset, len, res)?, now.duration_since(submitted_at));
+            free.push(slot);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Run the job described by `spec` and return its summary
+pub fn run(spec: &JobSpec) -> Result<JobResult, Box<dyn Error>> {
+    if spec.block_size == 0 || !spec.block_size.is_multiple_of(DIRECT_ALIGN) {
+        return Err(format!("Block size must be a multiple of {}", DIRECT_ALIGN).into());
+    }
+    if spec.queue_depth == 0 || spec.num_jobs == 0 {
+        return Err("Queue depth and job count must be >= 1".into());
+    }
+    if spec.read_pct > 100 {
+        return Err("Read percentage must be between 0 and 100".into());
+    }
+    if spec.fsync_every == Some(0) {
+        return Err("fsync interval must be >= 1".into());
+    }
+    if spec.io_mode == IoMode::Mmap && spec.engine != Engine::Sync {
+        return Err("mmap access only works with the sync engine".into());
+    }
+
+    let (file, size) = open_target(spec)?;
+    let nblocks = size / spec.block_size as u64;
+    if nblocks == 0 {
+        return Err(format!("{} is smaller than one block", spec.path).into());
+    }
+    let mapping = match spec.io_mode {
+        IoMode::Mmap => Some(
+            Mapping::new(&file, size, spec.writes()).map_err(|e| format!("Failed to mmap {}: {}", spec.path, e))?,
+        ),
+        _ => None,
+    };
+    let mapping = mapping.as_ref();
+
+    // Set up the asynchronous queues first so an unsupported engine fails early
+    let mut aio_queues = Vec::new();
+    let mut uring_queues = Vec::new();
+    for _ in 0..spec.num_jobs {
+        match spec.engine {
+            Engine::Sync => {}
+            Engine::Aio => aio_queues.push(
+                AioContext::new(spec.queue_depth).map_err(|e| format!("io_setup failed: {}", e))?,
+            ),
+            Engine::IoUring => uring_queues.push(
+                IoUring::new(spec.queue_depth).map_err(|e| format!("io_uring_setup failed: {}", e))?,
+            ),
+        }
+    }
+
+    // Trace requests may be larger than the block size
+    let max_len = spec.trace.as_ref().map_or(0, |trace| trace.max_len());
+    let buf_size = spec.block_size.max(max_len.div_ceil(DIRECT_ALIGN) * DIRECT_ALIGN);
+    // O_DIRECT rejects requests that are not aligned to the logical block size, which may be 4K
+    // although blktrace counts 512 byte sectors
+    let align = match &spec.trace {
+        Some(_) => Sysfs::new()
+            .resolve(&spec.path)
+            .and_then(|device| device.logical_block_size())
+            .map_or(SECTOR_SIZE, u64::from),
+        None => SECTOR_SIZE,
+    };
+
+    // Each job walks the region independently like a fio job; a trace is replayed once by all of them
+    let cursors: Vec<AtomicU64> = (0..spec.num_jobs).map(|_| AtomicU64::new(0)).collect();
+    let trace_cursor = AtomicUsize::new(0);
+    let start = Instant::now();
+    let deadline = start + spec.runtime;
+    let file = &file;
+
+    let mut worker_id = 0u64;
+    let mut new_requests = |cursor| {
+        worker_id += 1;
+        Generator {
+            spec,
+            cursor,
+            trace_cursor: &trace_cursor,
+            rng: XorShift::new(worker_id.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ start.elapsed().as_nanos() as u64),
+            zipf: match spec.distribution {
+                Distribution::Zipf(exponent) => Some(ZipfSampler::new(nblocks, exponent)),
+                _ => None,
+            },
+            region: size,
+            nblocks,
+            align,
+            start,
+            writes: 0,
+        }
+    };
+
+    let stats = thread::scope(|s| {
+        let mut handles = Vec::new();
+
+        match spec.engine {
+            Engine::Sync => {
+                for cursor in &cursors {
+                    // Queue depth is emulated by several submitters sharing the job's cursor
+                    for _ in 0..spec.queue_depth {
+                        let requests = new_requests(cursor);
+                        handles.push(s.spawn(move || sync_worker(file, mapping, requests, buf_size, deadline)));
+                    }
+                }
+            }
+            Engine::Aio => {
+                for (queue, cursor) in aio_queues.into_iter().zip(&cursors) {
+                    let requests = new_requests(cursor);
+                    handles.push(s.spawn(move || async_worker(queue, spec, file, requests, buf_size, deadline)));
+                }
+            }
+            Engine::IoUring => {
+                for (queue, cursor) in uring_queues.into_iter().zip(&cursors) {
+                    let requests = new_requests(cursor);
+                    handles.push(s.spawn(move || async_worker(queue, spec, file, requests, buf_size, deadline)));
+                }
+            }
+        }
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(io::Error::other("I/O worker panicked"))))
+            .collect::<io::Result<Vec<WorkerStats>>>()
+    })?;
+
+    let elapsed = start.elapsed();
+    let mut read_histogram = Histogram::new();
+    let mut write_histogram = Histogram::new();
+    let mut fsync_histogram = Histogram::new();
+    let mut bytes = 0;
+    for worker in &stats {
+        read_histogram.merge(&worker.read);
+        write_histogram.merge(&worker.write);
+        fsync_histogram.merge(&worker.fsync);
+        bytes += worker.bytes;
+    }
+    let mut histogram = read_histogram.clone();
+    histogram.merge(&write_histogram);
+
+    let total_ios = histogram.count();
+    let iops = total_ios as f64 / elapsed.as_secs_f64();
+
+    Ok(JobResult {
+        latency: histogram.summary(),
+        iops,
+        bw_bytes: bytes as f64 / elapsed.as_secs_f64(),
+        total_ios,
+        elapsed,
+        histogram,
+        read_histogram,
+        write_histogram,
+        fsync_histogram,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn replayed_requests_fit_the_region() {
+        // In range, only aligned
+        assert_eq!(fit_request(8192, 4096, 1 << 20, 512), (8192, 4096));
+        assert_eq!(fit_request(8704, 512, 1 << 20, 4096), (8192, 4096));
+        // Beyond the end, wrapped and aligned to 4K
+        let (offset, len) = fit_request(3 << 20 | 512, 8192, 1 << 20, 4096);
+        assert_eq!(len, 8192);
+        assert!(offset.is_multiple_of(4096) && offset + 8192 <= 1 << 20, "offset {}", offset);
+        // Longer than the region, cut to it
+        assert_eq!(fit_request(12288, 3 << 20, 1 << 20, 4096), (0, 1 << 20));
+        assert_eq!(fit_request(0, 1 << 20, 1 << 20, 4096), (0, 1 << 20));
+    }
+
+    #[test]
+    fn mmap_workers_write_and_read_the_shared_region() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("target");
+        fs::write(&path, vec![0u8; 64 * 1024]).unwrap();
+
+        let mut spec = JobSpec::new(path.to_str().unwrap(), RwMode::Mixed, Pattern::Random);
+        spec.io_mode = IoMode::Mmap;
+        spec.num_jobs = 2;
+        spec.queue_depth = 2;
+        spec.runtime = Duration::from_millis(50);
+        let result = run(&spec).unwrap();
+
+        assert!(result.read_histogram.count() > 0 && result.write_histogram.count() > 0);
+        let bytes = result.bw_bytes * result.elapsed.as_secs_f64();
+        assert!((bytes / (result.total_ios * 4096) as f64 - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,336 @@
+//! Loop device sandboxes, so scheduler experiments need no spare disk.
+//!
+//! A sandbox is a backing file (on tmpfs to keep real disks out of the
+//! measurement, or on a disk to include it) attached to a free loop device
+//! with LOOP_CTL_GET_FREE and LOOP_CONFIGURE (Linux 5.8+), optionally
+//! formatted and mounted. Loop devices are blk-mq devices with their own
+//! scheduler and read-ahead, unlike regular files.
+//!
+//! Nothing outlives the process, even when it is killed: the backing file is
+//! unlinked right after attaching, the device is configured to detach when
+//! its last user closes it (LO_FLAGS_AUTOCLEAR), and mounts are made in a
+//! private mount namespace (`private_mounts`) that dies with the process.
+//! Only the empty mount point stays behind; the next sandbox created in the
+//! same directory removes it.
+
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Loop ioctls from <linux/loop.h>
+const LOOP_CLR_FD: libc::c_ulong = 0x4C01;
+const LOOP_CONFIGURE: libc::c_ulong = 0x4C0A;
+const LOOP_CTL_GET_FREE: libc::c_ulong = 0x4C82;
+
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_FLAGS_DIRECT_IO: u32 = 16;
+
+const LO_NAME_SIZE: usize = 64;
+
+/// Another process may claim the free device between LOOP_CTL_GET_FREE and LOOP_CONFIGURE
+const ATTACH_ATTEMPTS: u32 = 8;
+
+/// `struct loop_info64`
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; LO_NAME_SIZE],
+    lo_crypt_name: [u8; LO_NAME_SIZE],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+/// `struct loop_config`
+#[repr(C)]
+struct LoopConfig {
+    fd: u32,
+    block_size: u32,
+    info: LoopInfo64,
+    reserved: [u64; 8],
+}
+
+fn ioctl(file: &File, request: libc::c_ulong, arg: libc::c_ulong) -> io::Result<libc::c_int> {
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// How the loop device is set up
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoopOptions {
+    /// Logical block size; 512 when unset
+    pub block_size: Option<u32>,
+    /// Access the backing file with O_DIRECT instead of through its page cache
+    pub direct_io: bool,
+}
+
+/// An attached loop device, detached when dropped
+pub struct LoopDevice {
+    pub path: PathBuf,
+    pub number: u32,
+    /// Kept open so the device stays attached until we are done (autoclear)
+    file: Option<File>,
+}
+
+impl LoopDevice {
+    /// Attach `backing` to a free loop device
+    pub fn attach(backing: &Path, options: &LoopOptions) -> io::Result<LoopDevice> {
+        let control = File::open("/dev/loop-control")?;
+        let backing_file = OpenOptions::new().read(true).write(true).open(backing)?;
+
+        let mut flags = LO_FLAGS_AUTOCLEAR;
+        if options.direct_io {
+            flags |= LO_FLAGS_DIRECT_IO;
+        }
+        let mut file_name = [0u8; LO_NAME_SIZE];
+        let name = backing.as_os_str().as_bytes();
+        let len = name.len().min(LO_NAME_SIZE - 1);
+        file_name[..len].copy_from_slice(&name[..len]);
+
+        let mut last_error = None;
+        for _ in 0..ATTACH_ATTEMPTS {
+            let number = ioctl(&control, LOOP_CTL_GET_FREE, 0)? as u32;
+            let path = PathBuf::from(format!("/dev/loop{}", number));
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+            let config = LoopConfig {
+                fd: backing_file.as_raw_fd() as u32,
+                block_size: options.block_size.unwrap_or(0),
+                info: LoopInfo64 {
+                    lo_flags: flags,
+                    lo_file_name: file_name,
+                    ..unsafe { std::mem::zeroed() }
+                },
+                reserved: [0; 8],
+            };
+            match ioctl(&file, LOOP_CONFIGURE, &config as *const LoopConfig as libc::c_ulong) {
+                Ok(_) => return Ok(LoopDevice { path, number, file: Some(file) }),
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::from_raw_os_error(libc::EBUSY)))
+    }
+
+    /// Kernel name, e.g. "loop0"
+    pub fn name(&self) -> String {
+        format!("loop{}", self.number)
+    }
+
+    /// Whether the kernel ended up using direct I/O on the backing file
+    pub fn direct_io(&self) -> bool {
+        fs::read_to_string(format!("/sys/block/{}/loop/dio", self.name())).is_ok_and(|dio| dio.trim() == "1")
+    }
+
+    /// Detach now and report failures instead of ignoring them on drop
+    pub fn detach(mut self) -> io::Result<()> {
+        self.detach_inner()
+    }
+
+    fn detach_inner(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            // With autoclear the kernel finishes the detach once every user has closed the device
+            Some(file) => ioctl(&file, LOOP_CLR_FD, 0).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        let _ = self.detach_inner();
+    }
+}
+
+/// Move the process into a private mount namespace, so sandbox mounts
+/// disappear with it. Call this before spawning threads.
+pub fn private_mounts() -> io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Keep our mounts from propagating back into the parent namespace
+    let root = CString::new("/").unwrap();
+    let ret = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn default_size_mb() -> u64 {
+    1024
+}
+
+fn default_direct_io() -> bool {
+    true
+}
+
+/// Sandbox description in a scenario file
+#[derive(Debug, Clone, Deserialize)]
+pub struct SandboxSpec {
+    /// Size of the backing file
+    #[serde(default = "default_size_mb")]
+    pub size_mb: u64,
+    /// Directory of the backing file; the system temporary directory when unset
+    pub dir: Option<String>,
+    /// Filesystem to format the device with (anything with a mkfs.<fs>); raw device when unset
+    pub fs: Option<String>,
+    /// Logical block size of the loop device
+    pub block_size: Option<u32>,
+    /// Bypass the page cache of the backing file (not possible on tmpfs)
+    #[serde(default = "default_direct_io")]
+    pub direct_io: bool,
+}
+
+/// Remove the empty mount points of sandboxes whose process is gone
+fn remove_stale_mount_dirs(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("chap09-sandbox-"))
+            .and_then(|rest| rest.strip_suffix(".mnt"))
+        else {
+            continue;
+        };
+        if !Path::new("/proc").join(pid).exists() {
+            // Fails harmlessly on anything that is not an empty directory
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+}
+
+/// A loop device over a throwaway backing file, formatted and mounted on request
+pub struct Sandbox {
+    pub device: LoopDevice,
+    /// Where the filesystem is mounted, if formatted
+    mount_dir: Option<PathBuf>,
+}
+
+impl Sandbox {
+    /// Create the backing file, attach it and format and mount it if requested
+    pub fn create(spec: &SandboxSpec) -> Result<Sandbox, Box<dyn Error>> {
+        if spec.size_mb == 0 {
+            return Err("Sandbox size must be at least 1 MiB".into());
+        }
+        let dir = spec.dir.as_ref().map_or_else(std::env::temp_dir, PathBuf::from);
+        remove_stale_mount_dirs(&dir);
+        let backing = dir.join(format!("chap09-sandbox-{}.img", std::process::id()));
+
+        // Written out rather than sparse, so reads reach the backing store
+        let result = (|| -> io::Result<LoopDevice> {
+            let mut file = File::create(&backing)?;
+            let chunk = vec![0xa5u8; 1 << 20];
+            for _ in 0..spec.size_mb {
+                file.write_all(&chunk)?;
+            }
+            file.sync_all()?;
+            let options = LoopOptions { block_size: spec.block_size, direct_io: spec.direct_io };
+            LoopDevice::attach(&backing, &options)
+        })();
+        // The loop device holds the file open; unlinking it now frees it whenever the device goes away
+        let _ = fs::remove_file(&backing);
+        let device = result.map_err(|e| format!("Failed to set up a loop device over {}: {}", backing.display(), e))?;
+
+        let mut sandbox = Sandbox { device, mount_dir: None };
+        if let Some(fs) = &spec.fs {
+            sandbox.format(fs)?;
+            let mount_dir = dir.join(format!("chap09-sandbox-{}.mnt", std::process::id()));
+            sandbox.mount(fs, &mount_dir)?;
+        }
+        Ok(sandbox)
+    }
+
+    fn format(&self, fs: &str) -> Result<(), Box<dyn Error>> {
+        let mkfs = format!("mkfs.{}", fs);
+        let mut command = Command::new(&mkfs);
+        // Without a force flag mkfs refuses or asks when it finds an old signature
+        match fs {
+            "ext2" | "ext3" | "ext4" => command.args(["-q", "-F"]),
+            "xfs" | "btrfs" => command.args(["-q", "-f"]),
+            _ => &mut command,
+        };
+        let output = command
+            .arg(&self.device.path)
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", mkfs, e))?;
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", mkfs, String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+        Ok(())
+    }
+
+    fn mount(&mut self, fs: &str, mount_dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mount_dir)?;
+        let source = CString::new(self.device.path.as_os_str().as_bytes())?;
+        let target = CString::new(mount_dir.as_os_str().as_bytes())?;
+        let fstype = CString::new(fs)?;
+        let ret = unsafe { libc::mount(source.as_ptr(), target.as_ptr(), fstype.as_ptr(), 0, std::ptr::null()) };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            let _ = fs::remove_dir(mount_dir);
+            return Err(format!("Failed to mount {} on {}: {}", self.device.path.display(), mount_dir.display(), e).into());
+        }
+        self.mount_dir = Some(mount_dir.to_path_buf());
+        Ok(())
+    }
+
+    /// What to benchmark: the mounted directory, or the raw device
+    pub fn target(&self) -> &Path {
+        self.mount_dir.as_deref().unwrap_or(&self.device.path)
+    }
+
+    /// Unmount and detach now, reporting failures
+    pub fn teardown(mut self) -> Result<(), Box<dyn Error>> {
+        self.unmount()?;
+        self.device.detach_inner()?;
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(dir) = self.mount_dir.take() else {
+            return Ok(());
+        };
+        let target = CString::new(dir.as_os_str().as_bytes())?;
+        if unsafe { libc::umount(target.as_ptr()) } < 0 {
+            return Err(format!("Failed to unmount {}: {}", dir.display(), io::Error::last_os_error()).into());
+        }
+        fs::remove_dir(&dir)?;
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if let Err(e) = self.unmount() {
+            eprintln!("{}", e);
+        }
+    }
+}
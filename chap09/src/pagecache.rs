@@ -0,0 +1,141 @@
+//! Page cache control and accounting for file-level benchmarks.
+//!
+//! Buffered, O_DSYNC and mmap runs go through the page cache, so their
+//! results depend on what is cached when they start. The cache can be
+//! dropped or pre-warmed before a run, and the pages of the target resident
+//! before and after it are counted with mincore(2) on a mapping of the file.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Region counted per mincore(2) call, to keep the residency vector small on large targets
+const MINCORE_CHUNK: u64 = 1 << 30;
+
+/// What to do with the page cache before each measured run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CachePolicy {
+    /// Leave the cache as the previous run left it
+    #[default]
+    Keep,
+    /// Write back dirty pages and drop the clean page cache of the whole system
+    Drop,
+    /// Read the whole target through the page cache
+    Warm,
+}
+
+impl CachePolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CachePolicy::Keep => "keep",
+            CachePolicy::Drop => "drop",
+            CachePolicy::Warm => "warm",
+        }
+    }
+}
+
+/// Write back dirty pages, then `echo 1 > /proc/sys/vm/drop_caches` (needs root)
+pub fn drop_caches() -> io::Result<()> {
+    unsafe { libc::sync() };
+    fs::write("/proc/sys/vm/drop_caches", "1")
+}
+
+/// Read the whole file or device through the page cache
+pub fn warm(path: &Path) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; 1 << 20];
+    while file.read(&mut buf)? > 0 {}
+    Ok(())
+}
+
+/// Apply `policy` to the target at `path`
+pub fn prepare(path: &Path, policy: CachePolicy) -> io::Result<()> {
+    match policy {
+        CachePolicy::Keep => Ok(()),
+        CachePolicy::Drop => drop_caches(),
+        CachePolicy::Warm => warm(path),
+    }
+}
+
+/// Bytes of the file or device currently in the page cache
+pub fn resident_bytes(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    // Block devices report a zero length in metadata
+    let size = file.seek(SeekFrom::End(0))?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+    let mut resident = 0;
+    let mut vec = Vec::new();
+    for start in (0..size).step_by(MINCORE_CHUNK as usize) {
+        let len = (size - start).min(MINCORE_CHUNK) as usize;
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), start as libc::off_t)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        vec.resize(len.div_ceil(page_size as usize), 0u8);
+        let ret = unsafe { libc::mincore(ptr, len, vec.as_mut_ptr()) };
+        let err = io::Error::last_os_error();
+        unsafe { libc::munmap(ptr, len) };
+        if ret < 0 {
+            return Err(err);
+        }
+        resident += vec.iter().filter(|&&page| page & 1 != 0).count() as u64 * page_size;
+    }
+
+    Ok(resident.min(size))
+}
+
+/// A line of /proc/meminfo in bytes, e.g. `meminfo_bytes("Dirty")`
+pub fn meminfo_bytes(field: &str) -> io::Result<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kib| kib * 1024)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("no {} line in /proc/meminfo", field)))
+}
+
+/// The "Cached" line of /proc/meminfo in bytes: page cache of the whole system
+pub fn system_cached_bytes() -> io::Result<u64> {
+    meminfo_bytes("Cached")
+}
+
+/// Page cache of the target and of the system at one point in time
+#[derive(Debug, Clone, Copy)]
+pub struct CacheSnapshot {
+    pub target: u64,
+    pub system: u64,
+}
+
+impl CacheSnapshot {
+    pub fn take(path: &Path) -> io::Result<CacheSnapshot> {
+        Ok(CacheSnapshot { target: resident_bytes(path)?, system: system_cached_bytes()? })
+    }
+}
+
+/// Page cache consumed by one run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheUsage {
+    /// Bytes of the target resident before and after the run
+    pub target_before: u64,
+    pub target_after: u64,
+    /// Change of the system-wide page cache, including other processes' activity
+    pub system_delta: i64,
+}
+
+impl CacheUsage {
+    pub fn between(before: &CacheSnapshot, after: &CacheSnapshot) -> CacheUsage {
+        CacheUsage {
+            target_before: before.target,
+            target_after: after.target,
+            system_delta: after.system as i64 - before.system as i64,
+        }
+    }
+}
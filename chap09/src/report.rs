@@ -0,0 +1,183 @@
+//! Output formats of the plots, and a self-contained HTML report.
+//!
+//! Plots are written as PNG or SVG files, or collected into one HTML file.
+//! The report inlines its charts as SVG and its styles, and refers to nothing
+//! outside the file, so a whole benchmark session can be passed around as a
+//! single attachment.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where the plots of a run go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// One PNG file per plot
+    #[default]
+    Png,
+    /// One SVG file per plot
+    Svg,
+    /// Every plot, the parameter matrix and the raw numbers in one HTML file
+    Html,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Png, OutputFormat::Svg, OutputFormat::Html];
+
+    pub fn parse(name: &str) -> Option<OutputFormat> {
+        OutputFormat::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    /// Name used in scenario files and on the command line, also the file extension
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Html => "html",
+        }
+    }
+}
+
+/// Escape text for use in HTML element content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Table of plain text cells
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new<S: ToString>(headers: &[S]) -> Table {
+        Table { headers: headers.iter().map(S::to_string).collect(), rows: Vec::new() }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::from("<table>\n<thead><tr>");
+        for header in &self.headers {
+            let _ = write!(html, "<th>{}</th>", escape(header));
+        }
+        html.push_str("</tr></thead>\n<tbody>\n");
+        for row in &self.rows {
+            html.push_str("<tr>");
+            for cell in row {
+                let _ = write!(html, "<td>{}</td>", escape(cell));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n");
+        html
+    }
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2, h3 { border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; margin: 1em 0; font-size: 90%; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; }
+td { font-variant-numeric: tabular-nums; }
+th { background: #eee; }
+tbody tr:nth-child(even) { background: #f8f8f8; }
+figure { display: inline-block; margin: 0.5em; }
+figcaption { text-align: center; font-size: 90%; }
+nav li { margin: 2px 0; }
+";
+
+/// HTML document built section by section
+#[derive(Debug, Clone)]
+pub struct Report {
+    title: String,
+    /// Headings of the table of contents, with their level and anchor
+    toc: Vec<(u8, String, String)>,
+    body: String,
+}
+
+impl Report {
+    pub fn new(title: impl Into<String>) -> Report {
+        Report { title: title.into(), toc: Vec::new(), body: String::new() }
+    }
+
+    /// Start a section; levels 2 and 3 are listed in the table of contents
+    pub fn heading(&mut self, level: u8, text: &str) {
+        let level = level.clamp(2, 6);
+        let anchor = format!("s{}", self.toc.len());
+        let _ = writeln!(self.body, "<h{0} id=\"{1}\">{2}</h{0}>", level, anchor, escape(text));
+        self.toc.push((level, anchor, text.to_string()));
+    }
+
+    pub fn paragraph(&mut self, text: &str) {
+        let _ = writeln!(self.body, "<p>{}</p>", escape(text));
+    }
+
+    pub fn table(&mut self, table: &Table) {
+        self.body.push_str(&table.to_html());
+    }
+
+    /// Table hidden behind a summary line until clicked, for bulky raw data
+    pub fn collapsed_table(&mut self, summary: &str, table: &Table) {
+        let _ = writeln!(self.body, "<details>\n<summary>{}</summary>", escape(summary));
+        self.table(table);
+        self.body.push_str("</details>\n");
+    }
+
+    /// Inline an SVG document as a figure
+    pub fn chart(&mut self, caption: &str, svg: &str) {
+        let svg = svg.trim_start();
+        // Inline SVG must not carry an XML declaration
+        let svg = match svg.strip_prefix("<?xml") {
+            Some(rest) => rest.split_once("?>").map_or(rest, |(_, rest)| rest),
+            None => svg,
+        };
+        let _ = writeln!(self.body, "<figure>\n{}\n<figcaption>{}</figcaption>\n</figure>", svg, escape(caption));
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>\n{1}</style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+            escape(&self.title),
+            STYLE
+        );
+
+        html.push_str("<nav>\n<ul>\n");
+        for (level, anchor, text) in self.toc.iter().filter(|(level, _, _)| *level <= 3) {
+            let indent = if *level == 3 { " style=\"margin-left: 2em\"" } else { "" };
+            let _ = writeln!(html, "<li{}><a href=\"#{}\">{}</a></li>", indent, anchor, escape(text));
+        }
+        html.push_str("</ul>\n</nav>\n");
+
+        html.push_str(&self.body);
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_html())
+    }
+}
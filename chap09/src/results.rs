@@ -0,0 +1,408 @@
+//! Result store shared by every benchmark run, and comparison between runs.
+//!
+//! Results are appended as JSON lines, one record per measurement. A record
+//! carries the host, kernel version and device model it was measured on, the
+//! run parameters and the time, so results of different runs (e.g. before and
+//! after a kernel upgrade) accumulate in one file instead of overwriting each
+//! other.
+
+use crate::histogram::LatencyStats;
+use crate::pagecache::CacheUsage;
+use crate::scenario::RunParams;
+use crate::stats::{self, TTest};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Store used when the caller has no better place
+pub const DEFAULT_STORE: &str = "chap09-results.jsonl";
+
+/// Numbers extracted from one benchmark run
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Measurement {
+    pub latency: LatencyStats,
+    pub iops: f64,
+    /// Bandwidth in bytes per second
+    pub bw_bytes: f64,
+}
+
+impl Measurement {
+    /// Apply `f` to the samples of every field across `results`
+    fn per_field(results: &[Measurement], f: impl Fn(&[f64]) -> f64) -> Measurement {
+        let field = |get: &dyn Fn(&Measurement) -> f64| f(&results.iter().map(get).collect::<Vec<_>>());
+
+        Measurement {
+            latency: LatencyStats {
+                mean: field(&|r| r.latency.mean),
+                p50: field(&|r| r.latency.p50),
+                p90: field(&|r| r.latency.p90),
+                p99: field(&|r| r.latency.p99),
+                p999: field(&|r| r.latency.p999),
+                max: field(&|r| r.latency.max),
+            },
+            iops: field(&|r| r.iops),
+            bw_bytes: field(&|r| r.bw_bytes),
+        }
+    }
+
+    /// Average of several repetitions of the same run
+    pub fn average(results: &[Measurement]) -> Measurement {
+        Measurement::per_field(results, stats::mean)
+    }
+}
+
+/// Confidence level of the intervals reported for repeated runs
+pub const CONFIDENCE: f64 = 0.95;
+
+/// Mean, standard deviation and confidence interval of repeated runs, field by field
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    /// Number of repetitions
+    pub n: usize,
+    pub mean: Measurement,
+    pub stddev: Measurement,
+    /// Half widths of the `CONFIDENCE` intervals; infinite for a single repetition
+    pub ci: Measurement,
+}
+
+impl Summary {
+    pub fn of(results: &[Measurement]) -> Summary {
+        Summary {
+            n: results.len(),
+            mean: Measurement::average(results),
+            stddev: Measurement::per_field(results, stats::stddev),
+            ci: Measurement::per_field(results, |samples| {
+                stats::confidence_interval(samples, CONFIDENCE).half_width
+            }),
+        }
+    }
+}
+
+/// Host and kernel the benchmark runs on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub host: String,
+    pub kernel: String,
+}
+
+impl SystemInfo {
+    pub fn current() -> SystemInfo {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string())
+        };
+        SystemInfo {
+            host: read("/proc/sys/kernel/hostname"),
+            kernel: read("/proc/sys/kernel/osrelease"),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Id of a run started now: the Unix time in milliseconds, so that runs
+/// started within the same second get ids of their own
+pub fn new_run_id() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Start time of a run in seconds since the Unix epoch
+pub fn run_started(run: u64) -> u64 {
+    run / 1000
+}
+
+/// Format a Unix timestamp as local "YYYY-MM-DD HH:MM:SS"
+pub fn format_time(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// One stored measurement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Id of the benchmark invocation, see `new_run_id`
+    pub run: u64,
+    /// Time the measurement finished
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub system: SystemInfo,
+    pub device_model: Option<String>,
+    pub params: RunParams,
+    pub result: Measurement,
+    /// Page cache consumed by the run; absent in records of older versions
+    #[serde(default)]
+    pub page_cache: Option<CacheUsage>,
+}
+
+/// Summary of one run in the store
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub run: u64,
+    pub system: SystemInfo,
+    pub devices: Vec<String>,
+    pub records: usize,
+}
+
+/// JSON lines file of records
+pub struct ResultStore {
+    path: PathBuf,
+}
+
+impl ResultStore {
+    pub fn new(path: impl Into<PathBuf>) -> ResultStore {
+        ResultStore { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one record; each record is a single write so a crash loses at most that line
+    pub fn append(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Every record in the store; a missing store is empty
+    pub fn load(&self) -> Result<Vec<Record>, Box<dyn Error>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut records = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(line)
+                .map_err(|e| format!("{}:{}: {}", self.path.display(), number + 1, e))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Runs in the store, oldest first
+    pub fn runs(&self) -> Result<Vec<RunInfo>, Box<dyn Error>> {
+        let mut runs: Vec<RunInfo> = Vec::new();
+        for record in self.load()? {
+            let info = match runs.iter_mut().find(|info| info.run == record.run) {
+                Some(info) => info,
+                None => {
+                    runs.push(RunInfo {
+                        run: record.run,
+                        system: record.system.clone(),
+                        devices: Vec::new(),
+                        records: 0,
+                    });
+                    runs.last_mut().unwrap()
+                }
+            };
+            if !info.devices.contains(&record.params.device_name) {
+                info.devices.push(record.params.device_name.clone());
+            }
+            info.records += 1;
+        }
+        runs.sort_by_key(|info| info.run);
+        Ok(runs)
+    }
+
+    /// Records of one run
+    pub fn run_records(&self, run: u64) -> Result<Vec<Record>, Box<dyn Error>> {
+        Ok(self.load()?.into_iter().filter(|record| record.run == run).collect())
+    }
+}
+
+/// Metric compared between runs: name, accessor and whether higher is better
+pub type Metric = (&'static str, fn(&Measurement) -> f64, bool);
+
+pub const METRICS: [Metric; 4] = [
+    ("IOPS", |m| m.iops, true),
+    ("BW (MiB/s)", |m| m.bw_bytes / 1024.0 / 1024.0, true),
+    ("lat mean (usec)", |m| m.latency.mean, false),
+    ("lat p99 (usec)", |m| m.latency.p99, false),
+];
+
+/// Outcome of comparing one metric of one configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Improvement,
+    Regression,
+    /// The difference is not significant
+    Unchanged,
+    /// Too few repetitions to test the difference
+    Inconclusive,
+}
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Improvement => "improvement",
+            Verdict::Regression => "REGRESSION",
+            Verdict::Unchanged => "unchanged",
+            Verdict::Inconclusive => "inconclusive",
+        }
+    }
+}
+
+/// One metric of one configuration present in both runs
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// Parameters of the configuration, see `RunParams::comparison_key`
+    pub params: RunParams,
+    pub metric: &'static str,
+    pub before: Vec<f64>,
+    pub after: Vec<f64>,
+    /// Relative change of the mean, (after - before) / before
+    pub change: f64,
+    pub test: Option<TTest>,
+    pub verdict: Verdict,
+}
+
+/// Repetitions of `records` grouped by configuration, in order of first appearance
+fn group(records: &[Record]) -> Vec<(RunParams, Vec<Measurement>)> {
+    let mut grouped: Vec<(RunParams, Vec<Measurement>)> = Vec::new();
+    for record in records {
+        let key = record.params.comparison_key();
+        match grouped.iter_mut().find(|(params, _)| *params == key) {
+            Some((_, results)) => results.push(record.result),
+            None => grouped.push((key, vec![record.result])),
+        }
+    }
+    grouped
+}
+
+/// Compare every configuration measured in both `before` and `after`.
+///
+/// A difference counts when Welch's t-test over the repetitions gives a
+/// p-value below `alpha`; configurations with fewer than two repetitions on
+/// either side are reported as inconclusive.
+pub fn compare(before: &[Record], after: &[Record], alpha: f64) -> Vec<Comparison> {
+    let after = group(after);
+    let mut comparisons = Vec::new();
+
+    for (params, old) in group(before) {
+        let Some((_, new)) = after.iter().find(|(p, _)| *p == params) else {
+            continue;
+        };
+
+        for (metric, value, higher_is_better) in METRICS {
+            let before: Vec<f64> = old.iter().map(value).collect();
+            let after: Vec<f64> = new.iter().map(value).collect();
+            let (mean_before, mean_after) = (stats::mean(&before), stats::mean(&after));
+            let change = if mean_before == 0.0 { 0.0 } else { (mean_after - mean_before) / mean_before };
+
+            let test = stats::welch_t_test(&before, &after);
+            let verdict = match test {
+                None => Verdict::Inconclusive,
+                Some(test) if test.p >= alpha => Verdict::Unchanged,
+                Some(_) if (mean_after > mean_before) == higher_is_better => Verdict::Improvement,
+                Some(_) => Verdict::Regression,
+            };
+
+            comparisons.push(Comparison {
+                params: params.clone(),
+                metric,
+                before,
+                after,
+                change,
+                test,
+                verdict,
+            });
+        }
+    }
+
+    comparisons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stored record as JSON; the sandbox device path changes between runs
+    fn line(run: u64, workload: &str, repetition: u32, iops: f64, lat_mean: f64) -> String {
+        format!(
+            concat!(
+                r#"{{"run":{},"timestamp":{},"host":"bench","kernel":"6.1.0","device_model":null,"#,
+                r#""params":{{"device":"/dev/loop{}","device_name":"loop-sandbox","workload":"{}","rw":"randread","#,
+                r#""engine":"sync","block_size":4096,"queue_depth":1,"num_jobs":1,"scheduler":null,"read_ahead":null,"#,
+                r#""runtime_secs":5,"repetition":{}}},"#,
+                r#""result":{{"latency":{{"mean":{},"p50":0,"p90":0,"p99":500,"p99.9":0,"max":0}},"iops":{},"bw_bytes":{}}}}}"#,
+            ),
+            run, run / 1000, run % 7, workload, repetition, lat_mean, iops, iops * 4096.0,
+        )
+    }
+
+    fn store() -> (tempfile::TempDir, ResultStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ResultStore::new(dir.path().join("results.jsonl"));
+        let mut lines = Vec::new();
+        for (repetition, (old, new)) in [(1000.0, 1200.0), (1010.0, 1210.0), (990.0, 1190.0)].into_iter().enumerate() {
+            lines.push(line(1_000_000, "randread", repetition as u32, old, 1e6 / old));
+            lines.push(line(2_000_000, "randread", repetition as u32, new, 1e6 / new));
+        }
+        lines.push(line(1_000_000, "seqread", 0, 5000.0, 200.0));
+        lines.push(line(2_000_000, "seqread", 0, 5100.0, 196.0));
+        lines.push(line(1_000_000, "randwrite", 0, 800.0, 1250.0));
+        fs::write(store.path(), lines.join("\n") + "\n\n").unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn runs_are_listed_from_the_store() {
+        let (_dir, store) = store();
+        let runs = store.runs().unwrap();
+        let summary: Vec<(u64, usize)> = runs.iter().map(|info| (info.run, info.records)).collect();
+        assert_eq!(summary, [(1_000_000, 5), (2_000_000, 4)]);
+        assert_eq!(runs[0].devices, ["loop-sandbox"]);
+        assert_eq!(store.run_records(2_000_000).unwrap().len(), 4);
+        assert!(ResultStore::new("/nonexistent/results.jsonl").load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn runs_are_compared_per_configuration() {
+        let (_dir, store) = store();
+        let comparisons = compare(&store.run_records(1_000_000).unwrap(), &store.run_records(2_000_000).unwrap(), 0.05);
+        let verdict = |workload: &str, metric: &str| {
+            let comparison = comparisons.iter().find(|c| c.params.workload == workload && c.metric == metric).unwrap();
+            (comparison.verdict, comparison.change)
+        };
+
+        // Configurations only in one run are left out
+        assert_eq!(comparisons.len(), 2 * METRICS.len());
+        let (iops, change) = verdict("randread", "IOPS");
+        assert_eq!(iops, Verdict::Improvement);
+        assert!((change - 0.2).abs() < 1e-9);
+        assert_eq!(verdict("randread", "lat mean (usec)").0, Verdict::Improvement);
+        assert_eq!(verdict("randread", "lat p99 (usec)").0, Verdict::Unchanged);
+        assert_eq!(verdict("seqread", "IOPS").0, Verdict::Inconclusive);
+
+        // The same numbers read the other way round are regressions
+        let reverse = compare(&store.run_records(2_000_000).unwrap(), &store.run_records(1_000_000).unwrap(), 0.05);
+        let iops = reverse.iter().find(|c| c.params.workload == "randread" && c.metric == "IOPS").unwrap();
+        assert_eq!(iops.verdict, Verdict::Regression);
+    }
+}
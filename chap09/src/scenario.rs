@@ -0,0 +1,566 @@
+//! Declarative benchmark scenario files and their expansion into a run matrix.
+//!
+//! A scenario is a JSON file listing the devices, the workloads and the value
+//! of every parameter axis to sweep. Axes set at the top level apply to every
+//! workload unless the workload sets the axis itself:
+//!
+//! ```json
+//! {
+//!   "devices": [{ "path": "/dev/sdb", "name": "sdb" }],
+//!   "schedulers": ["mq-deadline", "none"],
+//!   "runtimes": [60],
+//!   "repetitions": 1,
+//!   "workloads": [
+//!     { "name": "read", "rw": "read", "read_ahead": [0, 256] },
+//!     { "name": "randwrite", "rw": "randwrite", "num_jobs": [1, 2, 4], "plot": "jobs" }
+//!   ]
+//! }
+//! ```
+//!
+//! An empty `schedulers` or `read_ahead` list leaves that tunable unchanged.
+//! Devices can also be directories, files or loop device sandboxes, and
+//! workloads can mix, skew or replay I/O and run tenants in cgroups; see the
+//! items below for their keys.
+
+use crate::cgroup::GroupSpec;
+use crate::loadgen::{self, Distribution, Engine, IoMode};
+use crate::loopdev::SandboxSpec;
+use crate::pagecache::CachePolicy;
+use crate::report::OutputFormat;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+
+/// Default size of the test file created in a directory target
+pub const DEFAULT_FILE_SIZE_MB: u64 = 1024;
+
+/// Device to benchmark; `name` is used in output file names
+///
+/// A directory `path` is a filesystem under test: the runs use a test file of
+/// `file_size_mb` in it, e.g. `{ "path": "/mnt/ext4", "name": "ext4", "file_size_mb": 512 }`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceEntry {
+    /// Block device, regular file, or directory to create a test file in
+    #[serde(default)]
+    pub path: String,
+    pub name: String,
+    /// Size of the test file created when `path` is a directory (default 1024)
+    #[serde(default)]
+    pub file_size_mb: Option<u64>,
+    /// Loop device created before the device's benchmarks and removed
+    /// afterwards instead of an existing `path`, e.g.
+    /// `{ "size_mb": 1024, "dir": "/dev/shm", "fs": "ext4" }`; with `fs` it
+    /// is formatted and mounted and the runs use a test file in it
+    #[serde(default)]
+    pub sandbox: Option<SandboxSpec>,
+}
+
+/// How the results of a workload are plotted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlotKind {
+    /// One category per tunable combination (scheduler, read-ahead, ...)
+    #[default]
+    Tunables,
+    /// Number of jobs on the x-axis, one series per engine and scheduler
+    Jobs,
+    /// One bar per cgroup in each configuration, with the group's limits and fairness
+    Groups,
+}
+
+/// Parameter axes of the matrix; unset axes fall back to the scenario or the defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Axes {
+    pub engines: Option<Vec<String>>,
+    /// "direct", "buffered", "dsync" or "mmap"; mmap runs only with the sync
+    /// engine, other engines skip it
+    pub io_modes: Option<Vec<String>>,
+    pub block_sizes: Option<Vec<usize>>,
+    pub queue_depths: Option<Vec<u32>>,
+    pub num_jobs: Option<Vec<u32>>,
+    pub schedulers: Option<Vec<String>>,
+    /// Read-ahead in 512-byte sectors (the unit of `blockdev --setra`)
+    pub read_ahead: Option<Vec<u32>>,
+    /// Runtime of each run in seconds
+    pub runtimes: Option<Vec<u64>>,
+}
+
+/// How a workload accesses the device beyond its I/O type, e.g.
+/// `"rw": "randrw", "read_pct": 70, "distribution": "zipf:1.2", "fsync": 32`
+/// or `"rw": "replay", "trace": "sdb.blkparse"`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessOptions {
+    /// Percentage of reads of "rw" and "randrw" workloads (fio's rwmixread, default 50)
+    pub read_pct: Option<u8>,
+    /// Offsets of random I/O: "uniform", "zipf:<exponent>" or "hotspot:<io_pct>/<space_pct>"
+    pub distribution: Option<String>,
+    /// fsync after every N writes of a worker
+    pub fsync: Option<u32>,
+    /// blkparse output replayed by "replay" workloads
+    pub trace: Option<String>,
+    /// Replay the trace as fast as the queue allows instead of at its recorded pace
+    #[serde(default)]
+    pub trace_fast: bool,
+    /// Drop or pre-warm the page cache before each measured run
+    #[serde(default)]
+    pub cache: CachePolicy,
+}
+
+impl AccessOptions {
+    /// Parsed offset distribution
+    pub fn distribution(&self) -> Result<Distribution, String> {
+        match &self.distribution {
+            None => Ok(Distribution::Uniform),
+            Some(text) => Distribution::parse(text).ok_or_else(|| format!("invalid distribution {}", text)),
+        }
+    }
+
+    /// Short description for run ids; empty when every option has its default
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(read_pct) = self.read_pct {
+            parts.push(format!("rmix{}", read_pct));
+        }
+        if let Some(distribution) = &self.distribution {
+            parts.push(distribution.replace([':', '/'], ""));
+        }
+        if let Some(fsync) = self.fsync {
+            parts.push(format!("fsync{}", fsync));
+        }
+        if self.trace_fast {
+            parts.push("fast".to_string());
+        }
+        if self.cache != CachePolicy::Keep {
+            parts.push(self.cache.name().to_string());
+        }
+        parts.join("-")
+    }
+}
+
+/// One workload of the scenario
+#[derive(Debug, Clone, Deserialize)]
+pub struct Workload {
+    pub name: String,
+    /// fio style I/O type (read, write, rw, randread, randwrite, randrw), or
+    /// "replay" to replay the workload's trace
+    pub rw: String,
+    #[serde(default)]
+    pub plot: PlotKind,
+    #[serde(flatten)]
+    pub access: AccessOptions,
+    /// Tenants running concurrently in cgroups of their own, each with its
+    /// io.max limits and io.weight, e.g. `{ "name": "web", "weight": 400, "rbps": 10485760 }`;
+    /// none runs the workload alone
+    #[serde(default)]
+    pub groups: Vec<GroupSpec>,
+    #[serde(flatten)]
+    pub axes: Axes,
+}
+
+/// Contents of a scenario file
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub devices: Vec<DeviceEntry>,
+    pub workloads: Vec<Workload>,
+    /// Maximum number of measured runs per configuration
+    #[serde(default = "default_repetitions")]
+    pub repetitions: u32,
+    /// Runs before the first repetition of each configuration that are thrown away
+    #[serde(default)]
+    pub warmup: u32,
+    /// Relative half width of the 95% confidence interval of the IOPS that
+    /// ends the repetitions early, e.g. 0.02 for ±2%
+    #[serde(default)]
+    pub ci_target: Option<f64>,
+    /// Repetitions measured before `ci_target` may end them
+    #[serde(default = "default_min_repetitions")]
+    pub min_repetitions: u32,
+    /// Interval of the device statistics sampled during each run; 0 turns sampling off
+    #[serde(default = "default_iostat_interval_ms")]
+    pub iostat_interval_ms: u64,
+    /// How the plots are written: "png" (the default) and "svg" files, and
+    /// an "html" report with every plot, the parameter matrix, the host and
+    /// the raw numbers of every run
+    #[serde(default = "default_formats")]
+    pub formats: Vec<OutputFormat>,
+    /// Enable io.cost on the devices while workloads with groups run, so io.weight applies with any scheduler
+    #[serde(default)]
+    pub iocost: bool,
+    #[serde(flatten)]
+    pub axes: Axes,
+}
+
+fn default_repetitions() -> u32 {
+    1
+}
+
+fn default_min_repetitions() -> u32 {
+    3
+}
+
+fn default_iostat_interval_ms() -> u64 {
+    250
+}
+
+fn default_formats() -> Vec<OutputFormat> {
+    vec![OutputFormat::Png]
+}
+
+/// One point of the expanded matrix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunParams {
+    pub device: String,
+    pub device_name: String,
+    pub workload: String,
+    pub rw: String,
+    pub engine: Engine,
+    #[serde(default)]
+    pub io_mode: IoMode,
+    pub block_size: usize,
+    pub queue_depth: u32,
+    pub num_jobs: u32,
+    /// `None` leaves the scheduler unchanged
+    pub scheduler: Option<String>,
+    /// `None` leaves the read-ahead unchanged
+    pub read_ahead: Option<u32>,
+    pub runtime_secs: u64,
+    /// Zero based repetition index
+    pub repetition: u32,
+    #[serde(default)]
+    pub access: AccessOptions,
+    /// Group the run executes in, next to the other groups of its workload
+    #[serde(default)]
+    pub cgroup: Option<GroupSpec>,
+}
+
+impl RunParams {
+    /// Unique name of the run, used as the prefix of its output files
+    pub fn id(&self) -> String {
+        let access = self.access.label();
+        format!(
+            "{}-{}-{}-{}-bs{}-qd{}-nj{}-ra{}-{}-{}s{}{}-r{}",
+            self.workload,
+            self.device_name,
+            self.engine.name(),
+            self.io_mode.name(),
+            self.block_size,
+            self.queue_depth,
+            self.num_jobs,
+            self.read_ahead.map_or("keep".to_string(), |ra| ra.to_string()),
+            self.scheduler_label(),
+            self.runtime_secs,
+            if access.is_empty() { String::new() } else { format!("-{}", access) },
+            self.cgroup.as_ref().map_or(String::new(), |group| format!("-cg{}", group.name)),
+            self.repetition,
+        )
+    }
+
+    /// The configuration this run repeats, i.e. the parameters without the repetition index
+    pub fn configuration(&self) -> RunParams {
+        RunParams { repetition: 0, ..self.clone() }
+    }
+
+    /// The configuration as compared between runs: without the device path,
+    /// since a sandbox's loop device and mount point change on every run;
+    /// `device_name` still tells the devices apart
+    pub fn comparison_key(&self) -> RunParams {
+        RunParams { device: String::new(), ..self.configuration() }
+    }
+
+    /// The parameters shared by the groups that run together, i.e. without
+    /// the group and the I/O type it may override
+    pub fn batch(&self) -> RunParams {
+        RunParams { rw: String::new(), cgroup: None, ..self.clone() }
+    }
+
+    /// Scheduler name for labels; "current" when the scheduler is left unchanged
+    pub fn scheduler_label(&self) -> &str {
+        self.scheduler.as_deref().unwrap_or("current")
+    }
+}
+
+/// Pick the workload's axis, else the scenario's, else the default
+fn axis<T: Clone>(workload: &Option<Vec<T>>, scenario: &Option<Vec<T>>, default: Vec<T>) -> Vec<T> {
+    workload.clone().or_else(|| scenario.clone()).unwrap_or(default)
+}
+
+/// Turn an empty list into a single "leave unchanged" entry
+fn optional_axis<T: Clone>(values: Vec<T>) -> Vec<Option<T>> {
+    if values.is_empty() {
+        vec![None]
+    } else {
+        values.into_iter().map(Some).collect()
+    }
+}
+
+impl Scenario {
+    /// Read a scenario from a JSON file
+    pub fn load(path: &str) -> Result<Scenario, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read scenario {}: {}", path, e))?;
+        let scenario: Scenario = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid scenario {}: {}", path, e))?;
+
+        if scenario.devices.is_empty() {
+            return Err(format!("Scenario {} lists no devices", path).into());
+        }
+        for device in &scenario.devices {
+            if device.path.is_empty() == device.sandbox.is_none() {
+                return Err(format!("Scenario {}: device {} needs either a path or a sandbox", path, device.name).into());
+            }
+        }
+        if scenario.workloads.is_empty() {
+            return Err(format!("Scenario {} lists no workloads", path).into());
+        }
+        if scenario.repetitions == 0 {
+            return Err(format!("Scenario {}: repetitions must be at least 1", path).into());
+        }
+        if scenario.ci_target.is_some_and(|target| target <= 0.0) {
+            return Err(format!("Scenario {}: ci_target must be positive", path).into());
+        }
+        if scenario.ci_target.is_some() && scenario.repetitions < scenario.min_repetitions {
+            return Err(format!(
+                "Scenario {}: ci_target needs repetitions ({}) of at least min_repetitions ({}) to stop early",
+                path, scenario.repetitions, scenario.min_repetitions
+            )
+            .into());
+        }
+
+        Ok(scenario)
+    }
+
+    /// Expand one workload on one device into its runs
+    ///
+    /// Scheduler and read-ahead are the outer loops so the tunables change as
+    /// rarely as possible while the matrix is executed in order.
+    pub fn expand_workload(&self, device: &DeviceEntry, workload: &Workload) -> Result<Vec<RunParams>, Box<dyn Error>> {
+        let replay = workload.rw == "replay";
+        if !replay && loadgen::parse_rw(&workload.rw).is_none() {
+            return Err(format!("Workload {}: unsupported rw type {}", workload.name, workload.rw).into());
+        }
+        if replay != workload.access.trace.is_some() {
+            return Err(format!("Workload {}: a trace goes with rw \"replay\" and only with it", workload.name).into());
+        }
+        if workload.access.read_pct.is_some_and(|pct| pct > 100) {
+            return Err(format!("Workload {}: read_pct must be between 0 and 100", workload.name).into());
+        }
+        if workload.access.fsync == Some(0) {
+            return Err(format!("Workload {}: fsync must be at least 1", workload.name).into());
+        }
+        workload.access.distribution().map_err(|e| format!("Workload {}: {}", workload.name, e))?;
+        for (i, group) in workload.groups.iter().enumerate() {
+            let valid_name = !group.name.is_empty()
+                && group.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name || workload.groups[..i].iter().any(|other| other.name == group.name) {
+                return Err(format!("Workload {}: group names must be unique and made of letters, digits, '-' and '_'", workload.name).into());
+            }
+            if !(1..=10000).contains(&group.weight()) {
+                return Err(format!("Workload {}: weight of group {} must be between 1 and 10000", workload.name, group.name).into());
+            }
+            if group.rw.as_deref().is_some_and(|rw| replay || loadgen::parse_rw(rw).is_none()) {
+                return Err(format!("Workload {}: unsupported rw type of group {}", workload.name, group.name).into());
+            }
+        }
+        if workload.plot == PlotKind::Groups && workload.groups.is_empty() {
+            return Err(format!("Workload {}: the groups plot needs groups", workload.name).into());
+        }
+
+        let w = &workload.axes;
+        let s = &self.axes;
+        let engines = axis(&w.engines, &s.engines, vec!["libaio".to_string()])
+            .iter()
+            .map(|name| {
+                Engine::parse(name).ok_or_else(|| format!("Workload {}: unknown engine {}", workload.name, name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let io_modes = axis(&w.io_modes, &s.io_modes, vec!["direct".to_string()])
+            .iter()
+            .map(|name| {
+                IoMode::parse(name).ok_or_else(|| format!("Workload {}: unknown I/O mode {}", workload.name, name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // mmap access only works with the sync engine
+        let submissions: Vec<(Engine, IoMode)> = engines
+            .iter()
+            .flat_map(|&engine| io_modes.iter().map(move |&io_mode| (engine, io_mode)))
+            .filter(|&(engine, io_mode)| io_mode != IoMode::Mmap || engine == Engine::Sync)
+            .collect();
+        if submissions.is_empty() {
+            return Err(format!("Workload {}: mmap access needs the sync engine", workload.name).into());
+        }
+        let block_sizes = axis(&w.block_sizes, &s.block_sizes, vec![4096]);
+        let queue_depths = axis(&w.queue_depths, &s.queue_depths, vec![1]);
+        let num_jobs = axis(&w.num_jobs, &s.num_jobs, vec![1]);
+        let schedulers = optional_axis(axis(&w.schedulers, &s.schedulers, Vec::new()));
+        let read_ahead = optional_axis(axis(&w.read_ahead, &s.read_ahead, Vec::new()));
+        let runtimes = axis(&w.runtimes, &s.runtimes, vec![60]);
+        // The groups of a workload run together, so they are next to each other in the matrix
+        let groups: Vec<Option<&GroupSpec>> = if workload.groups.is_empty() {
+            vec![None]
+        } else {
+            workload.groups.iter().map(Some).collect()
+        };
+
+        let mut runs = Vec::new();
+        for scheduler in &schedulers {
+            for &ra in &read_ahead {
+                for &(engine, io_mode) in &submissions {
+                    for &block_size in &block_sizes {
+                        for &queue_depth in &queue_depths {
+                            for &nj in &num_jobs {
+                                for &runtime_secs in &runtimes {
+                                    for repetition in 0..self.repetitions {
+                                        for group in &groups {
+                                            runs.push(RunParams {
+                                                device: device.path.clone(),
+                                                device_name: device.name.clone(),
+                                                workload: workload.name.clone(),
+                                                rw: group.and_then(|g| g.rw.clone()).unwrap_or_else(|| workload.rw.clone()),
+                                                engine,
+                                                io_mode,
+                                                block_size,
+                                                queue_depth,
+                                                num_jobs: nj,
+                                                scheduler: scheduler.clone(),
+                                                read_ahead: ra,
+                                                runtime_secs,
+                                                repetition,
+                                                access: workload.access.clone(),
+                                                cgroup: group.cloned(),
+                                            });
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(runs)
+    }
+
+    /// Expand every workload on `device` into the full run matrix
+    pub fn expand(&self, device: &DeviceEntry) -> Result<Vec<RunParams>, Box<dyn Error>> {
+        let mut runs = Vec::new();
+        for workload in &self.workloads {
+            runs.extend(self.expand_workload(device, workload)?);
+        }
+        Ok(runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `json` through a scenario file, as the benchmarks do
+    fn load(json: &str) -> Result<Scenario, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scenario.json");
+        fs::write(&path, json).unwrap();
+        Scenario::load(path.to_str().unwrap()).map_err(|e| e.to_string())
+    }
+
+    /// A scenario on one device with `workload` and the top level `extra` keys
+    fn with_workload(workload: &str, extra: &str) -> Scenario {
+        load(&format!(r#"{{ "devices": [{{ "path": "/dev/sdb", "name": "sdb" }}], "workloads": [{}]{} }}"#, workload, extra))
+            .unwrap()
+    }
+
+    fn expand_error(workload: &str) -> String {
+        let scenario = with_workload(workload, "");
+        scenario.expand_workload(&scenario.devices[0], &scenario.workloads[0]).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn axes_expand_in_matrix_order() {
+        let scenario = with_workload(
+            r#"{ "name": "rand", "rw": "randread", "read_ahead": [0, 256], "engines": ["sync", "io_uring"],
+                 "io_modes": ["direct", "mmap"], "block_sizes": [4096, 65536], "num_jobs": [1, 2] },
+               { "name": "seq", "rw": "read", "schedulers": [] }"#,
+            r#", "schedulers": ["mq-deadline", "none"], "runtimes": [5], "repetitions": 2"#,
+        );
+        let runs = scenario.expand(&scenario.devices[0]).unwrap();
+
+        // 2 schedulers x 2 read-aheads x 3 engine/mode pairs (no mmap with
+        // io_uring) x 2 block sizes x 2 job counts x 2 repetitions, then the
+        // second workload with the scheduler left unchanged
+        let (rand, seq) = runs.split_at(96);
+        assert_eq!(seq.len(), 2);
+        assert!(seq.iter().all(|run| run.workload == "seq" && run.scheduler.is_none() && run.engine == Engine::Aio));
+
+        assert_eq!(rand[0].id(), "rand-sdb-sync-direct-bs4096-qd1-nj1-ra0-mq-deadline-5s-r0");
+        assert_eq!(rand[1].id(), "rand-sdb-sync-direct-bs4096-qd1-nj1-ra0-mq-deadline-5s-r1");
+        assert_eq!(rand[2].id(), "rand-sdb-sync-direct-bs4096-qd1-nj2-ra0-mq-deadline-5s-r0");
+        assert_eq!(rand[4].block_size, 65536);
+        assert_eq!((rand[8].engine, rand[8].io_mode), (Engine::Sync, IoMode::Mmap));
+        assert_eq!((rand[16].engine, rand[16].io_mode), (Engine::IoUring, IoMode::Direct));
+        assert_eq!(rand[24].read_ahead, Some(256));
+        // The scheduler changes once, half way through
+        let switches = rand.windows(2).filter(|pair| pair[0].scheduler != pair[1].scheduler).count();
+        assert_eq!((switches, rand[48].scheduler.as_deref()), (1, Some("none")));
+    }
+
+    #[test]
+    fn groups_of_a_workload_run_next_to_each_other() {
+        let scenario = with_workload(
+            r#"{ "name": "mixed", "rw": "randread", "num_jobs": [1, 2],
+                 "groups": [{ "name": "web", "weight": 400 }, { "name": "batch", "rw": "write", "wbps": 1048576 }] }"#,
+            "",
+        );
+        let runs = scenario.expand(&scenario.devices[0]).unwrap();
+        let order: Vec<(u32, &str, &str)> = runs
+            .iter()
+            .map(|run| (run.num_jobs, run.cgroup.as_ref().unwrap().name.as_str(), run.rw.as_str()))
+            .collect();
+        assert_eq!(order, [(1, "web", "randread"), (1, "batch", "write"), (2, "web", "randread"), (2, "batch", "write")]);
+        assert_eq!(runs[0].batch(), runs[1].batch());
+    }
+
+    #[test]
+    fn bad_scenarios_are_rejected() {
+        let device = r#"[{ "path": "/dev/sdb", "name": "sdb" }]"#;
+        let workload = r#"[{ "name": "read", "rw": "read" }]"#;
+        for (json, error) in [
+            (format!(r#"{{ "devices": [], "workloads": {} }}"#, workload), "lists no devices"),
+            (format!(r#"{{ "devices": [{{ "name": "sdb" }}], "workloads": {} }}"#, workload), "either a path or a sandbox"),
+            (format!(r#"{{ "devices": {}, "workloads": [] }}"#, device), "lists no workloads"),
+            (format!(r#"{{ "devices": {}, "workloads": {}, "repetitions": 0 }}"#, device, workload), "at least 1"),
+            (format!(r#"{{ "devices": {}, "workloads": {}, "repetitions": "two" }}"#, device, workload), "Invalid scenario"),
+            (format!(r#"{{ "devices": {}, "workloads": {}, "ci_target": -0.1 }}"#, device, workload), "must be positive"),
+            (
+                format!(r#"{{ "devices": {}, "workloads": {}, "ci_target": 0.05, "repetitions": 2 }}"#, device, workload),
+                "of at least min_repetitions (3)",
+            ),
+        ] {
+            let e = load(&json).unwrap_err();
+            assert!(e.contains(error), "{:?} gave {:?}", json, e);
+        }
+        assert!(load(&format!(r#"{{ "devices": {}, "workloads": {}, "ci_target": 0.05, "repetitions": 5 }}"#, device, workload)).is_ok());
+    }
+
+    #[test]
+    fn bad_workloads_are_rejected() {
+        for (workload, error) in [
+            (r#"{ "name": "w", "rw": "sideways" }"#, "unsupported rw type"),
+            (r#"{ "name": "w", "rw": "replay" }"#, "a trace goes with rw \"replay\""),
+            (r#"{ "name": "w", "rw": "read", "trace": "sdb.blkparse" }"#, "a trace goes with rw \"replay\""),
+            (r#"{ "name": "w", "rw": "randrw", "read_pct": 101 }"#, "read_pct"),
+            (r#"{ "name": "w", "rw": "randwrite", "fsync": 0 }"#, "fsync must be at least 1"),
+            (r#"{ "name": "w", "rw": "randread", "distribution": "zipf:x" }"#, "invalid distribution"),
+            (r#"{ "name": "w", "rw": "read", "groups": [{ "name": "a" }, { "name": "a" }] }"#, "group names must be unique"),
+            (r#"{ "name": "w", "rw": "read", "groups": [{ "name": "a b" }] }"#, "group names must be unique"),
+            (r#"{ "name": "w", "rw": "read", "groups": [{ "name": "a", "weight": 0 }] }"#, "between 1 and 10000"),
+            (r#"{ "name": "w", "rw": "read", "groups": [{ "name": "a", "rw": "up" }] }"#, "rw type of group a"),
+            (r#"{ "name": "w", "rw": "read", "plot": "groups" }"#, "needs groups"),
+            (r#"{ "name": "w", "rw": "read", "engines": ["spdk"] }"#, "unknown engine spdk"),
+            (r#"{ "name": "w", "rw": "read", "io_modes": ["psychic"] }"#, "unknown I/O mode psychic"),
+            (r#"{ "name": "w", "rw": "read", "engines": ["libaio"], "io_modes": ["mmap"] }"#, "needs the sync engine"),
+        ] {
+            let e = expand_error(workload);
+            assert!(e.starts_with("Workload w: ") && e.contains(error), "{} gave {:?}", workload, e);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Summary statistics and significance tests over repeated measurements.
+
+/// Arithmetic mean; 0 for no samples
+pub fn mean(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Sample variance (n - 1 in the denominator); 0 for fewer than two samples
+pub fn variance(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(samples);
+    samples.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (samples.len() - 1) as f64
+}
+
+/// Sample standard deviation
+pub fn stddev(samples: &[f64]) -> f64 {
+    variance(samples).sqrt()
+}
+
+/// Jain's fairness index: 1 when every share is equal, 1/n when one takes everything
+pub fn jain_index(shares: &[f64]) -> f64 {
+    let sum_sq = shares.iter().map(|x| x * x).sum::<f64>();
+    if sum_sq == 0.0 {
+        return 1.0;
+    }
+    let sum = shares.iter().sum::<f64>();
+    sum * sum / (shares.len() as f64 * sum_sq)
+}
+
+/// Natural logarithm of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, c) in COEFFS.iter().enumerate() {
+        series += c / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Continued fraction of the incomplete beta function
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+
+    for m in 1..300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        for aa in [
+            m * (b - m) * x / ((a + m2 - 1.0) * (a + m2)),
+            -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0)),
+        ] {
+            d = 1.0 + aa * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+fn beta_inc(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1.0 - front * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Two-sided p-value of Student's t distribution with `df` degrees of freedom
+pub fn t_two_sided_p(t: f64, df: f64) -> f64 {
+    beta_inc(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Result of Welch's unequal variances t-test
+#[derive(Debug, Clone, Copy)]
+pub struct TTest {
+    pub t: f64,
+    pub df: f64,
+    /// Two-sided p-value
+    pub p: f64,
+}
+
+/// Welch's t-test of the means of `a` and `b`; `None` with fewer than two samples on a side
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<TTest> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let (va, vb) = (variance(a) / na, variance(b) / nb);
+    let diff = mean(b) - mean(a);
+
+    // Identical samples on both sides: the difference is either exact or none
+    if va + vb == 0.0 {
+        let (t, p) = if diff == 0.0 { (0.0, 1.0) } else { (diff.signum() * f64::INFINITY, 0.0) };
+        return Some(TTest { t, df: na + nb - 2.0, p });
+    }
+
+    let t = diff / (va + vb).sqrt();
+    let df = (va + vb).powi(2) / (va * va / (na - 1.0) + vb * vb / (nb - 1.0));
+    Some(TTest { t, df, p: t_two_sided_p(t, df) })
+}
+
+/// Critical value t such that a two-sided interval of ±t covers `level` of
+/// Student's t distribution with `df` degrees of freedom
+pub fn t_critical(level: f64, df: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 1e6);
+    // The two-sided p-value falls monotonically with t
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if t_two_sided_p(mid, df) > 1.0 - level {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Confidence interval of the mean
+#[derive(Debug, Clone, Copy)]
+pub struct ConfidenceInterval {
+    pub n: usize,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Half width of the interval; infinite with fewer than two samples
+    pub half_width: f64,
+}
+
+impl ConfidenceInterval {
+    /// Half width relative to the mean
+    pub fn relative(&self) -> f64 {
+        if self.mean == 0.0 {
+            return f64::INFINITY;
+        }
+        self.half_width / self.mean.abs()
+    }
+}
+
+/// Confidence interval of the mean of `samples` at `level` (e.g. 0.95)
+pub fn confidence_interval(samples: &[f64], level: f64) -> ConfidenceInterval {
+    let n = samples.len();
+    let stddev = stddev(samples);
+    let half_width = if n < 2 {
+        f64::INFINITY
+    } else {
+        t_critical(level, (n - 1) as f64) * stddev / (n as f64).sqrt()
+    };
+    ConfidenceInterval { n, mean: mean(samples), stddev, half_width }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn ln_gamma_matches_factorials() {
+        assert_close(ln_gamma(1.0), 0.0, 1e-9);
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), 1e-9);
+        assert_close(ln_gamma(5.0), 24f64.ln(), 1e-9);
+        assert_close(ln_gamma(10.5), 13.940_625_219_403_763, 1e-9);
+    }
+
+    #[test]
+    fn incomplete_beta_matches_closed_forms() {
+        for x in [0.1, 0.4, 0.9] {
+            // I_x(1, 1) = x and I_x(a, 1) = x^a
+            assert_close(beta_inc(1.0, 1.0, x), x, 1e-9);
+            assert_close(beta_inc(3.0, 1.0, x), x.powi(3), 1e-9);
+            // I_x(a, b) = 1 - I_(1-x)(b, a)
+            assert_close(beta_inc(2.0, 3.0, x), 1.0 - beta_inc(3.0, 2.0, 1.0 - x), 1e-9);
+        }
+        // 1 - P(Binomial(4, 0.4) <= 1)
+        assert_close(beta_inc(2.0, 3.0, 0.4), 0.5248, 1e-9);
+        assert_eq!((beta_inc(2.0, 3.0, 0.0), beta_inc(2.0, 3.0, 1.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn two_sided_p_values_match_tables() {
+        assert_close(t_two_sided_p(2.228, 10.0), 0.05, 1e-4);
+        assert_close(t_two_sided_p(-2.228, 10.0), 0.05, 1e-4);
+        // Cauchy: P(|T| > 1) = 1/2
+        assert_close(t_two_sided_p(1.0, 1.0), 0.5, 1e-9);
+        assert_eq!(t_two_sided_p(0.0, 5.0), 1.0);
+    }
+
+    #[test]
+    fn welch_t_test_uses_unequal_variances() {
+        let a = [10.0, 12.0, 11.0, 13.0, 9.0];
+        let b = [14.0, 18.0, 11.0, 20.0, 15.0, 17.0];
+        let test = welch_t_test(&a, &b).unwrap();
+        assert_close(test.t, 3.262_754_912_685_47, 1e-9);
+        // Welch-Satterthwaite, below the pooled 9 degrees of freedom
+        assert_close(test.df, 7.563_015_026_660_205, 1e-9);
+        assert_close(test.p, 0.012_401_162, 1e-6);
+
+        let reversed = welch_t_test(&b, &a).unwrap();
+        assert_eq!((reversed.t, reversed.p), (-test.t, test.p));
+    }
+
+    #[test]
+    fn welch_t_test_handles_degenerate_samples() {
+        assert!(welch_t_test(&[1.0], &[1.0, 2.0]).is_none());
+        let same = welch_t_test(&[5.0, 5.0], &[5.0, 5.0, 5.0]).unwrap();
+        assert_eq!((same.t, same.p, same.df), (0.0, 1.0, 3.0));
+        let shifted = welch_t_test(&[5.0, 5.0], &[6.0, 6.0]).unwrap();
+        assert_eq!((shifted.t, shifted.p), (f64::INFINITY, 0.0));
+    }
+
+    #[test]
+    fn critical_values_match_tables() {
+        for (df, expected) in [(1.0, 12.706), (2.0, 4.303), (9.0, 2.262), (30.0, 2.042)] {
+            assert_close(t_critical(0.95, df), expected, 1e-3);
+        }
+        assert_close(t_critical(0.99, 9.0), 3.250, 1e-3);
+        // Approaches the normal quantile
+        assert_close(t_critical(0.95, 1e6), 1.960, 1e-3);
+    }
+
+    #[test]
+    fn confidence_interval_uses_the_critical_value() {
+        let ci = confidence_interval(&[9.0, 10.0, 11.0, 10.0, 12.0, 8.0, 10.0, 9.0, 11.0, 10.0], 0.95);
+        assert_eq!((ci.n, ci.mean), (10, 10.0));
+        assert_close(ci.half_width, 2.262 * ci.stddev / 10f64.sqrt(), 1e-3);
+        assert!(confidence_interval(&[10.0], 0.95).half_width.is_infinite());
+    }
+}
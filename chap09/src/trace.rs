@@ -0,0 +1,202 @@
+//! Block traces in blkparse's default text format, for replay by the load generator.
+//!
+//! Each event line looks like
+//!
+//! ```text
+//!   8,0    3       11     0.009507758   697  Q  WS 3418384 + 8 [kworker/3:1]
+//! ```
+//!
+//! i.e. device, CPU, sequence number, time in seconds, PID, action, RWBS
+//! flags, start sector and "+ <sectors>". Lines without a "+ <sectors>" part
+//! (plugs, messages, the per-CPU summary) are skipped. Queue (Q) events are
+//! replayed, as they are what the application submitted; traces without them
+//! fall back to issue (D) events.
+
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+/// Size of the sectors blkparse reports
+pub const SECTOR_SIZE: u64 = 512;
+
+/// One recorded request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    /// Time since the first replayed request
+    pub at: Duration,
+    pub write: bool,
+    /// Offset in bytes
+    pub offset: u64,
+    /// Length in bytes
+    pub len: usize,
+}
+
+/// Requests of a trace in recorded order
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+/// A parsed event line: (action, time in seconds, entry without the relative time)
+fn parse_line(line: &str) -> Result<Option<(String, f64, TraceEntry)>, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let Some(plus) = fields.iter().position(|&f| f == "+") else {
+        return Ok(None);
+    };
+    if plus < 8 || plus + 1 >= fields.len() {
+        return Ok(None);
+    }
+
+    let rwbs = fields[plus - 2];
+    let write = if rwbs.contains('W') {
+        true
+    } else if rwbs.contains('R') {
+        false
+    } else {
+        // Flushes and discards carry no data to replay
+        return Ok(None);
+    };
+
+    let number = |index: usize, what: &str| {
+        fields[index]
+            .parse::<u64>()
+            .map_err(|_| format!("invalid {} {:?}", what, fields[index]))
+    };
+    let sector = number(plus - 1, "sector")?;
+    let sectors = number(plus + 1, "sector count")?;
+    let time: f64 = fields[3]
+        .parse()
+        .map_err(|_| format!("invalid time {:?}", fields[3]))?;
+    if sectors == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((
+        fields[plus - 3].to_string(),
+        time,
+        TraceEntry {
+            at: Duration::ZERO,
+            write,
+            offset: sector * SECTOR_SIZE,
+            len: (sectors * SECTOR_SIZE) as usize,
+        },
+    )))
+}
+
+impl Trace {
+    /// Parse blkparse output
+    pub fn parse(text: &str) -> Result<Trace, String> {
+        let mut queued = Vec::new();
+        let mut issued = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let event = parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            match event {
+                Some((action, time, entry)) if action == "Q" => queued.push((time, entry)),
+                Some((action, time, entry)) if action == "D" => issued.push((time, entry)),
+                _ => {}
+            }
+        }
+
+        let mut events = if queued.is_empty() { issued } else { queued };
+        // Events of different CPUs are interleaved; replay them in time order
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let first = events.first().map_or(0.0, |(time, _)| *time);
+
+        Ok(Trace {
+            entries: events
+                .into_iter()
+                .map(|(time, entry)| TraceEntry {
+                    at: Duration::from_secs_f64((time - first).max(0.0)),
+                    ..entry
+                })
+                .collect(),
+        })
+    }
+
+    /// Read and parse a blkparse output file
+    pub fn load(path: &str) -> Result<Trace, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read trace {}: {}", path, e))?;
+        let trace = Trace::parse(&text).map_err(|e| format!("Invalid trace {}: {}", path, e))?;
+        if trace.entries.is_empty() {
+            return Err(format!("Trace {} contains no read or write requests", path).into());
+        }
+        Ok(trace)
+    }
+
+    pub fn has_writes(&self) -> bool {
+        self.entries.iter().any(|entry| entry.write)
+    }
+
+    /// Length of the largest request
+    pub fn max_len(&self) -> usize {
+        self.entries.iter().map(|entry| entry.len).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two CPUs of one device: CPU 0's events are printed after CPU 1's although they happened in between
+    const QUEUED: &str = "\
+  8,0    1        1     0.500000000   697  Q   R 2048 + 8 [dd]
+  8,0    1        2     0.500001000   697  G   R 2048 + 8 [dd]
+  8,0    1        3     0.500002000   697  P   N [dd]
+  8,0    1        4     0.500010000   697  D   R 2048 + 8 [dd]
+  8,0    1        5     0.507812500   697  Q   W 4096 + 8 [dd]
+  8,0    1        6     0.509000000     0  C   R 2048 + 8 [0]
+  8,0    0        1     0.503906250   212  Q  WS 3418384 + 16 [kworker/0:1H]
+  8,0    0        2     0.504000000   212  Q FWS [kworker/0:1H]
+  8,0    0        3     0.505000000   212  Q  DS 8192 + 64 [fstrim]
+CPU0 (8,0):
+ Reads Queued:           0,        0KiB  Writes Queued:           1,        8KiB
+Total (8,0):
+";
+
+    #[test]
+    fn queued_requests_are_replayed_in_time_order() {
+        let trace = Trace::parse(QUEUED).unwrap();
+        let expected = [
+            (0, false, 2048, 8),
+            (3_906_250, true, 3418384, 16),
+            (7_812_500, true, 4096, 8),
+        ];
+        let entries: Vec<(u128, bool, u64, usize)> = trace.entries.iter()
+            .map(|entry| (entry.at.as_nanos(), entry.write, entry.offset, entry.len))
+            .collect();
+        assert_eq!(entries, expected.map(|(at, write, sector, sectors)| {
+            (at, write, sector * SECTOR_SIZE, sectors as usize * SECTOR_SIZE as usize)
+        }));
+        assert!(trace.has_writes());
+        assert_eq!(trace.max_len(), 16 * 512);
+    }
+
+    #[test]
+    fn issued_requests_are_the_fallback() {
+        let trace = Trace::parse("\
+  8,16   0        1     2.000000000   512  D   R 100 + 256 [fio]
+  8,16   0        2     2.000400000     0  C   R 100 + 256 [0]
+  8,16   0        3     2.001000000   512  D  RA 356 + 8 [fio]
+").unwrap();
+        assert_eq!(trace.entries.len(), 2);
+        assert_eq!((trace.entries[0].offset, trace.entries[0].len), (100 * 512, 256 * 512));
+        assert_eq!((trace.entries[1].offset, trace.entries[1].len), (356 * 512, 8 * 512));
+        assert_eq!(trace.entries[1].at.as_micros(), 1000);
+        assert!(!trace.has_writes());
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        let error = Trace::parse("\
+  8,0    1        1     0.000000000   697  Q   R 2048 + 8 [dd]
+  8,0    1        2     0.000001000   697  Q   R 20x8 + 8 [dd]
+").unwrap_err();
+        assert_eq!(error, "line 2: invalid sector \"20x8\"");
+
+        let error = Trace::parse("  8,0    1        1     soon   697  Q   W 2048 + 8 [dd]").unwrap_err();
+        assert!(error.contains("invalid time"), "{}", error);
+
+        assert!(Trace::parse("").unwrap().entries.is_empty());
+    }
+}
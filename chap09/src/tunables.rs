@@ -0,0 +1,344 @@
+//! Crash-safe snapshot and restoration of block device queue tunables.
+//!
+//! `TunableGuard` records the queue attributes of a device, writes the
+//! snapshot to a journal file and restores it when dropped, which covers
+//! early returns through `?` and panics. `install_signal_handler` makes
+//! SIGINT and SIGTERM restore every active snapshot before exiting, and
+//! `repair` restores snapshots left in the journal by a killed process.
+//!
+//! `TunableGuard::vm` does the same for the writeback sysctls in
+//! /proc/sys/vm, and `TunableGuard::from_snapshots` for any other file
+//! taking its saved value back, such as io.cost.qos.
+
+use crate::blockdev::{self, BlockDevError, Sysfs};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// Journal file used when the caller has no better place
+pub const DEFAULT_JOURNAL: &str = "chap09-tunables.journal";
+
+/// Queue attributes covered by the snapshot, in restore order.
+/// The scheduler goes first because switching it resets nr_requests and wbt_lat_usec.
+pub const QUEUE_ATTRS: [&str; 6] = [
+    "scheduler",
+    "read_ahead_kb",
+    "nr_requests",
+    "rotational",
+    "rq_affinity",
+    "wbt_lat_usec",
+];
+
+/// Directory of the VM sysctls
+pub const VM_DIR: &str = "/proc/sys/vm";
+
+/// Writeback sysctls covered by `TunableGuard::vm`, in restore order.
+/// Each *_bytes setting is the alternative to its *_ratio: writing one zeroes
+/// the other, so only the one in use is saved.
+pub const VM_ATTRS: [&str; 6] = [
+    "dirty_ratio",
+    "dirty_background_ratio",
+    "dirty_bytes",
+    "dirty_background_bytes",
+    "dirty_expire_centisecs",
+    "dirty_writeback_centisecs",
+];
+
+/// Saved queue attributes of one device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Process that took the snapshot
+    pub pid: u32,
+    /// Kernel name of the disk owning the queue, "vm" for the writeback
+    /// sysctls, or whatever else the snapshot covers
+    pub device: String,
+    /// Directory holding the attributes
+    pub queue_dir: PathBuf,
+    /// (attribute, value) pairs; attributes the kernel does not provide are left out
+    pub values: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    /// Read the current queue attributes of `device`
+    pub fn capture(device: &str, queue_dir: &Path) -> io::Result<Snapshot> {
+        Snapshot::capture_attrs(device, queue_dir, &QUEUE_ATTRS)
+    }
+
+    /// Read the writeback sysctls
+    pub fn capture_vm() -> io::Result<Snapshot> {
+        let mut snapshot = Snapshot::capture_attrs("vm", Path::new(VM_DIR), &VM_ATTRS)?;
+        // The unused one of each ratio/bytes pair reads as 0, which the kernel refuses to write back
+        snapshot.values.retain(|(_, value)| value != "0");
+        Ok(snapshot)
+    }
+
+    /// Read `attrs` of `dir`; attributes that do not exist are left out
+    fn capture_attrs(device: &str, dir: &Path, attrs: &[&str]) -> io::Result<Snapshot> {
+        let mut values = Vec::new();
+
+        for &attr in attrs {
+            let content = match fs::read_to_string(dir.join(attr)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let value = if attr == "scheduler" {
+                match blockdev::parse_scheduler(&content).0 {
+                    Some(scheduler) => scheduler,
+                    None => continue,
+                }
+            } else {
+                content.trim().to_string()
+            };
+            values.push((attr.to_string(), value));
+        }
+
+        Ok(Snapshot {
+            pid: std::process::id(),
+            device: device.to_string(),
+            queue_dir: dir.to_path_buf(),
+            values,
+        })
+    }
+
+    /// Write every saved attribute back, continuing past failures
+    pub fn restore(&self) -> Result<(), Box<dyn Error>> {
+        let mut failed = Vec::new();
+
+        for (attr, value) in &self.values {
+            if let Err(e) = fs::write(self.queue_dir.join(attr), value) {
+                failed.push(format!("{}={} ({})", attr, value, e));
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to restore {}: {}", self.device, failed.join(", ")).into())
+        }
+    }
+}
+
+/// Load the journal; a missing file is an empty journal
+fn journal_load(journal: &Path) -> Result<Vec<Snapshot>, Box<dyn Error>> {
+    match fs::read_to_string(journal) {
+        Ok(text) => Ok(serde_json::from_str(&text)
+            .map_err(|e| format!("Corrupted tunable journal {}: {}", journal.display(), e))?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the journal with `entries`; the file is removed once it is empty
+fn journal_store(journal: &Path, entries: &[Snapshot]) -> Result<(), Box<dyn Error>> {
+    if entries.is_empty() {
+        match fs::remove_file(journal) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => return Ok(()),
+        }
+    }
+
+    // Write to a temporary file first so a crash never leaves half a journal
+    let tmp = journal.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(&tmp, journal)?;
+    Ok(())
+}
+
+fn journal_remove(journal: &Path, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    let mut entries = journal_load(journal)?;
+    entries.retain(|entry| entry != snapshot);
+    journal_store(journal, &entries)
+}
+
+/// Snapshots not restored yet, shared with the signal handling thread
+static ACTIVE: Mutex<Vec<(PathBuf, Snapshot)>> = Mutex::new(Vec::new());
+
+/// Take `snapshot` out of the active list; false if someone else already did
+fn deactivate(snapshot: &Snapshot) -> bool {
+    let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    let before = active.len();
+    active.retain(|(_, s)| s != snapshot);
+    active.len() != before
+}
+
+/// Queue directories of the device behind `path`, as (disk name, directory).
+///
+/// Files on a filesystem without a block device have no tunables and yield
+/// an empty list.
+pub fn queue_dirs(path: &str) -> Result<Vec<(String, PathBuf)>, BlockDevError> {
+    let device = match Sysfs::new().resolve(path) {
+        Ok(device) => device,
+        Err(BlockDevError::NoBackingDevice { .. }) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut dirs = vec![(device.name.clone(), device.queue_dir())];
+    for disk in device.backing_disks()? {
+        if disk.name != device.name {
+            dirs.push((disk.name.clone(), disk.queue_dir()));
+        }
+    }
+    Ok(dirs)
+}
+
+/// Restores the saved queue attributes of a device when dropped
+pub struct TunableGuard {
+    snapshots: Vec<Snapshot>,
+    journal: PathBuf,
+}
+
+impl TunableGuard {
+    /// Snapshot the tunables of every queue behind `path` and record them in `journal`.
+    ///
+    /// Stacked devices (dm, md) cover the queues of their member disks too.
+    pub fn new(path: &str, journal: &Path) -> Result<TunableGuard, Box<dyn Error>> {
+        let mut snapshots = Vec::new();
+        for (name, dir) in queue_dirs(path)? {
+            snapshots.push(Snapshot::capture(&name, &dir)?);
+        }
+        TunableGuard::from_snapshots(snapshots, journal)
+    }
+
+    /// Snapshot the writeback sysctls (`VM_ATTRS`) and record them in `journal`
+    pub fn vm(journal: &Path) -> Result<TunableGuard, Box<dyn Error>> {
+        TunableGuard::from_snapshots(vec![Snapshot::capture_vm()?], journal)
+    }
+
+    /// Guard snapshots taken by the caller and record them in `journal`
+    pub fn from_snapshots(snapshots: Vec<Snapshot>, journal: &Path) -> Result<TunableGuard, Box<dyn Error>> {
+        if !snapshots.is_empty() {
+            let mut entries = journal_load(journal)?;
+            entries.extend(snapshots.iter().cloned());
+            journal_store(journal, &entries)?;
+
+            let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+            for snapshot in &snapshots {
+                active.push((journal.to_path_buf(), snapshot.clone()));
+            }
+        }
+
+        Ok(TunableGuard {
+            snapshots,
+            journal: journal.to_path_buf(),
+        })
+    }
+
+    /// Saved snapshots, one per queue
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Restore now and report failures instead of printing them on drop
+    pub fn restore(mut self) -> Result<(), Box<dyn Error>> {
+        self.restore_inner()
+    }
+
+    fn restore_inner(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut failed = Vec::new();
+        for snapshot in mem::take(&mut self.snapshots) {
+            if !deactivate(&snapshot) {
+                continue;
+            }
+            match snapshot.restore() {
+                Ok(()) => journal_remove(&self.journal, &snapshot)?,
+                Err(e) => failed.push(e.to_string()),
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failed.join("; ").into())
+        }
+    }
+}
+
+impl Drop for TunableGuard {
+    fn drop(&mut self) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        eprintln!("Restoring original tunables...");
+        if let Err(e) = self.restore_inner() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Restore every active snapshot on SIGINT/SIGTERM, then exit.
+///
+/// Call this before spawning other threads: the signals are blocked in the
+/// calling thread (and inherited by later threads) and handled by a
+/// dedicated thread with sigwait, where restoring is safe.
+pub fn install_signal_handler() -> io::Result<()> {
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        set
+    };
+
+    let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+
+    thread::spawn(move || {
+        let mut sig: libc::c_int = 0;
+        while unsafe { libc::sigwait(&set, &mut sig) } != 0 {}
+
+        eprintln!("\nCaught signal {}, restoring original tunables...", sig);
+        // Keep the lock until exit so no guard restores concurrently
+        let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        for (journal, snapshot) in active.drain(..) {
+            match snapshot.restore() {
+                Ok(()) => {
+                    if let Err(e) = journal_remove(&journal, &snapshot) {
+                        eprintln!("{}", e);
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        std::process::exit(128 + sig);
+    });
+
+    Ok(())
+}
+
+fn process_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 || *libc::__errno_location() == libc::EPERM }
+}
+
+/// Restore snapshots left in `journal` by processes that no longer run.
+///
+/// Returns the devices that were repaired.
+pub fn repair(journal: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let entries = journal_load(journal)?;
+    let mut kept = Vec::new();
+    let mut repaired = Vec::new();
+
+    for entry in entries {
+        if entry.pid != std::process::id() && process_alive(entry.pid) {
+            kept.push(entry);
+            continue;
+        }
+        match entry.restore() {
+            Ok(()) => repaired.push(entry.device),
+            Err(e) => {
+                eprintln!("{}", e);
+                kept.push(entry);
+            }
+        }
+    }
+
+    journal_store(journal, &kept)?;
+    Ok(repaired)
+}
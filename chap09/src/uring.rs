@@ -0,0 +1,287 @@
+//! Minimal io_uring (io_uring_setup/io_uring_enter) through raw syscalls.
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_params` from <linux/io_uring.h>
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// `struct io_uring_sqe` (64 bytes) restricted to the fields used for read/write
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A memory mapped ring region
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: i32, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *mut u8, len })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// io_uring instance with a submission and a completion queue
+pub struct IoUring {
+    fd: i32,
+    sq_ring: Mmap,
+    cq_ring: Mmap,
+    sqes: Mmap,
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+    sq_mask: u32,
+    cq_mask: u32,
+    sq_tail: u32,
+    to_submit: u32,
+    /// Requests accepted by the kernel and not reaped yet
+    in_flight: usize,
+}
+
+// The rings are private to this instance, so it may move to another thread
+unsafe impl Send for IoUring {}
+
+impl IoUring {
+    pub fn new(entries: usize) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(libc::SYS_io_uring_setup, entries as u32, &mut params as *mut Params)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as i32;
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+
+        let rings = Mmap::new(fd, sq_len, IORING_OFF_SQ_RING)
+            .and_then(|sq| Ok((sq, Mmap::new(fd, cq_len, IORING_OFF_CQ_RING)?)))
+            .and_then(|(sq, cq)| Ok((sq, cq, Mmap::new(fd, sqes_len, IORING_OFF_SQES)?)));
+        let (sq_ring, cq_ring, sqes) = match rings {
+            Ok(rings) => rings,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+
+        let sq_mask = unsafe { *sq_ring.at::<u32>(params.sq_off.ring_mask) };
+        let cq_mask = unsafe { *cq_ring.at::<u32>(params.cq_off.ring_mask) };
+        let sq_tail = unsafe { (*sq_ring.at::<AtomicU32>(params.sq_off.tail)).load(Ordering::Acquire) };
+
+        Ok(IoUring {
+            fd,
+            sq_ring,
+            cq_ring,
+            sqes,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            sq_mask,
+            cq_mask,
+            sq_tail,
+            to_submit: 0,
+            in_flight: 0,
+        })
+    }
+
+    /// Queue a read or write tagged with `slot`; it is sent to the kernel by `submit`
+    ///
+    /// The buffer must stay valid until the completion for `slot` is reaped.
+    pub fn prepare(&mut self, slot: usize, fd: i32, write: bool, buf: *mut u8, len: usize, offset: u64) {
+        let index = self.sq_tail & self.sq_mask;
+        unsafe {
+            let sqe = self.sqes.at::<Sqe>(0).add(index as usize);
+            sqe.write(Sqe {
+                opcode: if write { IORING_OP_WRITE } else { IORING_OP_READ },
+                flags: 0,
+                ioprio: 0,
+                fd,
+                off: offset,
+                addr: buf as u64,
+                len: len as u32,
+                rw_flags: 0,
+                user_data: slot as u64,
+                pad: [0; 3],
+            });
+            *self.sq_ring.at::<u32>(self.sq_off.array).add(index as usize) = index;
+        }
+        self.sq_tail = self.sq_tail.wrapping_add(1);
+        self.to_submit += 1;
+    }
+
+    /// Submit all prepared requests
+    pub fn submit(&mut self) -> io::Result<()> {
+        unsafe { (*self.sq_ring.at::<AtomicU32>(self.sq_off.tail)).store(self.sq_tail, Ordering::Release) };
+        while self.to_submit > 0 {
+            let ret = self.enter(self.to_submit, 0, 0)?;
+            if ret == 0 {
+                // Nothing was consumed, retrying would spin forever
+                return Err(io::Error::other(format!("io_uring_enter accepted none of {} requests", self.to_submit)));
+            }
+            self.to_submit -= ret;
+            self.in_flight += ret as usize;
+        }
+        Ok(())
+    }
+
+    /// Number of submitted requests whose completion has not been reaped
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Wait for at least `min` completions and append (slot, result) pairs to `out`
+    pub fn wait(&mut self, min: usize, out: &mut Vec<(usize, i64)>) -> io::Result<()> {
+        if self.reap(out) >= min {
+            return Ok(());
+        }
+        self.enter(0, min as u32, IORING_ENTER_GETEVENTS)?;
+        self.reap(out);
+        Ok(())
+    }
+
+    /// Move every available completion into `out`
+    fn reap(&mut self, out: &mut Vec<(usize, i64)>) -> usize {
+        let head_ptr = self.cq_ring.at::<AtomicU32>(self.cq_off.head);
+        let tail_ptr = self.cq_ring.at::<AtomicU32>(self.cq_off.tail);
+        let cqes = self.cq_ring.at::<Cqe>(self.cq_off.cqes);
+
+        let mut head = unsafe { (*head_ptr).load(Ordering::Relaxed) };
+        let tail = unsafe { (*tail_ptr).load(Ordering::Acquire) };
+        let mut count = 0;
+        while head != tail {
+            let cqe = unsafe { &*cqes.add((head & self.cq_mask) as usize) };
+            out.push((cqe.user_data as usize, cqe.res as i64));
+            head = head.wrapping_add(1);
+            count += 1;
+        }
+        unsafe { (*head_ptr).store(head, Ordering::Release) };
+        self.in_flight -= count;
+        count
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> io::Result<u32> {
+        loop {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    to_submit,
+                    min_complete,
+                    flags,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if ret >= 0 {
+                return Ok(ret as u32);
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Dirty page and writeback accounting for buffered write experiments.
+//!
+//! Buffered writes only dirty the page cache. The flusher threads write the
+//! pages back once they are older than `dirty_expire_centisecs` or once the
+//! dirty pages exceed the background threshold. Past the dirty threshold,
+//! balance_dirty_pages() makes the writer itself sleep, which shows up as
+//! write(2) calls taking milliseconds instead of microseconds.
+//!
+//! `run` writes through the page cache while sampling the global counters
+//! from /proc/meminfo and /proc/vmstat and, with debugfs mounted, the
+//! counters of the device's backing_dev_info.
+
+use crate::histogram::Histogram;
+use crate::pagecache;
+use crate::tunables::VM_DIR;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Writes blocked at least this long count as throttled
+pub const STALL: Duration = Duration::from_millis(10);
+
+/// The writeback sysctls swept by the experiment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirtySettings {
+    /// vm.dirty_ratio: percent of dirtyable memory at which writers are throttled
+    pub ratio: u32,
+    /// vm.dirty_background_ratio: percent at which the flusher threads start writing back
+    pub background_ratio: u32,
+    /// vm.dirty_expire_centisecs: age at which dirty data is written back regardless
+    pub expire_centisecs: u32,
+}
+
+fn read_sysctl(name: &str) -> io::Result<u32> {
+    let path = Path::new(VM_DIR).join(name);
+    let content = fs::read_to_string(&path)?;
+    content.trim().parse().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected content {:?} in {}", content.trim(), path.display()))
+    })
+}
+
+impl DirtySettings {
+    pub fn current() -> io::Result<DirtySettings> {
+        Ok(DirtySettings {
+            ratio: read_sysctl("dirty_ratio")?,
+            background_ratio: read_sysctl("dirty_background_ratio")?,
+            expire_centisecs: read_sysctl("dirty_expire_centisecs")?,
+        })
+    }
+
+    /// Write the settings that differ from the current ones.
+    ///
+    /// Leaving equal values alone keeps a system configured through
+    /// dirty_bytes (where dirty_ratio reads 0) as it is.
+    pub fn apply(&self) -> io::Result<()> {
+        let current = DirtySettings::current()?;
+        for (name, value, old) in [
+            ("dirty_ratio", self.ratio, current.ratio),
+            ("dirty_background_ratio", self.background_ratio, current.background_ratio),
+            ("dirty_expire_centisecs", self.expire_centisecs, current.expire_centisecs),
+        ] {
+            if value != old {
+                fs::write(Path::new(VM_DIR).join(name), value.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Short description for file names and plot legends
+    pub fn label(&self) -> String {
+        format!("ratio{}-bg{}-expire{}", self.ratio, self.background_ratio, self.expire_centisecs)
+    }
+}
+
+/// Counters of one backing device from debugfs, in bytes and bytes per second
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BdiStats {
+    pub writeback: u64,
+    /// Dirty pages of the device not under writeback yet
+    pub reclaimable: u64,
+    /// The device's share of the dirty threshold
+    pub dirty_thresh: u64,
+    /// Estimated write bandwidth of the device
+    pub write_bandwidth: u64,
+}
+
+impl BdiStats {
+    /// Read /sys/kernel/debug/bdi/<major>:<minor>/stats; `None` without debugfs
+    pub fn read(major: u32, minor: u32) -> Option<BdiStats> {
+        let text = fs::read_to_string(format!("/sys/kernel/debug/bdi/{}:{}/stats", major, minor)).ok()?;
+        let field = |name: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+                .map(|kib| kib * 1024)
+        };
+        Some(BdiStats {
+            writeback: field("BdiWriteback")?,
+            reclaimable: field("BdiReclaimable")?,
+            dirty_thresh: field("BdiDirtyThresh")?,
+            write_bandwidth: field("BdiWriteBandwidth")?,
+        })
+    }
+}
+
+/// A line of /proc/vmstat
+fn vmstat(field: &str) -> io::Result<u64> {
+    let text = fs::read_to_string("/proc/vmstat")?;
+    text.lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(' '))
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("no {} in /proc/vmstat", field)))
+}
+
+/// What the writer and the kernel did during one interval
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DirtySample {
+    /// Seconds from the start of the run to the end of the interval
+    pub time: f64,
+    /// Bytes written by the writer per second
+    pub write_bps: f64,
+    /// Longest write(2) call completed in the interval, in microseconds
+    pub max_write_usec: f64,
+    /// Share of the interval the writer spent in throttled writes
+    pub stalled_pct: f64,
+    /// System-wide dirty and under-writeback page cache (/proc/meminfo)
+    pub dirty: u64,
+    pub writeback: u64,
+    /// Global thresholds the kernel currently derives from the settings (/proc/vmstat)
+    pub dirty_thresh: u64,
+    pub background_thresh: u64,
+    pub bdi: Option<BdiStats>,
+}
+
+/// Parameters of one buffered write run
+#[derive(Debug, Clone)]
+pub struct WritebackSpec {
+    /// Regular file to write; created or truncated at the start
+    pub path: PathBuf,
+    /// The writer wraps around to the start of the file at this size
+    pub size: u64,
+    pub block_size: usize,
+    pub runtime: Duration,
+    pub interval: Duration,
+    /// Device number of the disk whose bdi counters are sampled
+    pub bdi: Option<(u32, u32)>,
+}
+
+/// Result of a buffered write run
+#[derive(Debug, Clone)]
+pub struct WritebackResult {
+    pub samples: Vec<DirtySample>,
+    /// Latency of every write(2) call
+    pub write_latency: Histogram,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Writes that took at least `STALL`, and their total time
+    pub stalls: u64,
+    pub stalled: Duration,
+    /// Time of the final fsync, i.e. the dirty data left at the end
+    pub fsync: Duration,
+}
+
+impl WritebackResult {
+    pub fn bw_bytes(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn to_json(&self, spec: &WritebackSpec, settings: &DirtySettings) -> Value {
+        json!({
+            "path": spec.path,
+            "size": spec.size,
+            "bs": spec.block_size,
+            "interval_ms": spec.interval.as_millis() as u64,
+            "settings": settings,
+            "runtime_ms": self.elapsed.as_millis() as u64,
+            "bytes": self.bytes,
+            "bw_bytes": self.bw_bytes(),
+            "stalls": self.stalls,
+            "stalled_ms": self.stalled.as_millis() as u64,
+            "fsync_ms": self.fsync.as_millis() as u64,
+            "lat_usec": self.write_latency.summary().to_json(),
+            "lat_histogram_ns": self.write_latency.to_json(),
+            "samples": self.samples,
+        })
+    }
+}
+
+/// Counters shared between the writer and the sampler
+#[derive(Default)]
+struct Progress {
+    bytes: AtomicU64,
+    max_ns: AtomicU64,
+    stalled_ns: AtomicU64,
+    stalls: AtomicU64,
+    stop: AtomicBool,
+}
+
+/// Write `spec.block_size` blocks sequentially until told to stop
+fn writer(file: &File, spec: &WritebackSpec, progress: &Progress) -> io::Result<Histogram> {
+    let buf = vec![0x5au8; spec.block_size];
+    let blocks = (spec.size / spec.block_size as u64).max(1);
+    let mut latency = Histogram::new();
+    let mut block = 0;
+
+    while !progress.stop.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        file.write_all_at(&buf, block * spec.block_size as u64)?;
+        let elapsed = start.elapsed();
+
+        let ns = elapsed.as_nanos() as u64;
+        latency.record(ns);
+        progress.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        progress.max_ns.fetch_max(ns, Ordering::Relaxed);
+        if elapsed >= STALL {
+            progress.stalls.fetch_add(1, Ordering::Relaxed);
+            progress.stalled_ns.fetch_add(ns, Ordering::Relaxed);
+        }
+        block = (block + 1) % blocks;
+    }
+
+    Ok(latency)
+}
+
+fn sample(spec: &WritebackSpec, time: f64, secs: f64, bytes: u64, max_ns: u64, stalled_ns: u64) -> io::Result<DirtySample> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    Ok(DirtySample {
+        time,
+        write_bps: bytes as f64 / secs,
+        max_write_usec: max_ns as f64 / 1000.0,
+        stalled_pct: (stalled_ns as f64 / 1e9 / secs * 100.0).min(100.0),
+        dirty: pagecache::meminfo_bytes("Dirty")?,
+        writeback: pagecache::meminfo_bytes("Writeback")?,
+        dirty_thresh: vmstat("nr_dirty_threshold")? * page_size,
+        background_thresh: vmstat("nr_dirty_background_threshold")? * page_size,
+        bdi: spec.bdi.and_then(|(major, minor)| BdiStats::read(major, minor)),
+    })
+}
+
+/// Write through the page cache for `spec.runtime`, sampling every `spec.interval`
+pub fn run(spec: &WritebackSpec) -> Result<WritebackResult, Box<dyn Error>> {
+    if spec.block_size == 0 || spec.interval.is_zero() {
+        return Err("Block size and sampling interval must be positive".into());
+    }
+    // Never write over a device node or anything else that is not a plain file
+    if fs::metadata(&spec.path).is_ok_and(|metadata| !metadata.is_file()) {
+        return Err(format!("{} is not a regular file", spec.path.display()).into());
+    }
+    let file = File::create(&spec.path).map_err(|e| format!("Failed to create {}: {}", spec.path.display(), e))?;
+    let progress = Progress::default();
+
+    let start = Instant::now();
+    let (samples, latency) = thread::scope(|s| {
+        let handle = s.spawn(|| writer(&file, spec, &progress));
+
+        let mut samples = Vec::new();
+        let mut last = start;
+        let mut last_bytes = 0;
+        let mut last_stalled = 0;
+        let result = (|| -> io::Result<()> {
+            while last - start < spec.runtime && !handle.is_finished() {
+                thread::sleep((last + spec.interval).saturating_duration_since(Instant::now()));
+                let now = Instant::now();
+                let bytes = progress.bytes.load(Ordering::Relaxed);
+                let stalled = progress.stalled_ns.load(Ordering::Relaxed);
+                let max_ns = progress.max_ns.swap(0, Ordering::Relaxed);
+                samples.push(sample(
+                    spec,
+                    (now - start).as_secs_f64(),
+                    (now - last).as_secs_f64(),
+                    bytes - last_bytes,
+                    max_ns,
+                    stalled - last_stalled,
+                )?);
+                (last, last_bytes, last_stalled) = (now, bytes, stalled);
+            }
+            Ok(())
+        })();
+
+        progress.stop.store(true, Ordering::Relaxed);
+        let latency = handle.join().unwrap_or_else(|_| Err(io::Error::other("writer panicked")));
+        result.and(latency).map(|latency| (samples, latency))
+    })?;
+    let elapsed = start.elapsed();
+
+    let fsync_start = Instant::now();
+    file.sync_all()?;
+    let fsync = fsync_start.elapsed();
+
+    Ok(WritebackResult {
+        samples,
+        write_latency: latency,
+        bytes: progress.bytes.load(Ordering::Relaxed),
+        elapsed,
+        stalls: progress.stalls.load(Ordering::Relaxed),
+        stalled: Duration::from_nanos(progress.stalled_ns.load(Ordering::Relaxed)),
+        fsync,
+    })
+}